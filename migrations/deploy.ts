@@ -129,7 +129,7 @@ module.exports = async function (provider: anchor.AnchorProvider) {
     console.log("\n🛠️ Next Steps:");
     console.log("1. Fund user accounts with demo payment tokens");
     console.log("2. Create token offerings for registered companies");
-    console.log("3. Enable trading by creating buy/sell orders");
+    console.log("3. Enable trading by opening company markets");
     console.log("4. Monitor platform activity and fees");
 
     console.log("\n📚 Available Instructions:");
@@ -137,10 +137,11 @@ module.exports = async function (provider: anchor.AnchorProvider) {
     console.log("- registerCompany: Register a new company for token issuance");
     console.log("- createTokenOffering: Create an Initial Token Offering (ITO)");
     console.log("- participateInOffering: Participate in a token offering");
-    console.log("- createSellOrder: Create a sell order for tokens");
-    console.log("- createBuyOrder: Create a buy order for tokens");
-    console.log("- executeTrade: Execute a trade between matching orders");
-    console.log("- cancelOrder: Cancel an existing order");
+    console.log("- createMarket: Open the market of a verified company");
+    console.log("- createLimitOrder: Place a limit order on a market");
+    console.log("- createMarketOrder: Take liquidity from a market");
+    console.log("- executeTrade: Execute a trade between matching legacy orders");
+    console.log("- cancelOrder: Cancel an existing legacy order");
     console.log("- createPortfolio: Create a user portfolio");
     console.log("- updatePortfolio: Update portfolio metrics");
     console.log("- createEscrow: Create an escrow for trade security");
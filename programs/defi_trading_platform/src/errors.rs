@@ -131,4 +131,160 @@ pub enum DefiTradingError {
     
     #[msg("Price level not found")]
     PriceLevelNotFound,
-}
\ No newline at end of file
+    
+    #[msg("Operation not supported for this offering kind")]
+    InvalidOfferingKind,
+    
+    #[msg("Offering has not been finalized yet")]
+    OfferingNotFinalized,
+    
+    #[msg("Offering allocation already claimed")]
+    AlreadyClaimed,
+    
+    #[msg("Too many offering rounds")]
+    TooManyOfferingRounds,
+    
+    #[msg("No offering round is currently open")]
+    NoActiveRound,
+    
+    #[msg("Wallet is not on the round allowlist")]
+    NotOnAllowlist,
+    
+    #[msg("Per-wallet cap for this round exceeded")]
+    RoundWalletCapExceeded,
+    
+    #[msg("Token offering has been cancelled")]
+    OfferingCancelled,
+    
+    #[msg("Token offering is not cancelled")]
+    OfferingNotCancelled,
+    
+    #[msg("Invalid offering amendment")]
+    InvalidOfferingAmendment,
+    
+    #[msg("Offering lock account required while a lockup applies")]
+    LockAccountRequired,
+    
+    #[msg("Offering tokens are still locked")]
+    TokensStillLocked,
+    
+    #[msg("Slippage limit exceeded")]
+    SlippageExceeded,
+    
+    #[msg("Company is not verified")]
+    CompanyNotVerified,
+    
+    #[msg("Bonding curve is not active")]
+    CurveNotActive,
+    
+    #[msg("Invalid bonding curve parameters")]
+    InvalidCurveParams,
+    
+    #[msg("Orderbook already exists for this company")]
+    OrderbookAlreadyExists,
+    
+    #[msg("Graduation accounts are required for this trade")]
+    GraduationAccountsRequired,
+    
+    #[msg("Native SOL payments require a wrapped SOL payment mint")]
+    NativeMintRequired,
+    
+    #[msg("Temporary wrapped SOL account is required to receive native SOL")]
+    NativeSolAccountsRequired,
+    
+    #[msg("Order book side is full and the order is not priced better than its worst order")]
+    OrderBookFull,
+    
+    #[msg("Order is not resting in the order book")]
+    OrderNotInBook,
+    
+    #[msg("Order does not have price-time priority")]
+    OrderNotAtTopOfBook,
+    
+    #[msg("Accounts required to take liquidity from the book are missing")]
+    TakerAccountsRequired,
+    
+    #[msg("Post-only order would take liquidity")]
+    PostOnlyWouldTake,
+    
+    #[msg("Fill-or-kill order could not be filled in full")]
+    FillOrKillNotFilled,
+    
+    #[msg("Order has expired")]
+    OrderExpired,
+    
+    #[msg("Order has not expired and is still in the book")]
+    OrderNotExpired,
+    
+    #[msg("Reference price has not crossed the trigger price")]
+    TriggerNotReached,
+    
+    #[msg("Configured price feed account is required")]
+    PriceFeedRequired,
+    
+    #[msg("Price feed is stale")]
+    StalePrice,
+    
+    #[msg("Escrow account is required to rest the order")]
+    OrderEscrowRequired,
+    
+    #[msg("Amended order would cross the book")]
+    AmendedOrderWouldCross,
+    
+    #[msg("Wallet is already linked to this trading account")]
+    WalletAlreadyLinked,
+    
+    #[msg("Wallet is not linked to this trading account")]
+    WalletNotLinked,
+    
+    #[msg("Trading account has too many linked wallets")]
+    TooManyLinkedWallets,
+    
+    #[msg("Buy escrow does not cover the remaining order")]
+    EscrowInvariantViolated,
+    
+    #[msg("Invalid market configuration")]
+    InvalidMarketConfig,
+    
+    #[msg("Price is not a multiple of the market tick size")]
+    PriceNotOnTick,
+    
+    #[msg("Amount is not a multiple of the market lot size")]
+    AmountNotOnLot,
+    
+    #[msg("Order is below the market minimum size")]
+    OrderBelowMinimumSize,
+    
+    #[msg("Order value is below the market minimum notional")]
+    OrderBelowMinimumNotional,
+    
+    #[msg("Open orders account has no free order slot")]
+    OpenOrdersFull,
+    
+    #[msg("Market vault account does not match")]
+    InvalidMarketVault,
+    
+    #[msg("Account has already been migrated")]
+    AccountAlreadyMigrated,
+    
+    #[msg("Legacy orders can only be executed, reduced or cancelled; use the market instructions")]
+    LegacyTradingDeprecated,
+    
+    #[msg("Company trades on its bonding curve until the curve graduates")]
+    BondingCurveActive,
+    
+    #[msg("Invalid price band or circuit breaker configuration")]
+    InvalidCircuitBreakerConfig,
+    
+    #[msg("Fill price is outside the market price band")]
+    PriceOutsideBand,
+    
+    #[msg("Market is halted by its circuit breaker")]
+    MarketHalted,
+    
+    #[msg("Purchased tokens must be returned with the refund")]
+    RefundTokensRequired,
+    
+    #[msg("Graduation seed orders stay in the book until the liquidity lock ends")]
+    LiquidityLocked,
+}
@@ -25,6 +25,8 @@ pub struct TokenOfferingCreated {
     pub token_mint: Pubkey,
     pub total_supply: u64,
     pub price_per_token: u64,
+    pub min_investment: u64,
+    pub max_investment: u64,
     pub offering_start: i64,
     pub offering_end: i64,
     pub timestamp: i64,
@@ -37,6 +39,12 @@ pub struct OfferingParticipated {
     pub user: Pubkey,
     pub amount_invested: u64,
     pub tokens_received: u64,
+    pub total_invested: u64,
+    pub total_tokens_received: u64,
+    pub locked_amount: u64,
+    pub round_index: Option<u8>,
+    pub round_tokens_sold: u64,
+    pub round_amount_raised: u64,
     pub timestamp: i64,
 }
 
@@ -60,6 +68,38 @@ pub struct OrderCancelled {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct AllOrdersCancelled {
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub cancelled_orders: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OrderAmended {
+    pub order_id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub old_price: u64,
+    pub new_price: u64,
+    pub old_remaining_amount: u64,
+    pub new_remaining_amount: u64,
+    pub escrow_topped_up: u64,
+    pub escrow_refunded: u64,
+    pub priority_kept: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceImprovementRefunded {
+    pub order_id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct TradeExecuted {
     pub trade_id: u64,
@@ -98,6 +138,7 @@ pub struct HoldingUpdated {
     pub company_id: u64,
     pub token_mint: Pubkey,
     pub amount: u64,
+    pub locked_amount: u64,
     pub average_price: u64,
     pub total_invested: u64,
     pub current_value: u64,
@@ -166,17 +207,25 @@ pub struct LimitOrderCreated {
     pub order_type: String,
     pub amount: u64,
     pub price: u64,
+    pub filled_amount: u64,
+    pub fills: u8,
+    pub time_in_force: String,
+    pub expires_at: Option<i64>,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct MarketOrderCreated {
+pub struct MarketOrderFilled {
     pub order_id: u64,
     pub user: Pubkey,
     pub company_id: u64,
     pub order_type: String,
-    pub amount: u64,
-    pub execution_price: u64,
+    pub requested_amount: u64,
+    pub filled_amount: u64,
+    pub quote_amount: u64,
+    pub platform_fees: u64,
+    pub average_price: u64,
+    pub fills: u8,
     pub timestamp: i64,
 }
 
@@ -202,4 +251,413 @@ pub struct MarketDepthCalculated {
     pub total_buy_volume: u64,
     pub total_sell_volume: u64,
     pub timestamp: i64,
-}
\ No newline at end of file
+}
+
+#[event]
+pub struct DutchAuctionCreated {
+    pub offering_id: u64,
+    pub company_id: u64,
+    pub company_authority: Pubkey,
+    pub token_mint: Pubkey,
+    pub payment_mint: Pubkey,
+    pub total_supply: u64,
+    pub start_price: u64,
+    pub floor_price: u64,
+    pub offering_start: i64,
+    pub offering_end: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuctionTokensClaimed {
+    pub offering_id: u64,
+    pub user: Pubkey,
+    pub tokens_received: u64,
+    pub clearing_price: u64,
+    pub refund_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OfferingProceedsWithdrawn {
+    pub offering_id: u64,
+    pub company_id: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SubscriptionOfferingCreated {
+    pub offering_id: u64,
+    pub company_id: u64,
+    pub company_authority: Pubkey,
+    pub token_mint: Pubkey,
+    pub payment_mint: Pubkey,
+    pub total_supply: u64,
+    pub price_per_token: u64,
+    pub offering_start: i64,
+    pub offering_end: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AllocationClaimed {
+    pub offering_id: u64,
+    pub user: Pubkey,
+    pub amount_committed: u64,
+    pub tokens_allocated: u64,
+    pub refund_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OfferingRoundAdded {
+    pub offering_id: u64,
+    pub round_index: u8,
+    pub price_per_token: u64,
+    pub round_start: i64,
+    pub round_end: i64,
+    pub max_per_wallet: u64,
+    pub allowlist_root: [u8; 32],
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OfferingCancelled {
+    pub offering_id: u64,
+    pub company_id: u64,
+    pub tokens_burned: u64,
+    pub refund_mode: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OfferingRefunded {
+    pub offering_id: u64,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub tokens_burned: u64, // fixed-price purchases returned with the refund
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OfferingAmended {
+    pub offering_id: u64,
+    pub company_id: u64,
+    pub old_offering_end: i64,
+    pub new_offering_end: i64,
+    pub old_price_per_token: u64,
+    pub new_price_per_token: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OfferingLockupSet {
+    pub offering_id: u64,
+    pub lockup_duration: i64,
+    pub unlocks_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OfferingTokensUnlocked {
+    pub offering_id: u64,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BondingCurveCreated {
+    pub company_id: u64,
+    pub authority: Pubkey,
+    pub token_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub curve_kind: String,
+    pub base_price: u64,
+    pub curve_param: u64,
+    pub max_supply: u64,
+    pub graduation_threshold: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CurveTokensBought {
+    pub company_id: u64,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub cost: u64,
+    pub spot_price: u64,
+    pub tokens_sold: u64,
+    pub reserve_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CurveTokensSold {
+    pub company_id: u64,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub proceeds: u64,
+    pub spot_price: u64,
+    pub tokens_sold: u64,
+    pub reserve_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BondingCurveGraduated {
+    pub company_id: u64,
+    pub orderbook: Pubkey,
+    pub ask_order_id: u64,
+    pub ask_price: u64,
+    pub ask_amount: u64,
+    pub bid_order_id: u64,
+    pub bid_price: u64,
+    pub bid_amount: u64,
+    pub proceeds_to_authority: u64,
+    pub liquidity_locked_until: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OrderBookInitialized {
+    pub company_id: u64,
+    pub orderbook: Pubkey,
+    pub order_book: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OrderExpired {
+    pub order_id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub refunded_amount: u64,
+    pub cancelled_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OrderEvicted {
+    pub order_id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub open_orders: Pubkey, // default for orders with their own EnhancedOrder account
+    pub price: u64,
+    pub quantity: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TriggerOrderCreated {
+    pub trigger_id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub order_type: String,
+    pub market_order_type: String,
+    pub trigger_price: u64,
+    pub trigger_direction: String,
+    pub amount: u64,
+    pub price: u64,
+    pub escrowed_amount: u64,
+    pub keeper_bounty: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TriggerOrderFired {
+    pub trigger_id: u64,
+    pub order_id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub reference_price: u64,
+    pub filled_amount: u64,
+    pub rested_amount: u64,
+    pub fills: u8,
+    pub keeper: Pubkey,
+    pub keeper_bounty: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TriggerOrderCancelled {
+    pub trigger_id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub refunded_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MarketConfigured {
+    pub company_id: u64,
+    pub tick_size: u64,
+    pub lot_size: u64,
+    pub min_base_size: u64,
+    pub min_notional: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceFeedConfigured {
+    pub company_id: u64,
+    pub price_feed: Pubkey,
+    pub feeder: Pubkey,
+    pub max_staleness: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceFeedUpdated {
+    pub company_id: u64,
+    pub price: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TradingAccountCreated {
+    pub trading_account: Pubkey,
+    pub owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WalletLinked {
+    pub trading_account: Pubkey,
+    pub wallet: Pubkey,
+    pub linked: bool, // false when unlinked
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SelfTradePrevented {
+    pub company_id: u64,
+    pub taker_order_id: u64,
+    pub maker_order_id: u64,
+    pub user: Pubkey,
+    pub mode: String,
+    pub taker_cancelled_amount: u64,
+    pub maker_cancelled_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OpenOrdersCreated {
+    pub open_orders: Pubkey,
+    pub owner: Pubkey,
+    pub company_id: u64,
+    pub quote_mint: Pubkey,
+    pub trading_account: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FundsDeposited {
+    pub open_orders: Pubkey,
+    pub owner: Pubkey,
+    pub company_id: u64,
+    pub base_amount: u64,
+    pub quote_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FundsSettled {
+    pub open_orders: Pubkey,
+    pub owner: Pubkey,
+    pub company_id: u64,
+    pub base_amount: u64,
+    pub quote_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PlatformMigrated {
+    pub last_order_id: u64,
+    pub last_escrow_id: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OrderbookMigrated {
+    pub company_id: u64,
+    pub orderbook: Pubkey,
+    pub base_decimals: u8,
+    pub quote_mint: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OfferingMigrated {
+    pub offering_id: u64,
+    pub company_id: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ParticipationMigrated {
+    pub offering_id: u64,
+    pub user: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct HoldingMigrated {
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EnhancedOrderMigrated {
+    pub order_id: u64,
+    pub legacy_order_id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub legacy_order: Pubkey,
+    pub order: Pubkey,
+    pub escrow_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MarketCreated {
+    pub company_id: u64,
+    pub orderbook: Pubkey,
+    pub order_book: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerConfigured {
+    pub company_id: u64,
+    pub band_bps: u16,
+    pub reference: String,
+    pub max_breaches: u8,
+    pub breach_window: i64,
+    pub halt_duration: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceBandBreached {
+    pub company_id: u64,
+    pub price: u64,
+    pub reference_price: u64,
+    pub band_bps: u16,
+    pub breaches: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerTripped {
+    pub company_id: u64,
+    pub price: u64,
+    pub reference_price: u64,
+    pub halted_until: i64,
+    pub timestamp: i64,
+}
@@ -0,0 +1,815 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, Token, TokenAccount, Mint, Transfer, MintTo};
+use crate::state::*;
+use crate::errors::*;
+use crate::events::*;
+use crate::pricing::*;
+use super::market::*;
+use super::enhanced_trading::sync_book_state;
+
+// How long the graduation seed orders cannot be cancelled, amended or
+// evicted, so the company cannot pull the liquidity straight back out
+const GRADUATION_LIQUIDITY_LOCK: i64 = 30 * 24 * 60 * 60;
+
+pub fn create_bonding_curve(
+    ctx: Context<CreateBondingCurve>,
+    curve_kind: CurveKind,
+    base_price: u64,
+    curve_param: u64,
+    max_supply: u64,
+    graduation_threshold: u64,
+    seed_liquidity_bps: u16,
+) -> Result<()> {
+    let platform = &ctx.accounts.platform;
+    let company = &mut ctx.accounts.company;
+    let curve = &mut ctx.accounts.bonding_curve;
+    
+    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(company.verified, DefiTradingError::CompanyNotVerified);
+    require!(
+        ctx.accounts.authority.key() == company.authority,
+        DefiTradingError::Unauthorized
+    );
+    require!(base_price > 0, DefiTradingError::InvalidCurveParams);
+    require!(max_supply > 0, DefiTradingError::InvalidCurveParams);
+    require!(graduation_threshold > 0, DefiTradingError::InvalidCurveParams);
+    require!(
+        seed_liquidity_bps > 0 && seed_liquidity_bps <= 10_000,
+        DefiTradingError::InvalidCurveParams
+    );
+    // Trading moves to the orderbook at graduation, so it must not exist yet
+    require!(
+        ctx.accounts.orderbook.data_is_empty(),
+        DefiTradingError::OrderbookAlreadyExists
+    );
+    
+    // The full curve must be priceable and able to reach the threshold
+    let max_reserve = curve_reserve(
+        &curve_kind,
+        base_price,
+        curve_param,
+        max_supply,
+        ctx.accounts.token_mint.decimals,
+    )?;
+    require!(
+        max_reserve >= graduation_threshold,
+        DefiTradingError::InvalidCurveParams
+    );
+    
+    let now = Clock::get()?.unix_timestamp;
+    
+    curve.company_id = company.id;
+    curve.authority = company.authority;
+    curve.token_mint = company.token_mint;
+    curve.base_decimals = ctx.accounts.token_mint.decimals;
+    curve.quote_mint = ctx.accounts.quote_mint.key();
+    curve.curve_kind = curve_kind.clone();
+    curve.base_price = base_price;
+    curve.curve_param = curve_param;
+    curve.max_supply = max_supply;
+    curve.tokens_sold = 0;
+    curve.reserve_balance = 0;
+    curve.graduation_threshold = graduation_threshold;
+    curve.seed_liquidity_bps = seed_liquidity_bps;
+    curve.status = BondingCurveStatus::Active;
+    curve.created_at = now;
+    curve.graduated_at = None;
+    curve.bump = ctx.bumps.bonding_curve;
+    
+    company.total_supply = company.total_supply
+        .checked_add(max_supply)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    
+    // Mint the whole curve supply into the curve vault
+    let company_id_bytes = company.id.to_le_bytes();
+    let seeds = &[
+        b"company",
+        company_id_bytes.as_ref(),
+        &[company.bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    let cpi_accounts = MintTo {
+        mint: ctx.accounts.token_mint.to_account_info(),
+        to: ctx.accounts.curve_token_vault.to_account_info(),
+        authority: company.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::mint_to(cpi_ctx, max_supply)?;
+    
+    // The accounts created at graduation are funded now, so the buy that
+    // crosses the threshold does not pay their rent
+    let rent = Rent::get()?;
+    let graduation_accounts = [
+        (ctx.accounts.orderbook.to_account_info(), Market::LEN),
+        (ctx.accounts.order_book.to_account_info(), OrderBookSlab::LEN),
+        (ctx.accounts.ask_order.to_account_info(), EnhancedOrder::LEN),
+        (ctx.accounts.ask_escrow.to_account_info(), TokenAccount::LEN),
+        (ctx.accounts.bid_order.to_account_info(), EnhancedOrder::LEN),
+        (ctx.accounts.bid_escrow.to_account_info(), TokenAccount::LEN),
+    ];
+    for (account, space) in graduation_accounts {
+        let top_up = rent.minimum_balance(space).saturating_sub(account.lamports());
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.authority.to_account_info(),
+                to: account,
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
+        }
+    }
+    
+    emit!(BondingCurveCreated {
+        company_id: curve.company_id,
+        authority: curve.authority,
+        token_mint: curve.token_mint,
+        quote_mint: curve.quote_mint,
+        curve_kind: match curve_kind {
+            CurveKind::Linear => "Linear".to_string(),
+            CurveKind::Exponential => "Exponential".to_string(),
+        },
+        base_price,
+        curve_param,
+        max_supply,
+        graduation_threshold,
+        timestamp: now,
+    });
+    
+    Ok(())
+}
+
+pub fn buy_from_curve(
+    ctx: Context<BuyFromCurve>,
+    amount: u64,
+    max_quote_in: u64,
+) -> Result<()> {
+    require!(!ctx.accounts.platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(amount > 0, DefiTradingError::InvalidTradeAmount);
+    
+    let curve = &ctx.accounts.bonding_curve;
+    require!(curve.status == BondingCurveStatus::Active, DefiTradingError::CurveNotActive);
+    
+    let new_supply = curve.tokens_sold
+        .checked_add(amount)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    require!(new_supply <= curve.max_supply, DefiTradingError::InsufficientTokens);
+    
+    let new_reserve = curve_reserve(
+        &curve.curve_kind,
+        curve.base_price,
+        curve.curve_param,
+        new_supply,
+        curve.base_decimals,
+    )?;
+    let cost = new_reserve
+        .checked_sub(curve.reserve_balance)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    require!(cost > 0, DefiTradingError::InvalidTradeAmount);
+    require!(cost <= max_quote_in, DefiTradingError::SlippageExceeded);
+    require!(
+        ctx.accounts.user_quote_account.amount >= cost,
+        DefiTradingError::InsufficientFunds
+    );
+    
+    // Pay into the reserve
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.user_quote_account.to_account_info(),
+        to: ctx.accounts.curve_reserve_vault.to_account_info(),
+        authority: ctx.accounts.user.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    token::transfer(cpi_ctx, cost)?;
+    
+    // Deliver tokens from the curve vault
+    let company_id_bytes = curve.company_id.to_le_bytes();
+    let seeds = &[
+        b"bonding_curve",
+        company_id_bytes.as_ref(),
+        &[curve.bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.curve_token_vault.to_account_info(),
+        to: ctx.accounts.user_token_account.to_account_info(),
+        authority: ctx.accounts.bonding_curve.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)?;
+    
+    let now = Clock::get()?.unix_timestamp;
+    let curve = &mut ctx.accounts.bonding_curve;
+    curve.tokens_sold = new_supply;
+    curve.reserve_balance = new_reserve;
+    
+    let spot_price = curve_spot_price(
+        &curve.curve_kind,
+        curve.base_price,
+        curve.curve_param,
+        new_supply,
+        curve.base_decimals,
+    )?;
+    
+    emit!(CurveTokensBought {
+        company_id: curve.company_id,
+        user: ctx.accounts.user.key(),
+        amount,
+        cost,
+        spot_price,
+        tokens_sold: curve.tokens_sold,
+        reserve_balance: curve.reserve_balance,
+        timestamp: now,
+    });
+    
+    if curve.reserve_balance >= curve.graduation_threshold {
+        graduate_to_orderbook(ctx, spot_price, now)?;
+    }
+    
+    Ok(())
+}
+
+pub fn sell_to_curve(
+    ctx: Context<SellToCurve>,
+    amount: u64,
+    min_quote_out: u64,
+) -> Result<()> {
+    require!(!ctx.accounts.platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(amount > 0, DefiTradingError::InvalidTradeAmount);
+    
+    let curve = &ctx.accounts.bonding_curve;
+    require!(curve.status == BondingCurveStatus::Active, DefiTradingError::CurveNotActive);
+    require!(amount <= curve.tokens_sold, DefiTradingError::InvalidTradeAmount);
+    require!(
+        ctx.accounts.user_token_account.amount >= amount,
+        DefiTradingError::InsufficientTokens
+    );
+    
+    let new_supply = curve.tokens_sold - amount;
+    let new_reserve = curve_reserve(
+        &curve.curve_kind,
+        curve.base_price,
+        curve.curve_param,
+        new_supply,
+        curve.base_decimals,
+    )?;
+    let proceeds = curve.reserve_balance
+        .checked_sub(new_reserve)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    require!(proceeds >= min_quote_out, DefiTradingError::SlippageExceeded);
+    
+    // Return tokens to the curve vault
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.user_token_account.to_account_info(),
+        to: ctx.accounts.curve_token_vault.to_account_info(),
+        authority: ctx.accounts.user.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    token::transfer(cpi_ctx, amount)?;
+    
+    // Pay out of the reserve
+    let company_id_bytes = curve.company_id.to_le_bytes();
+    let seeds = &[
+        b"bonding_curve",
+        company_id_bytes.as_ref(),
+        &[curve.bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.curve_reserve_vault.to_account_info(),
+        to: ctx.accounts.user_quote_account.to_account_info(),
+        authority: ctx.accounts.bonding_curve.to_account_info(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::transfer(cpi_ctx, proceeds)?;
+    
+    let curve = &mut ctx.accounts.bonding_curve;
+    curve.tokens_sold = new_supply;
+    curve.reserve_balance = new_reserve;
+    
+    let spot_price = curve_spot_price(
+        &curve.curve_kind,
+        curve.base_price,
+        curve.curve_param,
+        new_supply,
+        curve.base_decimals,
+    )?;
+    
+    emit!(CurveTokensSold {
+        company_id: curve.company_id,
+        user: ctx.accounts.user.key(),
+        amount,
+        proceeds,
+        spot_price,
+        tokens_sold: curve.tokens_sold,
+        reserve_balance: curve.reserve_balance,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+// Closes the curve and opens the orderbook. Unsold tokens are offered at the
+// final spot price, part of the reserve bids at the average curve price, and
+// the rest of the reserve goes to the company. The seed orders are locked in
+// the book for GRADUATION_LIQUIDITY_LOCK.
+fn graduate_to_orderbook(ctx: Context<BuyFromCurve>, spot_price: u64, now: i64) -> Result<()> {
+    let accounts = ctx.accounts;
+    let (
+        Some(token_mint),
+        Some(_),
+        Some(orderbook),
+        Some(order_book),
+        Some(ask_order),
+        Some(ask_escrow),
+        Some(bid_order),
+        Some(bid_escrow),
+        Some(authority_quote_account),
+    ) = (
+        accounts.token_mint.as_ref(),
+        accounts.quote_mint.as_ref(),
+        accounts.orderbook.as_mut(),
+        accounts.order_book.as_ref(),
+        accounts.ask_order.as_mut(),
+        accounts.ask_escrow.as_ref(),
+        accounts.bid_order.as_mut(),
+        accounts.bid_escrow.as_ref(),
+        accounts.authority_quote_account.as_ref(),
+    ) else {
+        return err!(DefiTradingError::GraduationAccountsRequired);
+    };
+    
+    let curve = &accounts.bonding_curve;
+    let ask_amount = curve.max_supply - curve.tokens_sold;
+    let seed_quote = (curve.reserve_balance as u128 * curve.seed_liquidity_bps as u128 / 10_000) as u64;
+    let bid_price = unit_price(curve.reserve_balance, curve.tokens_sold, curve.base_decimals)?;
+    require!(bid_price > 0, DefiTradingError::InvalidCurveParams);
+    let bid_amount = base_for_quote(seed_quote, bid_price, curve.base_decimals)?;
+    require!(bid_amount > 0, DefiTradingError::InvalidCurveParams);
+    let bid_cost = buy_escrow_required(bid_amount, bid_price, curve.base_decimals)?;
+    let proceeds_to_authority = curve.reserve_balance
+        .checked_sub(bid_cost)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    
+    let company_id_bytes = curve.company_id.to_le_bytes();
+    let seeds = &[
+        b"bonding_curve",
+        company_id_bytes.as_ref(),
+        &[curve.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let curve_info = accounts.bonding_curve.to_account_info();
+    let cpi_program = accounts.token_program.to_account_info();
+    
+    if ask_amount > 0 {
+        let cpi_accounts = Transfer {
+            from: accounts.curve_token_vault.to_account_info(),
+            to: ask_escrow.to_account_info(),
+            authority: curve_info.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, ask_amount)?;
+    }
+    
+    let cpi_accounts = Transfer {
+        from: accounts.curve_reserve_vault.to_account_info(),
+        to: bid_escrow.to_account_info(),
+        authority: curve_info.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, bid_cost)?;
+    
+    if proceeds_to_authority > 0 {
+        let cpi_accounts = Transfer {
+            from: accounts.curve_reserve_vault.to_account_info(),
+            to: authority_quote_account.to_account_info(),
+            authority: curve_info,
+        };
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, proceeds_to_authority)?;
+    }
+    
+    // The market takes over from the curve, opening with the seed orders
+    let mut book = open_market(
+        orderbook,
+        order_book,
+        curve.company_id,
+        token_mint,
+        curve.quote_mint,
+        curve.authority,
+        spot_price,
+        MarketConfig::DEFAULT,
+        ctx.bumps.orderbook.ok_or(DefiTradingError::GraduationAccountsRequired)?,
+        now,
+    )?;
+    if ask_amount > 0 {
+        book.insert(
+            &OrderType::Sell,
+            GRADUATION_ASK_ORDER_ID,
+            curve.authority,
+            Pubkey::default(),
+            spot_price,
+            ask_amount,
+            0,
+        )?;
+    }
+    book.insert(
+        &OrderType::Buy,
+        GRADUATION_BID_ORDER_ID,
+        curve.authority,
+        Pubkey::default(),
+        bid_price,
+        bid_amount,
+        0,
+    )?;
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_order_id = GRADUATION_BID_ORDER_ID;
+    orderbook.liquidity_locked_until = now
+        .checked_add(GRADUATION_LIQUIDITY_LOCK)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    
+    ask_order.id = GRADUATION_ASK_ORDER_ID;
+    ask_order.user = curve.authority;
+    ask_order.company_id = curve.company_id;
+    ask_order.token_mint = curve.token_mint;
+    ask_order.order_type = OrderType::Sell;
+    ask_order.market_order_type = MarketOrderType::Limit;
+    ask_order.time_in_force = TimeInForce::GoodTillCancelled;
+    ask_order.expires_at = None;
+    ask_order.self_trade_prevention = SelfTradePrevention::CancelTaker;
+    ask_order.trading_account = Pubkey::default();
+    ask_order.amount = ask_amount;
+    ask_order.remaining_amount = ask_amount;
+    ask_order.price = spot_price;
+    ask_order.status = if ask_amount > 0 { OrderStatus::Active } else { OrderStatus::Filled };
+    ask_order.created_at = now;
+    ask_order.filled_at = None;
+    ask_order.bump = ctx.bumps.ask_order.ok_or(DefiTradingError::GraduationAccountsRequired)?;
+    
+    bid_order.id = GRADUATION_BID_ORDER_ID;
+    bid_order.user = curve.authority;
+    bid_order.company_id = curve.company_id;
+    bid_order.token_mint = curve.token_mint;
+    bid_order.order_type = OrderType::Buy;
+    bid_order.market_order_type = MarketOrderType::Limit;
+    bid_order.time_in_force = TimeInForce::GoodTillCancelled;
+    bid_order.expires_at = None;
+    bid_order.self_trade_prevention = SelfTradePrevention::CancelTaker;
+    bid_order.trading_account = Pubkey::default();
+    bid_order.amount = bid_amount;
+    bid_order.remaining_amount = bid_amount;
+    bid_order.price = bid_price;
+    bid_order.status = OrderStatus::Active;
+    bid_order.created_at = now;
+    bid_order.filled_at = None;
+    bid_order.bump = ctx.bumps.bid_order.ok_or(DefiTradingError::GraduationAccountsRequired)?;
+    
+    let orderbook_key = orderbook.key();
+    let curve = &mut accounts.bonding_curve;
+    curve.tokens_sold = curve.max_supply;
+    curve.reserve_balance = 0;
+    curve.status = BondingCurveStatus::Graduated;
+    curve.graduated_at = Some(now);
+    
+    emit!(BondingCurveGraduated {
+        company_id: curve.company_id,
+        orderbook: orderbook_key,
+        ask_order_id: GRADUATION_ASK_ORDER_ID,
+        ask_price: spot_price,
+        ask_amount,
+        bid_order_id: GRADUATION_BID_ORDER_ID,
+        bid_price,
+        bid_amount,
+        proceeds_to_authority,
+        liquidity_locked_until: orderbook.liquidity_locked_until,
+        timestamp: now,
+    });
+    
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CreateBondingCurve<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"company", company.id.to_le_bytes().as_ref()],
+        bump = company.bump
+    )]
+    pub company: Account<'info, Company>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = BondingCurve::LEN,
+        seeds = [b"bonding_curve", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    
+    #[account(
+        mut,
+        seeds = [b"token_mint", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub token_mint: Account<'info, Mint>,
+    
+    pub quote_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = bonding_curve,
+        seeds = [b"curve_tokens", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub curve_token_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = quote_mint,
+        token::authority = bonding_curve,
+        seeds = [b"curve_reserve", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub curve_reserve_vault: Account<'info, TokenAccount>,
+    
+    // Graduation accounts, only checked to be uninitialized and funded with
+    // their rent until graduation creates them
+    /// CHECK: Only checked to be uninitialized; graduation creates the orderbook here
+    #[account(
+        mut,
+        seeds = [b"orderbook", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub orderbook: UncheckedAccount<'info>,
+    
+    /// CHECK: Funded only; graduation creates the order book slab here
+    #[account(
+        mut,
+        seeds = [b"order_book", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: UncheckedAccount<'info>,
+    
+    /// CHECK: Funded only; graduation creates the seed ask here
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order",
+            company.id.to_le_bytes().as_ref(),
+            company.authority.as_ref(),
+            GRADUATION_ASK_ORDER_ID.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub ask_order: UncheckedAccount<'info>,
+    
+    /// CHECK: Funded only; graduation creates the seed ask escrow here
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order_escrow",
+            company.id.to_le_bytes().as_ref(),
+            company.authority.as_ref(),
+            GRADUATION_ASK_ORDER_ID.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub ask_escrow: UncheckedAccount<'info>,
+    
+    /// CHECK: Funded only; graduation creates the seed bid here
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order",
+            company.id.to_le_bytes().as_ref(),
+            company.authority.as_ref(),
+            GRADUATION_BID_ORDER_ID.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub bid_order: UncheckedAccount<'info>,
+    
+    /// CHECK: Funded only; graduation creates the seed bid escrow here
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order_escrow",
+            company.id.to_le_bytes().as_ref(),
+            company.authority.as_ref(),
+            GRADUATION_BID_ORDER_ID.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub bid_escrow: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct BuyFromCurve<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"bonding_curve", bonding_curve.company_id.to_le_bytes().as_ref()],
+        bump = bonding_curve.bump
+    )]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    
+    #[account(
+        mut,
+        seeds = [b"curve_tokens", bonding_curve.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub curve_token_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"curve_reserve", bonding_curve.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub curve_reserve_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == bonding_curve.token_mint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_quote_account.mint == bonding_curve.quote_mint
+    )]
+    pub user_quote_account: Account<'info, TokenAccount>,
+    
+    // Graduation accounts, only required by the buy that crosses the
+    // threshold. Their rent was deposited by the curve authority when the
+    // curve was created, so the buyer only covers a rent increase since then.
+    #[account(address = bonding_curve.token_mint)]
+    pub token_mint: Option<Account<'info, Mint>>,
+    
+    #[account(address = bonding_curve.quote_mint)]
+    pub quote_mint: Option<Account<'info, Mint>>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = Market::LEN,
+        seeds = [b"orderbook", bonding_curve.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub orderbook: Option<Account<'info, Market>>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = OrderBookSlab::LEN,
+        seeds = [b"order_book", bonding_curve.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: Option<AccountLoader<'info, OrderBookSlab>>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = EnhancedOrder::LEN,
+        seeds = [
+            b"enhanced_order",
+            bonding_curve.company_id.to_le_bytes().as_ref(),
+            bonding_curve.authority.as_ref(),
+            GRADUATION_ASK_ORDER_ID.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub ask_order: Option<Account<'info, EnhancedOrder>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = token_mint,
+        token::authority = ask_order,
+        seeds = [
+            b"enhanced_order_escrow",
+            bonding_curve.company_id.to_le_bytes().as_ref(),
+            bonding_curve.authority.as_ref(),
+            GRADUATION_ASK_ORDER_ID.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub ask_escrow: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = EnhancedOrder::LEN,
+        seeds = [
+            b"enhanced_order",
+            bonding_curve.company_id.to_le_bytes().as_ref(),
+            bonding_curve.authority.as_ref(),
+            GRADUATION_BID_ORDER_ID.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub bid_order: Option<Account<'info, EnhancedOrder>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = quote_mint,
+        token::authority = bid_order,
+        seeds = [
+            b"enhanced_order_escrow",
+            bonding_curve.company_id.to_le_bytes().as_ref(),
+            bonding_curve.authority.as_ref(),
+            GRADUATION_BID_ORDER_ID.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub bid_escrow: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        constraint = authority_quote_account.mint == bonding_curve.quote_mint,
+        constraint = authority_quote_account.owner == bonding_curve.authority @ DefiTradingError::Unauthorized
+    )]
+    pub authority_quote_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct SellToCurve<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"bonding_curve", bonding_curve.company_id.to_le_bytes().as_ref()],
+        bump = bonding_curve.bump
+    )]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    
+    #[account(
+        mut,
+        seeds = [b"curve_tokens", bonding_curve.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub curve_token_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"curve_reserve", bonding_curve.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub curve_reserve_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == bonding_curve.token_mint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_quote_account.mint == bonding_curve.quote_mint
+    )]
+    pub user_quote_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
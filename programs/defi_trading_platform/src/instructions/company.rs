@@ -3,6 +3,8 @@ use anchor_spl::token::{self, Token, Mint, MintTo, TokenAccount, Transfer};
 use crate::state::*;
 use crate::errors::*;
 use crate::events::*;
+use crate::pricing::*;
+use super::market::*;
 
 pub fn register_company(
     ctx: Context<RegisterCompany>,
@@ -58,6 +60,7 @@ pub fn admin_create_company(
     description: String,
     initial_supply: u64,
     initial_price: u64,
+    market_config: Option<MarketConfig>,
 ) -> Result<()> {
     // Store account infos before borrowing mutably
     let token_mint_info = ctx.accounts.token_mint.to_account_info();
@@ -91,7 +94,12 @@ pub fn admin_create_company(
     company.token_mint = ctx.accounts.token_mint.key();
     company.total_supply = initial_supply;
     company.circulating_supply = 0;
-    company.market_cap = initial_supply.checked_mul(initial_price).unwrap_or(0);
+    company.market_cap = quote_for_base(
+        initial_supply,
+        initial_price,
+        ctx.accounts.token_mint.decimals,
+        Rounding::Down,
+    ).unwrap_or(0);
     company.created_at = Clock::get()?.unix_timestamp;
     company.bump = ctx.bumps.company;
 
@@ -100,7 +108,6 @@ pub fn admin_create_company(
     // Mint initial supply to admin token account
     let company_id_bytes = company_id.to_le_bytes();
     let company_bump = company.bump;
-    let company_token_mint = company.token_mint;
     
     let seeds = &[
         b"company",
@@ -119,19 +126,19 @@ pub fn admin_create_company(
     
     token::mint_to(cpi_ctx, initial_supply)?;
 
-    // Initialize orderbook for the company
-    let orderbook = &mut ctx.accounts.orderbook;
-    orderbook.company_id = company_id;
-    orderbook.token_mint = company_token_mint;
-    orderbook.total_buy_orders = 0;
-    orderbook.total_sell_orders = 0;
-    orderbook.best_bid = 0;
-    orderbook.best_ask = 0;
-    orderbook.last_trade_price = initial_price;
-    orderbook.total_volume = 0;
-    orderbook.created_at = Clock::get()?.unix_timestamp;
-    orderbook.last_updated = Clock::get()?.unix_timestamp;
-    orderbook.bump = ctx.bumps.orderbook;
+    // Open the company's market
+    open_market(
+        &mut ctx.accounts.orderbook,
+        &ctx.accounts.order_book,
+        company_id,
+        &ctx.accounts.token_mint,
+        ctx.accounts.quote_mint.key(),
+        ctx.accounts.admin.key(),
+        initial_price,
+        market_config.unwrap_or(MarketConfig::DEFAULT),
+        ctx.bumps.orderbook,
+        Clock::get()?.unix_timestamp,
+    )?;
 
     emit!(CompanyCreatedByAdmin {
         company_id,
@@ -259,7 +266,7 @@ pub struct RegisterCompany<'info> {
     #[account(
         init,
         payer = authority,
-        mint::decimals = 6,
+        mint::decimals = COMPANY_TOKEN_DECIMALS,
         mint::authority = company,
         seeds = [
             b"token_mint",
@@ -302,7 +309,7 @@ pub struct AdminCreateCompany<'info> {
     #[account(
         init,
         payer = admin,
-        mint::decimals = 6,
+        mint::decimals = COMPANY_TOKEN_DECIMALS,
         mint::authority = company,
         seeds = [
             b"token_mint",
@@ -311,18 +318,32 @@ pub struct AdminCreateCompany<'info> {
         bump
     )]
     pub token_mint: Account<'info, Mint>,
+    
+    pub quote_mint: Account<'info, Mint>,
 
     #[account(
         init,
         payer = admin,
-        space = Orderbook::LEN,
+        space = Market::LEN,
         seeds = [
             b"orderbook",
             platform.total_companies.checked_add(1).unwrap().to_le_bytes().as_ref()
         ],
         bump
     )]
-    pub orderbook: Account<'info, Orderbook>,
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        init,
+        payer = admin,
+        space = OrderBookSlab::LEN,
+        seeds = [
+            b"order_book",
+            platform.total_companies.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
     
     #[account(
         init_if_needed,
@@ -1,93 +1,168 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::system_program;
+use anchor_spl::token::{self, spl_token::native_mint, CloseAccount, Mint, Token, TokenAccount, Transfer};
 use crate::state::*;
 use crate::errors::*;
 use crate::events::*;
+use crate::pricing::*;
+use crate::native_sol::*;
+use super::matching::*;
 
-// Enhanced limit order creation
-pub fn create_limit_order(
-    ctx: Context<CreateLimitOrder>,
+// Enhanced limit order creation. The order first takes liquidity from the
+// opposite side of the book, then only the unfilled remainder is escrowed
+// and rests, as allowed by its time in force.
+#[allow(clippy::too_many_arguments)]
+pub fn create_limit_order<'info>(
+    ctx: Context<'_, '_, '_, 'info, CreateLimitOrder<'info>>,
     order_type: OrderType,
     amount: u64,
     price: u64,
+    max_fills: u8,
+    time_in_force: TimeInForce,
+    expires_at: Option<i64>,
+    self_trade_prevention: SelfTradePrevention,
 ) -> Result<()> {
-    let platform = &ctx.accounts.platform;
+    let orderbook_info = ctx.accounts.orderbook.to_account_info();
+    let platform = &mut ctx.accounts.platform;
     let orderbook = &mut ctx.accounts.orderbook;
     let order = &mut ctx.accounts.order;
     
     require!(!platform.is_paused, DefiTradingError::PlatformPaused);
     require!(amount > 0, DefiTradingError::InvalidOrderParams);
     require!(price > 0, DefiTradingError::InvalidOrderParams);
+    orderbook.market_config.check_limit_order(amount, price, orderbook.base_decimals)?;
+
+    if order_type == OrderType::Sell {
+        require!(
+            ctx.accounts.user_token_account.amount >= amount,
+            DefiTradingError::InsufficientTokens
+        );
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    check_time_in_force(&time_in_force, expires_at, now)?;
+
+    let order_id = orderbook.last_order_id
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let user_key = ctx.accounts.user.key();
+    let trading_account = trading_account_key(&user_key, ctx.accounts.trading_account.as_ref())?;
+    let mut book = ctx.accounts.order_book.load_mut()?;
+    let maker_side = match order_type {
+        OrderType::Buy => OrderType::Sell,
+        OrderType::Sell => OrderType::Buy,
+    };
+    book.remove_expired_heads(&maker_side, now);
+    let max_fills = limit_order_max_fills(&book, &order_type, price, &time_in_force, max_fills, now)?;
+
+    // Take liquidity first
+    let taker = TakerAccounts {
+        user: ctx.accounts.user.to_account_info(),
+        authority: ctx.accounts.user.to_account_info(),
+        signer_seeds: &[],
+        orderbook: orderbook_info,
+        self_trade_key: if trading_account == Pubkey::default() { user_key } else { trading_account },
+        self_trade_prevention: self_trade_prevention.clone(),
+        user_token_account: &ctx.accounts.user_token_account,
+        user_payment_account: ctx.accounts.user_payment_account.as_ref(),
+        platform_fee_account: ctx.accounts.platform_fee_account.as_ref(),
+        token_program: ctx.accounts.token_program.to_account_info(),
+        system_program: ctx.accounts.system_program.to_account_info(),
+    };
+    let summary = match_against_book(
+        &mut book,
+        orderbook,
+        platform,
+        &taker,
+        ctx.remaining_accounts,
+        &order_type,
+        order_id,
+        price,
+        amount,
+        max_fills,
+    )?;
+    let remaining_amount = amount - summary.filled_amount - summary.cancelled_amount;
+    if time_in_force == TimeInForce::FillOrKill {
+        require!(summary.filled_amount == amount, DefiTradingError::FillOrKillNotFilled);
+    }
 
-    let order_id = orderbook.total_buy_orders + orderbook.total_sell_orders + 1;
+    // Immediate-or-cancel remainders, and takers cancelled by self-trade
+    // prevention, are dropped instead of resting
+    let rest_amount = if time_in_force == TimeInForce::ImmediateOrCancel || summary.taker_cancelled {
+        0
+    } else {
+        remaining_amount
+    };
     
-    // Validate sufficient balance based on order type
+    // Escrow the remainder based on order type
     match order_type {
         OrderType::Sell => {
-            require!(
-                ctx.accounts.user_token_account.amount >= amount,
-                DefiTradingError::InsufficientTokens
-            );
-            
             // Escrow tokens
-            let cpi_accounts = Transfer {
-                from: ctx.accounts.user_token_account.to_account_info(),
-                to: ctx.accounts.order_escrow_account.to_account_info(),
-                authority: ctx.accounts.user.to_account_info(),
-            };
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-            token::transfer(cpi_ctx, amount)?;
-            
-            orderbook.total_sell_orders = orderbook.total_sell_orders.checked_add(1).unwrap();
-            
-            // Update best ask if this is a new lowest price
-            if orderbook.best_ask == 0 || price < orderbook.best_ask {
-                orderbook.best_ask = price;
+            if rest_amount > 0 {
+                let cpi_accounts = Transfer {
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    to: ctx.accounts.order_escrow_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                };
+                let cpi_program = ctx.accounts.token_program.to_account_info();
+                let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+                token::transfer(cpi_ctx, rest_amount)?;
             }
+            
         },
         OrderType::Buy => {
-            let total_cost = amount.checked_mul(price).ok_or(DefiTradingError::ArithmeticOverflow)?;
-            require!(
-                ctx.accounts.user_payment_account.amount >= total_cost,
-                DefiTradingError::InsufficientFunds
-            );
-            
-            // Escrow payment
-            let cpi_accounts = Transfer {
-                from: ctx.accounts.user_payment_account.to_account_info(),
-                to: ctx.accounts.order_escrow_account.to_account_info(),
-                authority: ctx.accounts.user.to_account_info(),
-            };
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-            token::transfer(cpi_ctx, total_cost)?;
+            let total_cost = buy_escrow_required(rest_amount, price, orderbook.base_decimals)?;
             
-            orderbook.total_buy_orders = orderbook.total_buy_orders.checked_add(1).unwrap();
+            // Escrow payment, wrapping native SOL if no payment account was given
+            collect_payment(
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.user_payment_account.as_ref(),
+                ctx.accounts.order_escrow_account.to_account_info(),
+                ctx.accounts.order_escrow_account.mint,
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                total_cost,
+            )?;
             
-            // Update best bid if this is a new highest price
-            if orderbook.best_bid == 0 || price > orderbook.best_bid {
-                orderbook.best_bid = price;
-            }
         }
     }
+    orderbook.last_order_id = order_id;
     
     // Set up order
     order.id = order_id;
-    order.user = ctx.accounts.user.key();
+    order.user = user_key;
     order.company_id = orderbook.company_id;
-    order.token_mint = orderbook.token_mint;
+    order.token_mint = orderbook.base_mint;
     order.order_type = order_type.clone();
     order.market_order_type = MarketOrderType::Limit;
     order.amount = amount;
-    order.remaining_amount = amount;
+    order.remaining_amount = remaining_amount;
     order.price = price;
-    order.status = OrderStatus::Active;
-    order.created_at = Clock::get()?.unix_timestamp;
-    order.filled_at = None;
+    order.time_in_force = time_in_force.clone();
+    order.expires_at = expires_at;
+    order.self_trade_prevention = self_trade_prevention;
+    order.trading_account = trading_account;
+    order.status = if summary.filled_amount == amount {
+        OrderStatus::Filled
+    } else if rest_amount == 0 {
+        OrderStatus::Cancelled
+    } else if summary.filled_amount > 0 {
+        OrderStatus::PartiallyFilled
+    } else {
+        OrderStatus::Active
+    };
+    order.created_at = now;
+    order.filled_at = if summary.filled_amount == amount { Some(now) } else { None };
     order.bump = ctx.bumps.order;
 
-    orderbook.last_updated = Clock::get()?.unix_timestamp;
+    // Rest the remainder in the book behind everything at an equal or better price
+    if rest_amount > 0 {
+        let evicted = book.insert(&order_type, order_id, user_key, Pubkey::default(), price, rest_amount, expires_at.unwrap_or(0))?;
+        record_eviction(orderbook, evicted, now)?;
+    }
+    sync_book_state(orderbook, &book, now);
+
+    orderbook.last_updated = now;
 
     emit!(LimitOrderCreated {
         order_id,
@@ -99,72 +174,140 @@ pub fn create_limit_order(
         },
         amount,
         price,
+        filled_amount: summary.filled_amount,
+        fills: summary.fills,
+        time_in_force: time_in_force_name(&time_in_force),
+        expires_at,
         timestamp: order.created_at,
     });
 
     Ok(())
 }
 
-// Market order that executes immediately at best available price
-pub fn create_market_order(
-    ctx: Context<CreateMarketOrder>,
+// Market order that sweeps the opposite side of the book, across as many
+// price levels as needed, and never rests. Any unfilled remainder is
+// cancelled; the fill summary is returned to the caller.
+pub fn create_market_order<'info>(
+    ctx: Context<'_, '_, '_, 'info, CreateMarketOrder<'info>>,
     order_type: OrderType,
     amount: u64,
-) -> Result<()> {
-    let platform = &ctx.accounts.platform;
+    worst_price: u64,
+    min_amount_out: u64,
+    max_fills: u8,
+    self_trade_prevention: SelfTradePrevention,
+) -> Result<MarketOrderFill> {
+    let orderbook_info = ctx.accounts.orderbook.to_account_info();
+    let platform = &mut ctx.accounts.platform;
     let orderbook = &mut ctx.accounts.orderbook;
     let order = &mut ctx.accounts.order;
     
     require!(!platform.is_paused, DefiTradingError::PlatformPaused);
     require!(amount > 0, DefiTradingError::InvalidOrderParams);
+    require!(
+        order_type == OrderType::Sell || worst_price > 0,
+        DefiTradingError::InvalidOrderParams
+    );
+    orderbook.market_config.check_amount(amount)?;
+    if order_type == OrderType::Sell {
+        require!(
+            ctx.accounts.user_token_account.amount >= amount,
+            DefiTradingError::InsufficientTokens
+        );
+    }
 
-    let order_id = orderbook.total_buy_orders + orderbook.total_sell_orders + 1;
-    let execution_price = match order_type {
-        OrderType::Buy => {
-            require!(orderbook.best_ask > 0, DefiTradingError::NoLiquidity);
-            orderbook.best_ask
-        },
-        OrderType::Sell => {
-            require!(orderbook.best_bid > 0, DefiTradingError::NoLiquidity);
-            orderbook.best_bid
-        }
+    let order_id = orderbook.last_order_id
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let user_key = ctx.accounts.user.key();
+    let trading_account = trading_account_key(&user_key, ctx.accounts.trading_account.as_ref())?;
+    let mut book = ctx.accounts.order_book.load_mut()?;
+
+    let taker = TakerAccounts {
+        user: ctx.accounts.user.to_account_info(),
+        authority: ctx.accounts.user.to_account_info(),
+        signer_seeds: &[],
+        orderbook: orderbook_info,
+        self_trade_key: if trading_account == Pubkey::default() { user_key } else { trading_account },
+        self_trade_prevention: self_trade_prevention.clone(),
+        user_token_account: &ctx.accounts.user_token_account,
+        user_payment_account: ctx.accounts.user_payment_account.as_ref(),
+        platform_fee_account: Some(&ctx.accounts.platform_fee_account),
+        token_program: ctx.accounts.token_program.to_account_info(),
+        system_program: ctx.accounts.system_program.to_account_info(),
     };
+    let summary = match_against_book(
+        &mut book,
+        orderbook,
+        platform,
+        &taker,
+        ctx.remaining_accounts,
+        &order_type,
+        order_id,
+        worst_price,
+        amount,
+        max_fills,
+    )?;
+    // An order stopped by the price band before any fill still goes through,
+    // so the breach is recorded
+    require!(
+        summary.filled_amount > 0 || summary.band_breached,
+        DefiTradingError::NoLiquidity
+    );
+    let average_price = if summary.filled_amount > 0 {
+        // Market orders have no price up front, so the minimum notional
+        // applies to what actually filled
+        orderbook.market_config.check_notional(summary.quote_amount)?;
 
-    // Validate sufficient balance
-    match order_type {
-        OrderType::Sell => {
-            require!(
-                ctx.accounts.user_token_account.amount >= amount,
-                DefiTradingError::InsufficientTokens
-            );
-        },
-        OrderType::Buy => {
-            let total_cost = amount.checked_mul(execution_price).ok_or(DefiTradingError::ArithmeticOverflow)?;
-            require!(
-                ctx.accounts.user_payment_account.amount >= total_cost,
-                DefiTradingError::InsufficientFunds
-            );
-        }
-    }
+        // Buyers receive tokens, sellers receive payment net of platform fees
+        let amount_out = match order_type {
+            OrderType::Buy => summary.filled_amount,
+            OrderType::Sell => summary.quote_amount
+                .checked_sub(summary.platform_fees)
+                .ok_or(DefiTradingError::ArithmeticUnderflow)?,
+        };
+        require!(amount_out >= min_amount_out, DefiTradingError::SlippageExceeded);
+
+        unit_price(summary.quote_amount, summary.filled_amount, orderbook.base_decimals)?
+    } else {
+        0
+    };
+    let remaining_amount = amount - summary.filled_amount;
+    let now = Clock::get()?.unix_timestamp;
+
+    orderbook.last_order_id = order_id;
     
-    // Set up market order (will be immediately filled by matching logic)
+    // Record the market order; it never rests, so any remainder is cancelled
     order.id = order_id;
-    order.user = ctx.accounts.user.key();
+    order.user = user_key;
     order.company_id = orderbook.company_id;
-    order.token_mint = orderbook.token_mint;
+    order.token_mint = orderbook.base_mint;
     order.order_type = order_type.clone();
     order.market_order_type = MarketOrderType::Market;
     order.amount = amount;
-    order.remaining_amount = amount;
-    order.price = execution_price; // Market price
-    order.status = OrderStatus::Active;
-    order.created_at = Clock::get()?.unix_timestamp;
-    order.filled_at = None;
+    order.remaining_amount = remaining_amount;
+    order.price = average_price;
+    order.time_in_force = TimeInForce::ImmediateOrCancel;
+    order.expires_at = None;
+    order.self_trade_prevention = self_trade_prevention;
+    order.trading_account = trading_account;
+    order.status = if remaining_amount == 0 { OrderStatus::Filled } else { OrderStatus::Cancelled };
+    order.created_at = now;
+    order.filled_at = Some(now);
     order.bump = ctx.bumps.order;
 
-    orderbook.last_updated = Clock::get()?.unix_timestamp;
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
+
+    let fill = MarketOrderFill {
+        order_id,
+        filled_amount: summary.filled_amount,
+        quote_amount: summary.quote_amount,
+        platform_fees: summary.platform_fees,
+        average_price,
+        fills: summary.fills,
+    };
 
-    emit!(MarketOrderCreated {
+    emit!(MarketOrderFilled {
         order_id,
         user: order.user,
         company_id: order.company_id,
@@ -172,12 +315,16 @@ pub fn create_market_order(
             OrderType::Buy => "Buy".to_string(),
             OrderType::Sell => "Sell".to_string(),
         },
-        amount,
-        execution_price,
-        timestamp: order.created_at,
+        requested_amount: amount,
+        filled_amount: fill.filled_amount,
+        quote_amount: fill.quote_amount,
+        platform_fees: fill.platform_fees,
+        average_price,
+        fills: fill.fills,
+        timestamp: now,
     });
 
-    Ok(())
+    Ok(fill)
 }
 
 // Enhanced order matching with automatic execution
@@ -201,12 +348,36 @@ pub fn match_orders(
     require!(amount > 0, DefiTradingError::InvalidTradeAmount);
     require!(buy_order.id == buy_order_id, DefiTradingError::OrderNotFound);
     require!(sell_order.id == sell_order_id, DefiTradingError::OrderNotFound);
-    require!(buy_order.status == OrderStatus::Active, DefiTradingError::OrderAlreadyFilled);
-    require!(sell_order.status == OrderStatus::Active, DefiTradingError::OrderAlreadyFilled);
-    require!(buy_order.user != sell_order.user, DefiTradingError::SelfTrade);
+    require!(
+        buy_order.status == OrderStatus::Active || buy_order.status == OrderStatus::PartiallyFilled,
+        DefiTradingError::OrderAlreadyFilled
+    );
+    require!(
+        sell_order.status == OrderStatus::Active || sell_order.status == OrderStatus::PartiallyFilled,
+        DefiTradingError::OrderAlreadyFilled
+    );
+    require!(buy_order.self_trade_key() != sell_order.self_trade_key(), DefiTradingError::SelfTrade);
+    let now = Clock::get()?.unix_timestamp;
+    require!(!buy_order.is_expired(now), DefiTradingError::OrderExpired);
+    require!(!sell_order.is_expired(now), DefiTradingError::OrderExpired);
     require!(amount <= buy_order.remaining_amount, DefiTradingError::InvalidTradeAmount);
     require!(amount <= sell_order.remaining_amount, DefiTradingError::InvalidTradeAmount);
 
+    // Resting orders are filled strictly in price-time priority
+    let mut book = ctx.accounts.order_book.load_mut()?;
+    if buy_order.market_order_type == MarketOrderType::Limit {
+        require!(
+            book.head(&OrderType::Buy).map(|node| node.order_id) == Some(buy_order.id),
+            DefiTradingError::OrderNotAtTopOfBook
+        );
+    }
+    if sell_order.market_order_type == MarketOrderType::Limit {
+        require!(
+            book.head(&OrderType::Sell).map(|node| node.order_id) == Some(sell_order.id),
+            DefiTradingError::OrderNotAtTopOfBook
+        );
+    }
+
     // Determine execution price based on order types
     let execution_price = match (buy_order.market_order_type.clone(), sell_order.market_order_type.clone()) {
         (MarketOrderType::Market, MarketOrderType::Limit) => sell_order.price,
@@ -218,22 +389,29 @@ pub fn match_orders(
         }
     };
 
-    let total_value = amount.checked_mul(execution_price).ok_or(DefiTradingError::ArithmeticOverflow)?;
-    let platform_fee = total_value
-        .checked_mul(platform.platform_fee as u64)
-        .ok_or(DefiTradingError::ArithmeticOverflow)?
-        .checked_div(10000)
-        .ok_or(DefiTradingError::DivisionByZero)?;
+    // Explicit matches cannot stop part way, so a fill outside the band
+    // fails outright
+    orderbook.circuit_breaker.check_not_halted(now)?;
+    let reference_price = orderbook.circuit_breaker.reference_price(orderbook.last_trade_price);
+    require!(
+        orderbook.circuit_breaker.in_band(execution_price, reference_price),
+        DefiTradingError::PriceOutsideBand
+    );
+
+    let total_value = quote_for_base(amount, execution_price, orderbook.base_decimals, Rounding::Down)?;
+    let platform_fee = fee_amount(total_value, platform.platform_fee)?;
     let seller_proceeds = total_value.checked_sub(platform_fee).ok_or(DefiTradingError::ArithmeticUnderflow)?;
 
-    let trade_id = platform.total_trades + 1;
+    let trade_id = platform.total_trades
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
     
     // Record trade
     trade.id = trade_id;
     trade.buyer = buy_order.user;
     trade.seller = sell_order.user;
     trade.company_id = orderbook.company_id;
-    trade.token_mint = orderbook.token_mint;
+    trade.token_mint = orderbook.base_mint;
     trade.amount = amount;
     trade.price = execution_price;
     trade.total_value = total_value;
@@ -244,36 +422,55 @@ pub fn match_orders(
     trade.bump = ctx.bumps.trade;
 
     // Execute transfers based on order type
+    let company_id_bytes = orderbook.company_id.to_le_bytes();
     let buy_order_user = buy_order.user;
     let buy_order_bump = buy_order.bump;
     let sell_order_user = sell_order.user;
     let sell_order_bump = sell_order.bump;
     
     if buy_order.market_order_type == MarketOrderType::Market {
-        // Market buy order - transfer payment directly from user
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.buyer_payment_account.to_account_info(),
-            to: ctx.accounts.seller_payment_account.to_account_info(),
-            authority: ctx.accounts.buyer.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, seller_proceeds)?;
+        // Market buy order - transfer payment directly from user, wrapping
+        // native SOL if no payment account was given
+        collect_payment(
+            ctx.accounts.buyer.to_account_info(),
+            ctx.accounts.buyer_payment_account.as_ref(),
+            ctx.accounts.seller_payment_account.to_account_info(),
+            ctx.accounts.seller_payment_account.mint,
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            seller_proceeds,
+        )?;
 
         // Platform fee
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.buyer_payment_account.to_account_info(),
-            to: ctx.accounts.platform_fee_account.to_account_info(),
-            authority: ctx.accounts.buyer.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_ctx, platform_fee)?;
+        collect_payment(
+            ctx.accounts.buyer.to_account_info(),
+            ctx.accounts.buyer_payment_account.as_ref(),
+            ctx.accounts.platform_fee_account.to_account_info(),
+            ctx.accounts.platform_fee_account.mint,
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            platform_fee,
+        )?;
     } else {
-        // Limit buy order - transfer from escrow
+        // Limit buy order - transfer from escrow. The escrow was locked at
+        // the buy price, so any price improvement goes back to the buyer and
+        // the escrow keeps exactly what the rest of the order needs.
+        let buy_remaining_after = buy_order.remaining_amount
+            .checked_sub(amount)
+            .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+        let price_improvement = buy_escrow_refund(
+            ctx.accounts.buy_order_escrow.amount,
+            total_value,
+            buy_remaining_after,
+            buy_order.price,
+            orderbook.base_decimals,
+        )?;
+        
         let buy_order_id_bytes = buy_order_id.to_le_bytes();
         
         let buy_seeds = &[
             b"enhanced_order",
+            company_id_bytes.as_ref(),
             buy_order_user.as_ref(),
             buy_order_id_bytes.as_ref(),
             &[buy_order_bump],
@@ -297,6 +494,31 @@ pub fn match_orders(
         };
         let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, buy_signer);
         token::transfer(cpi_ctx, platform_fee)?;
+        
+        // A temporary wSOL account is always closed again, even when there
+        // is nothing to refund
+        if price_improvement > 0 || ctx.accounts.temp_wsol_account.is_some() {
+            require_keys_eq!(ctx.accounts.buyer.key(), buy_order_user, DefiTradingError::Unauthorized);
+            return_payment(
+                &ctx.accounts.buy_order_escrow,
+                buy_order_info.clone(),
+                buy_signer,
+                ctx.accounts.buyer.to_account_info(),
+                ctx.accounts.buyer_payment_account.as_ref(),
+                ctx.accounts.temp_wsol_account.as_ref(),
+                ctx.accounts.token_program.to_account_info(),
+                price_improvement,
+            )?;
+        }
+        if price_improvement > 0 {
+            emit!(PriceImprovementRefunded {
+                order_id: buy_order_id,
+                user: buy_order_user,
+                company_id: buy_order.company_id,
+                amount: price_improvement,
+                timestamp: trade.executed_at,
+            });
+        }
     }
 
     // Transfer tokens to buyer
@@ -316,6 +538,7 @@ pub fn match_orders(
         
         let sell_seeds = &[
             b"enhanced_order",
+            company_id_bytes.as_ref(),
             sell_order_user.as_ref(),
             sell_order_id_bytes.as_ref(),
             &[sell_order_bump],
@@ -333,8 +556,12 @@ pub fn match_orders(
     }
 
     // Update orders
-    buy_order.remaining_amount = buy_order.remaining_amount.checked_sub(amount).unwrap();
-    sell_order.remaining_amount = sell_order.remaining_amount.checked_sub(amount).unwrap();
+    buy_order.remaining_amount = buy_order.remaining_amount
+        .checked_sub(amount)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+    sell_order.remaining_amount = sell_order.remaining_amount
+        .checked_sub(amount)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
 
     if buy_order.remaining_amount == 0 {
         buy_order.status = OrderStatus::Filled;
@@ -350,9 +577,20 @@ pub fn match_orders(
         sell_order.status = OrderStatus::PartiallyFilled;
     }
 
+    if buy_order.market_order_type == MarketOrderType::Limit {
+        book.reduce(&OrderType::Buy, buy_order.id, amount)?;
+    }
+    if sell_order.market_order_type == MarketOrderType::Limit {
+        book.reduce(&OrderType::Sell, sell_order.id, amount)?;
+    }
+
     // Update orderbook
+    sync_book_state(orderbook, &book, now);
     orderbook.last_trade_price = execution_price;
-    orderbook.total_volume = orderbook.total_volume.checked_add(amount).unwrap();
+    orderbook.circuit_breaker.record_fill(execution_price);
+    orderbook.total_volume = orderbook.total_volume
+        .checked_add(amount)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
     orderbook.last_updated = Clock::get()?.unix_timestamp;
     platform.total_trades = trade_id;
 
@@ -373,224 +611,1351 @@ pub fn match_orders(
     Ok(())
 }
 
-// Calculate and update market depth
-pub fn calculate_market_depth(
-    ctx: Context<CalculateMarketDepth>,
-    company_id: u64,
-) -> Result<()> {
-    let orderbook = &ctx.accounts.orderbook;
-    let market_depth = &mut ctx.accounts.market_depth;
+// Creates the sorted order book for a market. Open orders placed before the
+// book existed join it as they are moved over by migrate_enhanced_order.
+pub fn initialize_order_book(ctx: Context<InitializeOrderBook>) -> Result<()> {
+    let orderbook = &mut ctx.accounts.orderbook;
     
-    require!(orderbook.company_id == company_id, DefiTradingError::InvalidCompanyData);
+    require!(
+        ctx.accounts.authority.key() == ctx.accounts.platform.authority,
+        DefiTradingError::Unauthorized
+    );
+    // Markets opened before minimum order values were required are
+    // configured with one before they get a book
+    orderbook.market_config.validate()?;
 
-    // Initialize market depth
-    market_depth.orderbook = orderbook.key();
-    market_depth.price_levels = Vec::new();
-    market_depth.total_buy_volume = 0;
-    market_depth.total_sell_volume = 0;
-    market_depth.last_updated = Clock::get()?.unix_timestamp;
-    market_depth.bump = ctx.bumps.market_depth;
+    let mut book = ctx.accounts.order_book.load_init()?;
+    book.orderbook = orderbook.key();
+    let now = Clock::get()?.unix_timestamp;
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
 
-    emit!(MarketDepthCalculated {
-        company_id,
+    emit!(OrderBookInitialized {
+        company_id: orderbook.company_id,
         orderbook: orderbook.key(),
-        total_buy_volume: market_depth.total_buy_volume,
-        total_sell_volume: market_depth.total_sell_volume,
-        timestamp: market_depth.last_updated,
+        order_book: ctx.accounts.order_book.key(),
+        timestamp: orderbook.last_updated,
     });
 
     Ok(())
 }
 
-// Context structs
-#[derive(Accounts)]
-#[instruction(order_type: OrderType)]
-pub struct CreateLimitOrder<'info> {
-    #[account(
-        seeds = [b"platform"],
-        bump = platform.bump
-    )]
-    pub platform: Account<'info, Platform>,
-    
-    #[account(
-        mut,
-        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
-        bump = orderbook.bump
-    )]
-    pub orderbook: Account<'info, Orderbook>,
-    
-    #[account(
-        init,
-        payer = user,
-        space = EnhancedOrder::LEN,
-        seeds = [
-            b"enhanced_order",
-            user.key().as_ref(),
-            (orderbook.total_buy_orders + orderbook.total_sell_orders + 1).to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub order: Account<'info, EnhancedOrder>,
-    
-    #[account(
-        init,
-        payer = user,
-        token::mint = token_mint,
-        token::authority = order,
-        seeds = [
-            b"enhanced_order_escrow",
-            user.key().as_ref(),
-            (orderbook.total_buy_orders + orderbook.total_sell_orders + 1).to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub order_escrow_account: Account<'info, TokenAccount>,
-    
-    #[account(
-        mut,
-        constraint = user_token_account.mint == orderbook.token_mint
-    )]
-    pub user_token_account: Account<'info, TokenAccount>,
+// Changes a market's tick size, lot size and minimum order size. Orders
+// already resting keep their price and size.
+pub fn configure_market(ctx: Context<ConfigureMarket>, market_config: MarketConfig) -> Result<()> {
+    let orderbook = &mut ctx.accounts.orderbook;
     
-    #[account(mut)]
-    pub user_payment_account: Account<'info, TokenAccount>,
+    require!(
+        ctx.accounts.authority.key() == ctx.accounts.platform.authority,
+        DefiTradingError::Unauthorized
+    );
+    market_config.validate()?;
     
-    pub token_mint: Account<'info, anchor_spl::token::Mint>,
-    pub payment_mint: Account<'info, anchor_spl::token::Mint>,
+    orderbook.market_config = market_config;
+    orderbook.last_updated = Clock::get()?.unix_timestamp;
     
-    #[account(mut)]
-    pub user: Signer<'info>,
+    emit!(MarketConfigured {
+        company_id: orderbook.company_id,
+        tick_size: market_config.tick_size,
+        lot_size: market_config.lot_size,
+        min_base_size: market_config.min_base_size,
+        min_notional: market_config.min_notional,
+        timestamp: orderbook.last_updated,
+    });
     
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+    Ok(())
 }
 
-#[derive(Accounts)]
-#[instruction(order_type: OrderType)]
-pub struct CreateMarketOrder<'info> {
-    #[account(
-        seeds = [b"platform"],
-        bump = platform.bump
-    )]
-    pub platform: Account<'info, Platform>,
-    
-    #[account(
-        mut,
-        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
-        bump = orderbook.bump
-    )]
-    pub orderbook: Account<'info, Orderbook>,
-    
-    #[account(
-        init,
-        payer = user,
-        space = EnhancedOrder::LEN,
-        seeds = [
-            b"enhanced_order",
-            user.key().as_ref(),
-            (orderbook.total_buy_orders + orderbook.total_sell_orders + 1).to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub order: Account<'info, EnhancedOrder>,
+// Sets the price band of a market. Reconfiguring clears the breach count but
+// leaves a running halt in place.
+pub fn configure_circuit_breaker(ctx: Context<ConfigureCircuitBreaker>, config: CircuitBreakerConfig) -> Result<()> {
+    let orderbook = &mut ctx.accounts.orderbook;
     
-    #[account(
-        mut,
-        constraint = user_token_account.mint == orderbook.token_mint
-    )]
-    pub user_token_account: Account<'info, TokenAccount>,
+    require!(
+        ctx.accounts.authority.key() == ctx.accounts.platform.authority,
+        DefiTradingError::Unauthorized
+    );
+    config.validate()?;
     
-    #[account(mut)]
-    pub user_payment_account: Account<'info, TokenAccount>,
+    let now = Clock::get()?.unix_timestamp;
+    orderbook.circuit_breaker.config = config;
+    orderbook.circuit_breaker.breaches = 0;
+    orderbook.circuit_breaker.window_started_at = now;
+    orderbook.last_updated = now;
     
-    #[account(mut)]
-    pub user: Signer<'info>,
+    emit!(CircuitBreakerConfigured {
+        company_id: orderbook.company_id,
+        band_bps: config.band_bps,
+        reference: band_reference_name(config.reference),
+        max_breaches: config.max_breaches,
+        breach_window: config.breach_window,
+        halt_duration: config.halt_duration,
+        timestamp: now,
+    });
     
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct MatchOrders<'info> {
-    #[account(
-        mut,
-        seeds = [b"platform"],
-        bump = platform.bump
-    )]
-    pub platform: Account<'info, Platform>,
+fn band_reference_name(reference: BandReference) -> String {
+    match reference {
+        BandReference::LastTrade => "LastTrade".to_string(),
+        BandReference::RollingAverage => "RollingAverage".to_string(),
+    }
+}
+
+// Anyone may cancel an order once it has expired, or once a full book side
+// has evicted it. The escrow goes back to the owner, as native SOL for
+// wrapped SOL escrows when no owner token account is given.
+pub fn cancel_expired_order(ctx: Context<CancelExpiredOrder>) -> Result<()> {
+    // Store account info before any mutations
+    let order_info = ctx.accounts.order.to_account_info();
+
+    let orderbook = &mut ctx.accounts.orderbook;
+    let order = &mut ctx.accounts.order;
+    let now = Clock::get()?.unix_timestamp;
+    let mut book = ctx.accounts.order_book.load_mut()?;
     
-    #[account(
-        mut,
-        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
-        bump = orderbook.bump
-    )]
-    pub orderbook: Account<'info, Orderbook>,
+    require!(
+        order.status == OrderStatus::Active || order.status == OrderStatus::PartiallyFilled,
+        DefiTradingError::OrderAlreadyCancelled
+    );
+    let evicted = order.market_order_type == MarketOrderType::Limit
+        && book.find(&order.order_type, order.id).is_none();
+    require!(order.is_expired(now) || evicted, DefiTradingError::OrderNotExpired);
+
+    let company_id_bytes = order.company_id.to_le_bytes();
+    let order_id_bytes = order.id.to_le_bytes();
+    let seeds = &[
+        b"enhanced_order",
+        company_id_bytes.as_ref(),
+        order.user.as_ref(),
+        order_id_bytes.as_ref(),
+        &[order.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let refund_amount = ctx.accounts.order_escrow_account.amount;
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+
+    match &ctx.accounts.owner_token_account {
+        Some(owner_token_account) => {
+            if refund_amount > 0 {
+                let cpi_accounts = Transfer {
+                    from: ctx.accounts.order_escrow_account.to_account_info(),
+                    to: owner_token_account.to_account_info(),
+                    authority: order_info,
+                };
+                let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+                token::transfer(cpi_ctx, refund_amount)?;
+            }
+        },
+        None => {
+            require_keys_eq!(
+                ctx.accounts.order_escrow_account.mint,
+                native_mint::ID,
+                DefiTradingError::NativeMintRequired
+            );
+            let cpi_accounts = CloseAccount {
+                account: ctx.accounts.order_escrow_account.to_account_info(),
+                destination: ctx.accounts.owner.to_account_info(),
+                authority: order_info,
+            };
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::close_account(cpi_ctx)?;
+        }
+    }
+
+    release_order(&mut book, order)?;
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
+
+    emit!(OrderExpired {
+        order_id: order.id,
+        user: order.user,
+        company_id: order.company_id,
+        refunded_amount: refund_amount,
+        cancelled_by: ctx.accounts.caller.key(),
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Owner cancels an open order. The escrow is refunded and closed, the order
+// leaves the book and the open order count for its side drops.
+pub fn cancel_enhanced_order(ctx: Context<CancelEnhancedOrder>) -> Result<()> {
+    let order_info = ctx.accounts.order.to_account_info();
+    let orderbook = &mut ctx.accounts.orderbook;
+    let order = &mut ctx.accounts.order;
+    let now = Clock::get()?.unix_timestamp;
     
-    #[account(
-        mut,
-        seeds = [
-            b"enhanced_order",
-            buy_order.user.as_ref(),
-            buy_order.id.to_le_bytes().as_ref()
-        ],
-        bump = buy_order.bump
-    )]
-    pub buy_order: Account<'info, EnhancedOrder>,
+    require!(
+        order.status == OrderStatus::Active || order.status == OrderStatus::PartiallyFilled,
+        DefiTradingError::OrderAlreadyCancelled
+    );
+    require!(order.user == ctx.accounts.user.key(), DefiTradingError::Unauthorized);
+    require!(!orderbook.is_locked_order(order.id, now), DefiTradingError::LiquidityLocked);
+
+    refund_order_escrow(
+        order,
+        order_info,
+        ctx.accounts.order_escrow_account.to_account_info(),
+        &ctx.accounts.order_escrow_account,
+        ctx.accounts.user_account.as_ref().map(|account| account.to_account_info()),
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+    )?;
+
+    let mut book = ctx.accounts.order_book.load_mut()?;
+    release_order(&mut book, order)?;
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
+
+    emit!(OrderCancelled {
+        order_id: order.id,
+        user: order.user,
+        company_id: order.company_id,
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Cancels every order the user passes in, two remaining accounts per order:
+//   [order, order escrow]
+// Sell escrows are refunded to the token account and buy escrows to the
+// payment account, or unwrapped to the wallet when that is omitted.
+pub fn cancel_all_orders<'info>(ctx: Context<'_, '_, '_, 'info, CancelAllOrders<'info>>) -> Result<()> {
+    let orderbook = &mut ctx.accounts.orderbook;
+    let user_key = ctx.accounts.user.key();
+    let now = Clock::get()?.unix_timestamp;
     
-    #[account(
-        mut,
-        seeds = [
-            b"enhanced_order",
-            sell_order.user.as_ref(),
-            sell_order.id.to_le_bytes().as_ref()
-        ],
-        bump = sell_order.bump
-    )]
+    require!(
+        !ctx.remaining_accounts.is_empty() && ctx.remaining_accounts.len().is_multiple_of(2),
+        DefiTradingError::OrderNotFound
+    );
+
+    let mut book = ctx.accounts.order_book.load_mut()?;
+    let mut cancelled_orders = 0u64;
+    for accounts in ctx.remaining_accounts.chunks(2) {
+        let (order_info, escrow_info) = (&accounts[0], &accounts[1]);
+        require_keys_eq!(*order_info.owner, crate::ID, DefiTradingError::OrderNotFound);
+        require!(order_info.is_writable, DefiTradingError::OrderNotFound);
+        let mut order = EnhancedOrder::try_deserialize(&mut &order_info.try_borrow_data()?[..])?;
+        require!(order.user == user_key, DefiTradingError::Unauthorized);
+        require!(order.company_id == orderbook.company_id, DefiTradingError::InvalidCompanyData);
+
+        let company_id_bytes = order.company_id.to_le_bytes();
+        let order_id_bytes = order.id.to_le_bytes();
+        let order_key = Pubkey::create_program_address(
+            &[
+                b"enhanced_order",
+                company_id_bytes.as_ref(),
+                user_key.as_ref(),
+                order_id_bytes.as_ref(),
+                &[order.bump],
+            ],
+            &crate::ID,
+        )
+        .map_err(|_| error!(DefiTradingError::OrderNotFound))?;
+        require_keys_eq!(order_info.key(), order_key, DefiTradingError::OrderNotFound);
+
+        // Already closed orders are skipped so a stale list still goes through
+        if order.status != OrderStatus::Active && order.status != OrderStatus::PartiallyFilled {
+            continue;
+        }
+        require!(!orderbook.is_locked_order(order.id, now), DefiTradingError::LiquidityLocked);
+
+        let (escrow_key, _) = Pubkey::find_program_address(
+            &[
+                b"enhanced_order_escrow",
+                company_id_bytes.as_ref(),
+                user_key.as_ref(),
+                order_id_bytes.as_ref(),
+            ],
+            &crate::ID,
+        );
+        require_keys_eq!(escrow_info.key(), escrow_key, DefiTradingError::TokenAccountMismatch);
+        let escrow = TokenAccount::try_deserialize(&mut &escrow_info.try_borrow_data()?[..])?;
+
+        let user_account = match order.order_type {
+            OrderType::Sell => ctx.accounts.user_token_account.as_ref(),
+            OrderType::Buy => ctx.accounts.user_payment_account.as_ref(),
+        };
+        if let Some(user_account) = user_account {
+            require_keys_eq!(user_account.mint, escrow.mint, DefiTradingError::TokenAccountMismatch);
+        }
+
+        refund_order_escrow(
+            &order,
+            order_info.clone(),
+            escrow_info.clone(),
+            &escrow,
+            user_account.map(|account| account.to_account_info()),
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+        release_order(&mut book, &mut order)?;
+        order.try_serialize(&mut &mut order_info.try_borrow_mut_data()?[..])?;
+        cancelled_orders += 1;
+
+        emit!(OrderCancelled {
+            order_id: order.id,
+            user: order.user,
+            company_id: order.company_id,
+            timestamp: now,
+        });
+    }
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
+
+    emit!(AllOrdersCancelled {
+        user: user_key,
+        company_id: orderbook.company_id,
+        cancelled_orders,
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Returns an order's whole escrow to its owner and closes the escrow. With
+// no owner account a wrapped SOL escrow is unwrapped straight to the owner.
+fn refund_order_escrow<'info>(
+    order: &EnhancedOrder,
+    order_info: AccountInfo<'info>,
+    escrow_info: AccountInfo<'info>,
+    escrow: &TokenAccount,
+    owner_account: Option<AccountInfo<'info>>,
+    owner: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+) -> Result<u64> {
+    let company_id_bytes = order.company_id.to_le_bytes();
+    let order_id_bytes = order.id.to_le_bytes();
+    let seeds = &[
+        b"enhanced_order",
+        company_id_bytes.as_ref(),
+        order.user.as_ref(),
+        order_id_bytes.as_ref(),
+        &[order.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    match owner_account {
+        Some(owner_account) => {
+            if escrow.amount > 0 {
+                let cpi_accounts = Transfer {
+                    from: escrow_info.clone(),
+                    to: owner_account,
+                    authority: order_info.clone(),
+                };
+                let cpi_ctx = CpiContext::new_with_signer(token_program.clone(), cpi_accounts, signer);
+                token::transfer(cpi_ctx, escrow.amount)?;
+            }
+        },
+        None => require_keys_eq!(escrow.mint, native_mint::ID, DefiTradingError::NativeMintRequired),
+    }
+
+    let cpi_accounts = CloseAccount {
+        account: escrow_info,
+        destination: owner,
+        authority: order_info,
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer);
+    token::close_account(cpi_ctx)?;
+
+    Ok(escrow.amount)
+}
+
+// Takes a cancelled order out of the book. Matching may already have dropped
+// it from the book.
+pub(crate) fn release_order(book: &mut OrderBookSlab, order: &mut EnhancedOrder) -> Result<()> {
+    release_book_order(book, &order.order_type, order.id)?;
+    order.status = OrderStatus::Cancelled;
+    Ok(())
+}
+
+// Book side of release_order, shared with open orders accounts
+pub(crate) fn release_book_order(book: &mut OrderBookSlab, order_type: &OrderType, order_id: u64) -> Result<()> {
+    if book.find(order_type, order_id).is_some() {
+        book.remove(order_type, order_id)?;
+    }
+    Ok(())
+}
+
+// Amends a resting limit order in place. Reducing its size keeps its place
+// in the queue; a new price or a larger size re-enters the book behind
+// every order already at that price. The escrow difference is settled
+// atomically.
+pub fn amend_enhanced_order(
+    ctx: Context<AmendEnhancedOrder>,
+    new_remaining_amount: u64,
+    new_price: u64,
+) -> Result<()> {
+    let order_info = ctx.accounts.order.to_account_info();
+    let orderbook = &mut ctx.accounts.orderbook;
+    let order = &mut ctx.accounts.order;
+    let now = Clock::get()?.unix_timestamp;
+    
+    require!(!ctx.accounts.platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(
+        order.status == OrderStatus::Active || order.status == OrderStatus::PartiallyFilled,
+        DefiTradingError::OrderAlreadyCancelled
+    );
+    require!(order.user == ctx.accounts.user.key(), DefiTradingError::Unauthorized);
+    require!(order.market_order_type == MarketOrderType::Limit, DefiTradingError::InvalidOrderParams);
+    require!(!orderbook.is_locked_order(order.id, now), DefiTradingError::LiquidityLocked);
+    require!(!order.is_expired(now), DefiTradingError::OrderExpired);
+    require!(new_remaining_amount > 0, DefiTradingError::InvalidOrderParams);
+    require!(new_price > 0, DefiTradingError::InvalidOrderParams);
+    // A partially filled order may already be below the minimum size, so
+    // only the granularity is checked
+    orderbook.market_config.check_price(new_price)?;
+    orderbook.market_config.check_lot(new_remaining_amount)?;
+
+    let old_price = order.price;
+    let old_remaining_amount = order.remaining_amount;
+    let priority_kept = new_price == old_price && new_remaining_amount <= old_remaining_amount;
+
+    let mut book = ctx.accounts.order_book.load_mut()?;
+    if priority_kept {
+        if new_remaining_amount < old_remaining_amount {
+            book.reduce(&order.order_type, order.id, old_remaining_amount - new_remaining_amount)?;
+        }
+    } else {
+        book.remove(&order.order_type, order.id)?;
+
+        // An amended order only rests; it never takes liquidity
+        let maker_side = match order.order_type {
+            OrderType::Buy => OrderType::Sell,
+            OrderType::Sell => OrderType::Buy,
+        };
+        book.remove_expired_heads(&maker_side, now);
+        let best_opposite = book.best_price(&maker_side, now);
+        let would_cross = match order.order_type {
+            OrderType::Buy => best_opposite != 0 && best_opposite <= new_price,
+            OrderType::Sell => best_opposite != 0 && best_opposite >= new_price,
+        };
+        require!(!would_cross, DefiTradingError::AmendedOrderWouldCross);
+
+        book.insert(
+            &order.order_type,
+            order.id,
+            order.user,
+            Pubkey::default(),
+            new_price,
+            new_remaining_amount,
+            order.expires_at.unwrap_or(0),
+        )?;
+    }
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
+
+    let required_escrow = match order.order_type {
+        OrderType::Sell => new_remaining_amount,
+        OrderType::Buy => buy_escrow_required(new_remaining_amount, new_price, orderbook.base_decimals)?,
+    };
+
+    let company_id_bytes = order.company_id.to_le_bytes();
+    let order_id_bytes = order.id.to_le_bytes();
+    let seeds = &[
+        b"enhanced_order",
+        company_id_bytes.as_ref(),
+        order.user.as_ref(),
+        order_id_bytes.as_ref(),
+        &[order.bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    let (escrow_topped_up, escrow_refunded) = rebalance_escrow(
+        &ctx.accounts.order_escrow_account,
+        order_info,
+        signer,
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.user_account.as_ref(),
+        ctx.accounts.temp_wsol_account.as_ref(),
+        ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.system_program.to_account_info(),
+        required_escrow,
+    )?;
+
+    order.amount = order.amount - old_remaining_amount + new_remaining_amount;
+    order.remaining_amount = new_remaining_amount;
+    order.price = new_price;
+
+    emit!(OrderAmended {
+        order_id: order.id,
+        user: order.user,
+        company_id: order.company_id,
+        old_price,
+        new_price,
+        old_remaining_amount,
+        new_remaining_amount,
+        escrow_topped_up,
+        escrow_refunded,
+        priority_kept,
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Moves an open order created before order addresses included the market to
+// its market-scoped address. Legacy ids were not unique, so the order gets
+// the market's next id, and a limit order joins the back of its price level
+// in the book.
+pub fn migrate_enhanced_order(ctx: Context<MigrateEnhancedOrder>) -> Result<()> {
+    let legacy_order_info = ctx.accounts.legacy_order.to_account_info();
+    let legacy_order = &ctx.accounts.legacy_order;
+    let orderbook = &mut ctx.accounts.orderbook;
+    
+    require!(
+        legacy_order.status == OrderStatus::Active || legacy_order.status == OrderStatus::PartiallyFilled,
+        DefiTradingError::OrderAlreadyFilled
+    );
+    
+    let legacy_id_bytes = legacy_order.id.to_le_bytes();
+    let seeds = &[
+        b"enhanced_order",
+        legacy_order.user.as_ref(),
+        legacy_id_bytes.as_ref(),
+        &[legacy_order.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let escrow_amount = ctx.accounts.legacy_escrow_account.amount;
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    
+    if escrow_amount > 0 {
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.legacy_escrow_account.to_account_info(),
+            to: ctx.accounts.order_escrow_account.to_account_info(),
+            authority: legacy_order_info.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, escrow_amount)?;
+    }
+    
+    let cpi_accounts = CloseAccount {
+        account: ctx.accounts.legacy_escrow_account.to_account_info(),
+        destination: ctx.accounts.user.to_account_info(),
+        authority: legacy_order_info,
+    };
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token::close_account(cpi_ctx)?;
+    
+    let order_id = orderbook.last_order_id
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let now = Clock::get()?.unix_timestamp;
+    
+    if legacy_order.market_order_type == MarketOrderType::Limit && legacy_order.remaining_amount > 0 {
+        let mut book = ctx.accounts.order_book.load_mut()?;
+        let evicted = book.insert(
+            &legacy_order.order_type,
+            order_id,
+            legacy_order.user,
+            Pubkey::default(),
+            legacy_order.price,
+            legacy_order.remaining_amount,
+            0,
+        )?;
+        record_eviction(orderbook, evicted, now)?;
+        sync_book_state(orderbook, &book, now);
+    }
+    orderbook.last_order_id = order_id;
+    orderbook.last_updated = now;
+    
+    ctx.accounts.order.set_inner(EnhancedOrder {
+        id: order_id,
+        user: legacy_order.user,
+        company_id: legacy_order.company_id,
+        token_mint: legacy_order.token_mint,
+        order_type: legacy_order.order_type.clone(),
+        market_order_type: legacy_order.market_order_type.clone(),
+        amount: legacy_order.amount,
+        remaining_amount: legacy_order.remaining_amount,
+        price: legacy_order.price,
+        time_in_force: TimeInForce::GoodTillCancelled,
+        expires_at: None,
+        self_trade_prevention: SelfTradePrevention::CancelTaker,
+        trading_account: Pubkey::default(),
+        status: legacy_order.status.clone(),
+        created_at: legacy_order.created_at,
+        filled_at: legacy_order.filled_at,
+        bump: ctx.bumps.order,
+    });
+    
+    emit!(EnhancedOrderMigrated {
+        order_id,
+        legacy_order_id: legacy_order.id,
+        user: legacy_order.user,
+        company_id: legacy_order.company_id,
+        legacy_order: ctx.accounts.legacy_order.key(),
+        order: ctx.accounts.order.key(),
+        escrow_amount,
+        timestamp: now,
+    });
+    
+    Ok(())
+}
+
+// Trading account an order is placed for, checked to include the user
+pub(crate) fn trading_account_key(user: &Pubkey, trading_account: Option<&Account<TradingAccount>>) -> Result<Pubkey> {
+    match trading_account {
+        Some(trading_account) => {
+            require!(trading_account.includes(user), DefiTradingError::WalletNotLinked);
+            Ok(trading_account.key())
+        },
+        None => Ok(Pubkey::default()),
+    }
+}
+
+pub(crate) fn time_in_force_name(time_in_force: &TimeInForce) -> String {
+    match time_in_force {
+        TimeInForce::GoodTillCancelled => "GoodTillCancelled".to_string(),
+        TimeInForce::ImmediateOrCancel => "ImmediateOrCancel".to_string(),
+        TimeInForce::FillOrKill => "FillOrKill".to_string(),
+        TimeInForce::PostOnly => "PostOnly".to_string(),
+        TimeInForce::GoodTillTime => "GoodTillTime".to_string(),
+    }
+}
+
+// Only good-till-time orders carry an expiry, which must lie in the future
+pub(crate) fn check_time_in_force(time_in_force: &TimeInForce, expires_at: Option<i64>, now: i64) -> Result<()> {
+    match time_in_force {
+        TimeInForce::GoodTillTime => {
+            let expires_at = expires_at.ok_or(DefiTradingError::InvalidOrderParams)?;
+            require!(expires_at > now, DefiTradingError::InvalidTimestamp);
+        },
+        _ => require!(expires_at.is_none(), DefiTradingError::InvalidOrderParams),
+    }
+    Ok(())
+}
+
+// Fills a limit order may take before resting. Post-only orders must rest
+// without taking anything.
+pub(crate) fn limit_order_max_fills(
+    book: &OrderBookSlab,
+    order_type: &OrderType,
+    price: u64,
+    time_in_force: &TimeInForce,
+    max_fills: u8,
+    now: i64,
+) -> Result<u8> {
+    if *time_in_force != TimeInForce::PostOnly {
+        return Ok(max_fills);
+    }
+    let best_opposite = match order_type {
+        OrderType::Buy => book.best_price(&OrderType::Sell, now),
+        OrderType::Sell => book.best_price(&OrderType::Buy, now),
+    };
+    let would_take = match order_type {
+        OrderType::Buy => best_opposite != 0 && best_opposite <= price,
+        OrderType::Sell => best_opposite != 0 && best_opposite >= price,
+    };
+    require!(!would_take, DefiTradingError::PostOnlyWouldTake);
+    Ok(0)
+}
+
+// Reports an order a full book side pushed out to make room. An evicted
+// order with its own account is refunded like an expired one; one in an open
+// orders account is released when its owner cancels it. Locked seed orders
+// are never evicted, so the new order is rejected instead.
+pub(crate) fn record_eviction(orderbook: &Market, evicted: Option<BookNode>, now: i64) -> Result<()> {
+    if let Some(node) = evicted {
+        require!(!orderbook.is_locked_order(node.order_id, now), DefiTradingError::OrderBookFull);
+        emit!(OrderEvicted {
+            order_id: node.order_id,
+            user: node.owner,
+            company_id: orderbook.company_id,
+            open_orders: node.open_orders,
+            price: node.price,
+            quantity: node.quantity,
+            timestamp: now,
+        });
+    }
+    Ok(())
+}
+
+// Refreshes the market's best prices and order counts from the book. Called
+// after every change to the book, so they never go stale after fills and
+// cancels.
+pub(crate) fn sync_book_state(orderbook: &mut Market, book: &OrderBookSlab, now: i64) {
+    orderbook.best_bid = book.best_price(&OrderType::Buy, now);
+    orderbook.best_ask = book.best_price(&OrderType::Sell, now);
+    orderbook.total_buy_orders = book.live_count(&OrderType::Buy, now);
+    orderbook.total_sell_orders = book.live_count(&OrderType::Sell, now);
+}
+
+// Snapshots the top price levels of both sides of the book and the total
+// resting volume into the market's depth account, so clients can read depth
+// from one account. Anyone may refresh it; the account is created on first
+// use and grown if it predates the current layout.
+pub fn calculate_market_depth(
+    ctx: Context<CalculateMarketDepth>,
+    company_id: u64,
+) -> Result<()> {
+    let orderbook = &ctx.accounts.orderbook;
+    
+    require!(orderbook.company_id == company_id, DefiTradingError::InvalidCompanyData);
+
+    let now = Clock::get()?.unix_timestamp;
+    let book = ctx.accounts.order_book.load()?;
+    let mut price_levels = book.levels(&OrderType::Buy, MarketDepth::MAX_LEVELS_PER_SIDE, now)?;
+    price_levels.extend(book.levels(&OrderType::Sell, MarketDepth::MAX_LEVELS_PER_SIDE, now)?);
+    let market_depth = MarketDepth {
+        orderbook: orderbook.key(),
+        price_levels,
+        total_buy_volume: book.total_quantity(&OrderType::Buy, now)?,
+        total_sell_volume: book.total_quantity(&OrderType::Sell, now)?,
+        last_updated: now,
+        bump: ctx.bumps.market_depth,
+    };
+    drop(book);
+
+    let company_id_bytes = company_id.to_le_bytes();
+    let seeds = &[
+        b"market_depth",
+        company_id_bytes.as_ref(),
+        &[market_depth.bump],
+    ];
+    let market_depth_info = ctx.accounts.market_depth.to_account_info();
+    prepare_market_depth_account(
+        &market_depth_info,
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.system_program.to_account_info(),
+        &[&seeds[..]],
+    )?;
+    market_depth.try_serialize(&mut &mut market_depth_info.try_borrow_mut_data()?[..])?;
+
+    emit!(MarketDepthCalculated {
+        company_id,
+        orderbook: orderbook.key(),
+        total_buy_volume: market_depth.total_buy_volume,
+        total_sell_volume: market_depth.total_sell_volume,
+        timestamp: now,
+    });
+
+    Ok(())
+}
+
+// Creates the depth account, or grows one sized for fewer levels, paying the
+// rent from the user
+fn prepare_market_depth_account<'info>(
+    market_depth_info: &AccountInfo<'info>,
+    user: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    signer: &[&[&[u8]]],
+) -> Result<()> {
+    let is_new = market_depth_info.data_is_empty();
+    if !is_new {
+        require_keys_eq!(*market_depth_info.owner, crate::ID, DefiTradingError::Unauthorized);
+        if market_depth_info.data_len() >= MarketDepth::LEN {
+            return Ok(());
+        }
+    }
+
+    let rent = Rent::get()?.minimum_balance(MarketDepth::LEN);
+    let top_up = rent.saturating_sub(market_depth_info.lamports());
+    if top_up > 0 {
+        let cpi_accounts = system_program::Transfer {
+            from: user,
+            to: market_depth_info.clone(),
+        };
+        let cpi_ctx = CpiContext::new(system_program.clone(), cpi_accounts);
+        system_program::transfer(cpi_ctx, top_up)?;
+    }
+
+    if is_new {
+        let cpi_accounts = system_program::Allocate {
+            account_to_allocate: market_depth_info.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(system_program.clone(), cpi_accounts, signer);
+        system_program::allocate(cpi_ctx, MarketDepth::LEN as u64)?;
+
+        let cpi_accounts = system_program::Assign {
+            account_to_assign: market_depth_info.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(system_program, cpi_accounts, signer);
+        system_program::assign(cpi_ctx, &crate::ID)?;
+    } else {
+        market_depth_info.resize(MarketDepth::LEN)?;
+    }
+    Ok(())
+}
+
+// Context structs
+#[derive(Accounts)]
+#[instruction(order_type: OrderType)]
+pub struct CreateLimitOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = EnhancedOrder::LEN,
+        seeds = [
+            b"enhanced_order",
+            orderbook.company_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            orderbook.last_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order: Account<'info, EnhancedOrder>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = escrow_mint,
+        token::authority = order,
+        seeds = [
+            b"enhanced_order_escrow",
+            orderbook.company_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            orderbook.last_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_escrow_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == orderbook.base_mint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    // Omit to pay in native SOL
+    #[account(
+        mut,
+        constraint = user_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    // Base mint for asks, quote mint for bids
+    #[account(address = orderbook.escrow_mint(&order_type) @ DefiTradingError::TokenAccountMismatch)]
+    pub escrow_mint: Account<'info, Mint>,
+    
+    // Required when the order takes liquidity from the book
+    #[account(
+        mut,
+        constraint = platform_fee_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub platform_fee_account: Option<Account<'info, TokenAccount>>,
+    
+    // Set to trade for a trading account the user is linked to
+    pub trading_account: Option<Account<'info, TradingAccount>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(order_type: OrderType)]
+pub struct CreateMarketOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = EnhancedOrder::LEN,
+        seeds = [
+            b"enhanced_order",
+            orderbook.company_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            orderbook.last_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order: Account<'info, EnhancedOrder>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == orderbook.base_mint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    // Omit to pay in native SOL
+    #[account(
+        mut,
+        constraint = user_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        constraint = platform_fee_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub platform_fee_account: Account<'info, TokenAccount>,
+    
+    // Set to trade for a trading account the user is linked to
+    pub trading_account: Option<Account<'info, TradingAccount>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MatchOrders<'info> {
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order",
+            orderbook.company_id.to_le_bytes().as_ref(),
+            buy_order.user.as_ref(),
+            buy_order.id.to_le_bytes().as_ref()
+        ],
+        bump = buy_order.bump
+    )]
+    pub buy_order: Account<'info, EnhancedOrder>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order",
+            orderbook.company_id.to_le_bytes().as_ref(),
+            sell_order.user.as_ref(),
+            sell_order.id.to_le_bytes().as_ref()
+        ],
+        bump = sell_order.bump
+    )]
     pub sell_order: Account<'info, EnhancedOrder>,
     
     #[account(
-        init,
-        payer = authority,
-        space = Trade::LEN,
+        mut,
+        seeds = [b"order_book", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = Trade::LEN,
+        seeds = [
+            b"trade",
+            (platform.total_trades + 1).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub trade: Account<'info, Trade>,
+    
+    #[account(
+        mut,
+        constraint = buy_order_escrow.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub buy_order_escrow: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = sell_order_escrow.mint == orderbook.base_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub sell_order_escrow: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = buyer_token_account.mint == orderbook.base_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub buyer_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = seller_token_account.mint == orderbook.base_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub seller_token_account: Account<'info, TokenAccount>,
+    
+    // Omit for market buys paid in native SOL; a limit buy's price
+    // improvement is then refunded through the temporary wSOL account
+    #[account(
+        mut,
+        constraint = buyer_payment_account.owner == buyer.key() @ DefiTradingError::TokenAccountMismatch,
+        constraint = buyer_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub buyer_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        constraint = seller_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub seller_payment_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = platform_fee_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub platform_fee_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = buyer,
+        token::mint = native_mint,
+        token::authority = buyer,
+        seeds = [TEMP_WSOL_SEED, buyer.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeOrderBook<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = OrderBookSlab::LEN,
+        seeds = [b"order_book", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureMarket<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureCircuitBreaker<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelExpiredOrder<'info> {
+    #[account(
+        mut,
         seeds = [
-            b"trade",
-            (platform.total_trades + 1).to_le_bytes().as_ref()
+            b"enhanced_order",
+            order.company_id.to_le_bytes().as_ref(),
+            order.user.as_ref(),
+            order.id.to_le_bytes().as_ref()
         ],
+        bump = order.bump
+    )]
+    pub order: Account<'info, EnhancedOrder>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", order.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", order.company_id.to_le_bytes().as_ref()],
         bump
     )]
-    pub trade: Account<'info, Trade>,
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
     
-    #[account(mut)]
-    pub buy_order_escrow: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order_escrow",
+            order.company_id.to_le_bytes().as_ref(),
+            order.user.as_ref(),
+            order.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_escrow_account: Account<'info, TokenAccount>,
     
-    #[account(mut)]
-    pub sell_order_escrow: Account<'info, TokenAccount>,
+    // Omit to refund a wrapped SOL escrow as native SOL
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == order.user @ DefiTradingError::TokenAccountMismatch,
+        constraint = owner_token_account.mint == order_escrow_account.mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub owner_token_account: Option<Account<'info, TokenAccount>>,
     
-    #[account(mut)]
-    pub buyer_token_account: Account<'info, TokenAccount>,
+    /// CHECK: Order owner, only receives lamports of a closed wrapped SOL escrow
+    #[account(
+        mut,
+        address = order.user
+    )]
+    pub owner: UncheckedAccount<'info>,
     
-    #[account(mut)]
-    pub seller_token_account: Account<'info, TokenAccount>,
+    pub caller: Signer<'info>,
     
-    #[account(mut)]
-    pub buyer_payment_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelEnhancedOrder<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order",
+            order.company_id.to_le_bytes().as_ref(),
+            order.user.as_ref(),
+            order.id.to_le_bytes().as_ref()
+        ],
+        bump = order.bump
+    )]
+    pub order: Account<'info, EnhancedOrder>,
     
-    #[account(mut)]
-    pub seller_payment_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"orderbook", order.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
     
-    #[account(mut)]
-    pub platform_fee_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"order_book", order.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order_escrow",
+            order.company_id.to_le_bytes().as_ref(),
+            order.user.as_ref(),
+            order.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_escrow_account: Account<'info, TokenAccount>,
+    
+    // Omit to refund a wrapped SOL escrow as native SOL
+    #[account(
+        mut,
+        constraint = user_account.mint == order_escrow_account.mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_account: Option<Account<'info, TokenAccount>>,
     
     #[account(mut)]
-    pub buyer: Signer<'info>,
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAllOrders<'info> {
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    // Receives sell escrows
+    #[account(
+        mut,
+        constraint = user_token_account.mint == orderbook.base_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_token_account: Option<Account<'info, TokenAccount>>,
+    
+    // Receives buy escrows; omit to unwrap wrapped SOL escrows to the wallet
+    #[account(
+        mut,
+        constraint = user_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_payment_account: Option<Account<'info, TokenAccount>>,
     
     #[account(mut)]
-    pub seller: Signer<'info>,
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AmendEnhancedOrder<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order",
+            order.company_id.to_le_bytes().as_ref(),
+            order.user.as_ref(),
+            order.id.to_le_bytes().as_ref()
+        ],
+        bump = order.bump
+    )]
+    pub order: Account<'info, EnhancedOrder>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", order.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", order.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order_escrow",
+            order.company_id.to_le_bytes().as_ref(),
+            order.user.as_ref(),
+            order.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_escrow_account: Account<'info, TokenAccount>,
+    
+    // Account in the escrow's mint that tops up or receives the difference.
+    // Omit to settle a wrapped SOL escrow in native SOL; refunds then also
+    // need the temporary wSOL account.
+    #[account(
+        mut,
+        constraint = user_account.mint == order_escrow_account.mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = native_mint,
+        token::authority = user,
+        seeds = [TEMP_WSOL_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
     
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub user: Signer<'info>,
     
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
@@ -603,23 +1968,113 @@ pub struct CalculateMarketDepth<'info> {
         seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
         bump = orderbook.bump
     )]
-    pub orderbook: Account<'info, Orderbook>,
+    pub orderbook: Account<'info, Market>,
     
     #[account(
-        init,
-        payer = user,
-        space = MarketDepth::LEN,
+        seeds = [b"order_book", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    /// CHECK: Created, grown and rewritten in calculate_market_depth
+    #[account(
+        mut,
         seeds = [
             b"market_depth",
             orderbook.company_id.to_le_bytes().as_ref()
         ],
         bump
     )]
-    pub market_depth: Account<'info, MarketDepth>,
+    pub market_depth: UncheckedAccount<'info>,
     
     #[account(mut)]
     pub user: Signer<'info>,
     
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateEnhancedOrder<'info> {
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"enhanced_order",
+            legacy_order.user.as_ref(),
+            legacy_order.id.to_le_bytes().as_ref()
+        ],
+        bump = legacy_order.bump
+    )]
+    pub legacy_order: Account<'info, LegacyEnhancedOrder>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"enhanced_order_escrow",
+            legacy_order.user.as_ref(),
+            legacy_order.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub legacy_escrow_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", legacy_order.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", legacy_order.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = EnhancedOrder::LEN,
+        seeds = [
+            b"enhanced_order",
+            legacy_order.company_id.to_le_bytes().as_ref(),
+            legacy_order.user.as_ref(),
+            orderbook.last_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order: Account<'info, EnhancedOrder>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = token_mint,
+        token::authority = order,
+        seeds = [
+            b"enhanced_order_escrow",
+            legacy_order.company_id.to_le_bytes().as_ref(),
+            legacy_order.user.as_ref(),
+            orderbook.last_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_escrow_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        address = legacy_escrow_account.mint,
+        constraint = token_mint.key() == orderbook.escrow_mint(&legacy_order.order_type) @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        constraint = user.key() == legacy_order.user @ DefiTradingError::Unauthorized
+    )]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
     pub rent: Sysvar<'info, Rent>,
 }
\ No newline at end of file
@@ -1,8 +1,9 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_spl::token::{self, spl_token::native_mint, Token, TokenAccount, Mint, Transfer};
 use crate::state::*;
 use crate::errors::*;
 use crate::events::*;
+use crate::native_sol::*;
 
 pub fn create_escrow(
     ctx: Context<CreateEscrow>,
@@ -10,12 +11,15 @@ pub fn create_escrow(
     trade_id: u64,
 ) -> Result<()> {
     let escrow = &mut ctx.accounts.escrow;
-    let platform = &ctx.accounts.platform;
+    let platform = &mut ctx.accounts.platform;
     
     require!(!platform.is_paused, DefiTradingError::PlatformPaused);
     require!(amount > 0, DefiTradingError::InvalidTradeAmount);
     
-    let escrow_id = platform.total_trades + 1; // Using total_trades as counter
+    let escrow_id = platform.last_escrow_id
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    platform.last_escrow_id = escrow_id;
     
     escrow.id = escrow_id;
     escrow.trade_id = trade_id;
@@ -28,16 +32,17 @@ pub fn create_escrow(
     escrow.released_at = None;
     escrow.bump = ctx.bumps.escrow;
 
-    // Transfer tokens to escrow account
-    let cpi_accounts = Transfer {
-        from: ctx.accounts.payer_token_account.to_account_info(),
-        to: ctx.accounts.escrow_token_account.to_account_info(),
-        authority: ctx.accounts.payer.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-    
-    token::transfer(cpi_ctx, amount)?;
+    // Transfer tokens to escrow account, wrapping native SOL if no payer
+    // token account was given
+    collect_payment(
+        ctx.accounts.payer.to_account_info(),
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.escrow_token_account.to_account_info(),
+        ctx.accounts.escrow_token_account.mint,
+        ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.system_program.to_account_info(),
+        amount,
+    )?;
 
     emit!(EscrowCreated {
         escrow_id,
@@ -121,7 +126,8 @@ pub fn cancel_escrow(ctx: Context<CancelEscrow>) -> Result<()> {
     let escrow_amount = escrow.amount;
     let escrow_bump = escrow.bump;
 
-    // Return tokens to payer
+    // Return tokens to payer, as native SOL for wrapped SOL escrows without
+    // a payer token account
     let escrow_id_bytes = escrow_id.to_le_bytes();
     let seeds = &[
         b"escrow",
@@ -130,15 +136,16 @@ pub fn cancel_escrow(ctx: Context<CancelEscrow>) -> Result<()> {
     ];
     let signer = &[&seeds[..]];
     
-    let cpi_accounts = Transfer {
-        from: ctx.accounts.escrow_token_account.to_account_info(),
-        to: ctx.accounts.payer_token_account.to_account_info(),
-        authority: escrow_info,
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-    
-    token::transfer(cpi_ctx, escrow_amount)?;
+    return_payment(
+        &ctx.accounts.escrow_token_account,
+        escrow_info,
+        signer,
+        ctx.accounts.authority.to_account_info(),
+        ctx.accounts.payer_token_account.as_ref(),
+        ctx.accounts.temp_wsol_account.as_ref(),
+        ctx.accounts.token_program.to_account_info(),
+        escrow_amount,
+    )?;
 
     escrow.status = EscrowStatus::Cancelled;
     escrow.released_at = Some(Clock::get()?.unix_timestamp);
@@ -150,6 +157,7 @@ pub fn cancel_escrow(ctx: Context<CancelEscrow>) -> Result<()> {
 #[instruction(amount: u64, trade_id: u64)]
 pub struct CreateEscrow<'info> {
     #[account(
+        mut,
         seeds = [b"platform"],
         bump = platform.bump
     )]
@@ -161,7 +169,7 @@ pub struct CreateEscrow<'info> {
         space = Escrow::LEN,
         seeds = [
             b"escrow",
-            (platform.total_trades + 1).to_le_bytes().as_ref()
+            platform.last_escrow_id.checked_add(1).unwrap().to_le_bytes().as_ref()
         ],
         bump
     )]
@@ -174,17 +182,18 @@ pub struct CreateEscrow<'info> {
         token::authority = escrow,
         seeds = [
             b"escrow_tokens",
-            (platform.total_trades + 1).to_le_bytes().as_ref()
+            platform.last_escrow_id.checked_add(1).unwrap().to_le_bytes().as_ref()
         ],
         bump
     )]
     pub escrow_token_account: Account<'info, TokenAccount>,
     
+    // Omit to fund a wrapped SOL escrow with native SOL
     #[account(
         mut,
         constraint = payer_token_account.mint == token_mint.key()
     )]
-    pub payer_token_account: Account<'info, TokenAccount>,
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
     
     pub token_mint: Account<'info, anchor_spl::token::Mint>,
     
@@ -242,13 +251,31 @@ pub struct CancelEscrow<'info> {
     )]
     pub escrow_token_account: Account<'info, TokenAccount>,
     
+    // Omit, together with passing the temporary wSOL account, to have a
+    // wrapped SOL escrow returned as native SOL
     #[account(
         mut,
         constraint = payer_token_account.mint == escrow.token_mint
     )]
-    pub payer_token_account: Account<'info, TokenAccount>,
+    pub payer_token_account: Option<Account<'info, TokenAccount>>,
     
+    #[account(
+        init,
+        payer = authority,
+        token::mint = native_mint,
+        token::authority = authority,
+        seeds = [TEMP_WSOL_SEED, authority.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    
+    #[account(mut)]
     pub authority: Signer<'info>,
     
     pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
 }
\ No newline at end of file
@@ -0,0 +1,227 @@
+use std::cell::RefMut;
+use anchor_lang::prelude::*;
+use anchor_spl::token::Mint;
+use crate::state::*;
+use crate::errors::*;
+use crate::events::*;
+use crate::pricing::*;
+use super::platform::grow_legacy_account;
+
+// A company's market is its Market account together with the order book
+// slab. All new orders go through the market instructions; the legacy order
+// instructions only drain orders that already exist.
+
+// Opens the market of a verified company. Companies launched on a bonding
+// curve get their market when the curve graduates instead.
+pub fn create_market(ctx: Context<CreateMarket>, market_config: Option<MarketConfig>) -> Result<()> {
+    let platform = &ctx.accounts.platform;
+    let company = &ctx.accounts.company;
+    let authority = ctx.accounts.authority.key();
+    
+    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(company.verified, DefiTradingError::CompanyNotVerified);
+    require!(
+        ctx.accounts.bonding_curve.data_is_empty(),
+        DefiTradingError::BondingCurveActive
+    );
+    require!(
+        authority == company.authority || authority == platform.authority,
+        DefiTradingError::Unauthorized
+    );
+    
+    open_market(
+        &mut ctx.accounts.orderbook,
+        &ctx.accounts.order_book,
+        company.id,
+        &ctx.accounts.token_mint,
+        ctx.accounts.quote_mint.key(),
+        authority,
+        0,
+        market_config.unwrap_or(MarketConfig::DEFAULT),
+        ctx.bumps.orderbook,
+        Clock::get()?.unix_timestamp,
+    )?;
+    
+    Ok(())
+}
+
+// Sets up an empty market: its Market account and its order book slab.
+// Every instruction that creates a market goes through here, and gets the
+// new book back to seed it with orders.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn open_market<'a, 'info>(
+    orderbook: &mut Account<'info, Market>,
+    order_book: &'a AccountLoader<'info, OrderBookSlab>,
+    company_id: u64,
+    token_mint: &Account<Mint>,
+    quote_mint: Pubkey,
+    authority: Pubkey,
+    last_trade_price: u64,
+    market_config: MarketConfig,
+    bump: u8,
+    now: i64,
+) -> Result<RefMut<'a, OrderBookSlab>> {
+    market_config.validate()?;
+    
+    orderbook.company_id = company_id;
+    orderbook.base_mint = token_mint.key();
+    orderbook.quote_mint = quote_mint;
+    orderbook.liquidity_locked_until = 0;
+    orderbook.base_decimals = token_mint.decimals;
+    orderbook.total_buy_orders = 0;
+    orderbook.total_sell_orders = 0;
+    orderbook.last_order_id = 0;
+    orderbook.best_bid = 0;
+    orderbook.best_ask = 0;
+    orderbook.last_trade_price = last_trade_price;
+    orderbook.total_volume = 0;
+    orderbook.created_at = now;
+    orderbook.last_updated = now;
+    orderbook.price_feed = Pubkey::default();
+    orderbook.last_trigger_order_id = 0;
+    orderbook.market_config = market_config;
+    orderbook.circuit_breaker = CircuitBreaker::DISABLED;
+    orderbook.bump = bump;
+    
+    let mut book = order_book.load_init()?;
+    book.orderbook = orderbook.key();
+    
+    emit!(MarketCreated {
+        company_id,
+        orderbook: orderbook.key(),
+        order_book: order_book.key(),
+        base_mint: token_mint.key(),
+        quote_mint,
+        authority,
+        timestamp: now,
+    });
+    
+    Ok(book)
+}
+
+// Grows a market opened before base_decimals and the fields after it.
+// Legacy prices were per base atom, so the market keeps that price scale,
+// and it starts with an empty book that initialize_order_book creates.
+pub fn migrate_orderbook(ctx: Context<MigrateOrderbook>, _company_id: u64) -> Result<()> {
+    let orderbook_info = ctx.accounts.orderbook.to_account_info();
+    
+    require!(
+        ctx.accounts.authority.key() == ctx.accounts.platform.authority,
+        DefiTradingError::Unauthorized
+    );
+    grow_legacy_account(
+        &orderbook_info,
+        &ctx.accounts.authority.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        Market::LEGACY_LEN,
+        Market::LEN,
+    )?;
+    
+    // The new fields read as zero until they are set below
+    let mut orderbook = Market::try_deserialize(&mut &orderbook_info.try_borrow_data()?[..])?;
+    let now = Clock::get()?.unix_timestamp;
+    
+    // Legacy counters were never decremented and legacy best prices were
+    // never maintained; both are recomputed once orders rest in the book
+    orderbook.total_buy_orders = 0;
+    orderbook.total_sell_orders = 0;
+    orderbook.best_bid = 0;
+    orderbook.best_ask = 0;
+    orderbook.last_updated = now;
+    orderbook.base_decimals = LEGACY_PRICE_DECIMALS;
+    orderbook.last_order_id = 0;
+    orderbook.price_feed = Pubkey::default();
+    orderbook.last_trigger_order_id = 0;
+    orderbook.market_config = MarketConfig::DEFAULT;
+    orderbook.circuit_breaker = CircuitBreaker::DISABLED;
+    orderbook.quote_mint = ctx.accounts.quote_mint.key();
+    orderbook.liquidity_locked_until = 0;
+    orderbook.try_serialize(&mut &mut orderbook_info.try_borrow_mut_data()?[..])?;
+    
+    emit!(OrderbookMigrated {
+        company_id: orderbook.company_id,
+        orderbook: orderbook_info.key(),
+        base_decimals: orderbook.base_decimals,
+        quote_mint: orderbook.quote_mint,
+        timestamp: now,
+    });
+    
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CreateMarket<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        seeds = [b"company", company.id.to_le_bytes().as_ref()],
+        bump = company.bump
+    )]
+    pub company: Account<'info, Company>,
+    
+    /// CHECK: Only checked to be uninitialized; curve companies get their market at graduation
+    #[account(
+        seeds = [b"bonding_curve", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub bonding_curve: UncheckedAccount<'info>,
+    
+    #[account(address = company.token_mint)]
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(constraint = quote_mint.key() != token_mint.key() @ DefiTradingError::InvalidTokenMint)]
+    pub quote_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = Market::LEN,
+        seeds = [b"orderbook", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = OrderBookSlab::LEN,
+        seeds = [b"order_book", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(company_id: u64)]
+pub struct MigrateOrderbook<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    /// CHECK: Old market layout, checked and rewritten in migrate_orderbook
+    #[account(
+        mut,
+        seeds = [b"orderbook", company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub orderbook: UncheckedAccount<'info>,
+    
+    // The mint legacy orders of this market were paid in
+    pub quote_mint: Account<'info, Mint>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
@@ -0,0 +1,836 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, TokenAccount, Transfer};
+use crate::state::*;
+use crate::errors::*;
+use crate::events::*;
+use crate::pricing::*;
+use crate::native_sol::*;
+use super::enhanced_trading::{release_book_order, release_order};
+
+// Taker matching against the resting side of the order book. Makers are
+// passed as remaining accounts, three per fill and in book order:
+//   [maker order, maker escrow, maker destination]
+// where the destination receives payment when the maker is selling and
+// company tokens when the maker is buying. When the maker belongs to the
+// taker's own owner or trading account the destination instead receives
+// whatever self-trade prevention refunds from the maker's escrow.
+//
+// Makers resting through an open orders account pass
+//   [open orders, market base vault, market quote vault]
+// instead. Their side of a fill never leaves the market vaults: locked
+// funds are spent and the proceeds are credited to their free balance.
+
+pub const MAKER_ACCOUNTS_PER_FILL: usize = 3;
+
+// Taker side of a match: the order owner and the accounts it settles from.
+// `authority` moves the taker's funds; it is the signing user, or a program
+// account signing with `signer_seeds` when funds are already escrowed.
+// `orderbook` is the Market account, which owns the vaults.
+pub struct TakerAccounts<'a, 'info> {
+    pub user: AccountInfo<'info>,
+    pub authority: AccountInfo<'info>,
+    pub signer_seeds: &'a [&'a [&'a [u8]]],
+    pub orderbook: AccountInfo<'info>,
+    pub self_trade_key: Pubkey,
+    pub self_trade_prevention: SelfTradePrevention,
+    pub user_token_account: &'a Account<'info, TokenAccount>,
+    pub user_payment_account: Option<&'a Account<'info, TokenAccount>>,
+    pub platform_fee_account: Option<&'a Account<'info, TokenAccount>>,
+    pub token_program: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+}
+
+#[derive(Default)]
+pub struct MatchSummary {
+    pub filled_amount: u64,
+    pub quote_amount: u64, // total value of all fills before fees
+    pub platform_fees: u64,
+    pub fills: u8,
+    pub cancelled_amount: u64, // taker size removed by self-trade prevention
+    pub taker_cancelled: bool, // the rest of the taker must not rest
+    pub band_breached: bool,   // matching stopped at a price outside the band
+}
+
+impl MatchSummary {
+    fn record_fill(&mut self, amount: u64, value: u64, platform_fee: u64) -> Result<()> {
+        self.filled_amount += amount;
+        self.quote_amount = self.quote_amount
+            .checked_add(value)
+            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        self.platform_fees = self.platform_fees
+            .checked_add(platform_fee)
+            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        self.fills += 1;
+        Ok(())
+    }
+
+    fn record_self_trade(&mut self, taker_cancelled_amount: u64, mode: &SelfTradePrevention) {
+        self.cancelled_amount += taker_cancelled_amount;
+        self.taker_cancelled = matches!(
+            mode,
+            SelfTradePrevention::CancelTaker | SelfTradePrevention::CancelBoth
+        );
+    }
+}
+
+/// Fills `amount` against the opposite side of the book for as long as the
+/// best maker price is within `limit_price` and `max_fills` allows.
+#[allow(clippy::too_many_arguments)]
+pub fn match_against_book<'info>(
+    book: &mut OrderBookSlab,
+    orderbook: &mut Market,
+    platform: &mut Platform,
+    taker: &TakerAccounts<'_, 'info>,
+    maker_accounts: &[AccountInfo<'info>],
+    taker_side: &OrderType,
+    taker_order_id: u64,
+    limit_price: u64,
+    amount: u64,
+    max_fills: u8,
+) -> Result<MatchSummary> {
+    require!(
+        maker_accounts.len().is_multiple_of(MAKER_ACCOUNTS_PER_FILL),
+        DefiTradingError::TakerAccountsRequired
+    );
+    // Every fill settles in the market's own mints
+    require_keys_eq!(taker.user_token_account.mint, orderbook.base_mint, DefiTradingError::TokenAccountMismatch);
+    for account in [taker.user_payment_account, taker.platform_fee_account].into_iter().flatten() {
+        require_keys_eq!(account.mint, orderbook.quote_mint, DefiTradingError::TokenAccountMismatch);
+    }
+    let maker_side = match taker_side {
+        OrderType::Buy => OrderType::Sell,
+        OrderType::Sell => OrderType::Buy,
+    };
+
+    let mut summary = MatchSummary::default();
+    let mut makers = maker_accounts.chunks(MAKER_ACCOUNTS_PER_FILL);
+    let now = Clock::get()?.unix_timestamp;
+    orderbook.circuit_breaker.check_not_halted(now)?;
+    // Fixed for the whole walk so a sweep cannot drag the band along
+    let reference_price = orderbook.circuit_breaker.reference_price(orderbook.last_trade_price);
+
+    while summary.filled_amount + summary.cancelled_amount < amount
+        && summary.fills < max_fills
+        && !summary.taker_cancelled
+    {
+        // Expired makers are never filled
+        book.remove_expired_heads(&maker_side, now);
+        let head = match book.head(&maker_side) {
+            Some(node) => *node,
+            None => break,
+        };
+        let crosses = match taker_side {
+            OrderType::Buy => head.price <= limit_price,
+            OrderType::Sell => head.price >= limit_price,
+        };
+        if !crosses {
+            break;
+        }
+        // A fill outside the price band is rejected: matching stops, the
+        // rest of the taker is dropped and the breach counts towards a halt
+        if !orderbook.circuit_breaker.in_band(head.price, reference_price) {
+            record_band_breach(orderbook, head.price, reference_price, now)?;
+            summary.band_breached = true;
+            summary.taker_cancelled = true;
+            break;
+        }
+        let Some(maker) = makers.next() else {
+            break;
+        };
+
+        let taker_remaining = amount - summary.filled_amount - summary.cancelled_amount;
+        if head.open_orders != Pubkey::default() {
+            let mut open_orders = load_maker_open_orders(&maker[0], &head, orderbook.company_id)?;
+            if open_orders.self_trade_key() == taker.self_trade_key {
+                let taker_cancelled_amount = prevent_open_orders_self_trade(
+                    book,
+                    orderbook,
+                    taker,
+                    &mut open_orders,
+                    &maker[0],
+                    &head,
+                    taker_order_id,
+                    taker_remaining,
+                )?;
+                summary.record_self_trade(taker_cancelled_amount, &taker.self_trade_prevention);
+                continue;
+            }
+
+            let fill_amount = taker_remaining.min(head.quantity);
+            let (value, platform_fee) = fill_open_orders_maker(
+                platform,
+                orderbook,
+                taker,
+                &mut open_orders,
+                maker,
+                &head,
+                taker_side,
+                taker_order_id,
+                fill_amount,
+            )?;
+            book.reduce(&maker_side, head.order_id, fill_amount)?;
+            summary.record_fill(fill_amount, value, platform_fee)?;
+            continue;
+        }
+
+        let mut maker_order = load_maker_order(&maker[0], &head, orderbook.company_id)?;
+        if maker_order.self_trade_key() == taker.self_trade_key {
+            let taker_cancelled_amount = prevent_self_trade(
+                book,
+                orderbook,
+                taker,
+                &mut maker_order,
+                maker,
+                &head,
+                taker_order_id,
+                taker_remaining,
+            )?;
+            summary.record_self_trade(taker_cancelled_amount, &taker.self_trade_prevention);
+            continue;
+        }
+
+        let fill_amount = taker_remaining.min(head.quantity);
+        let (value, platform_fee) = fill_maker(
+            platform,
+            orderbook,
+            taker,
+            &mut maker_order,
+            &maker[0],
+            &maker[1],
+            &maker[2],
+            &head,
+            taker_side,
+            taker_order_id,
+            fill_amount,
+        )?;
+        book.reduce(&maker_side, head.order_id, fill_amount)?;
+        summary.record_fill(fill_amount, value, platform_fee)?;
+    }
+
+    Ok(summary)
+}
+
+// Settles one fill at the maker's price, updates the maker order and
+// returns the fill's quote value and the platform fee charged
+#[allow(clippy::too_many_arguments)]
+fn fill_maker<'info>(
+    platform: &mut Platform,
+    orderbook: &mut Market,
+    taker: &TakerAccounts<'_, 'info>,
+    maker_order: &mut EnhancedOrder,
+    maker_order_info: &AccountInfo<'info>,
+    maker_escrow_info: &AccountInfo<'info>,
+    maker_destination_info: &AccountInfo<'info>,
+    head: &BookNode,
+    taker_side: &OrderType,
+    taker_order_id: u64,
+    amount: u64,
+) -> Result<(u64, u64)> {
+    let maker_company_bytes = maker_order.company_id.to_le_bytes();
+    let maker_id_bytes = maker_order.id.to_le_bytes();
+    check_maker_escrow(maker_order, maker_escrow_info)?;
+    let maker_destination = load_maker_destination(maker_order, maker_destination_info)?;
+
+    let platform_fee_account = taker.platform_fee_account
+        .ok_or(DefiTradingError::TakerAccountsRequired)?;
+    // A buying taker pays the fill rounded up. A buying maker pays exactly
+    // the escrow the fill releases, so its escrow keeps matching what the
+    // rest of the order needs.
+    let total_value = match taker_side {
+        OrderType::Buy => taker_buy_cost(amount, head.price, orderbook.base_decimals)?,
+        OrderType::Sell => {
+            let total_value = buy_escrow_release(maker_order.remaining_amount, amount, head.price, orderbook.base_decimals)?;
+            // Fails if the escrow could not cover the rest of the order
+            let maker_escrow = TokenAccount::try_deserialize(&mut &maker_escrow_info.try_borrow_data()?[..])?;
+            buy_escrow_refund(
+                maker_escrow.amount,
+                total_value,
+                maker_order.remaining_amount - amount,
+                head.price,
+                orderbook.base_decimals,
+            )?;
+            total_value
+        }
+    };
+    let platform_fee = fee_amount(total_value, platform.platform_fee)?;
+    let seller_proceeds = total_value
+        .checked_sub(platform_fee)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+
+    let maker_seeds = &[
+        b"enhanced_order",
+        maker_company_bytes.as_ref(),
+        maker_order.user.as_ref(),
+        maker_id_bytes.as_ref(),
+        &[maker_order.bump],
+    ];
+    let maker_signer = &[&maker_seeds[..]];
+
+    match taker_side {
+        OrderType::Buy => {
+            require_keys_eq!(
+                maker_destination.mint,
+                orderbook.quote_mint,
+                DefiTradingError::TokenAccountMismatch
+            );
+
+            // Taker pays the selling maker and the platform
+            pay_from_taker(taker, maker_destination_info.clone(), maker_destination.mint, seller_proceeds)?;
+            pay_from_taker(
+                taker,
+                platform_fee_account.to_account_info(),
+                platform_fee_account.mint,
+                platform_fee,
+            )?;
+
+            // Maker's escrowed tokens go to the taker
+            let cpi_accounts = Transfer {
+                from: maker_escrow_info.clone(),
+                to: taker.user_token_account.to_account_info(),
+                authority: maker_order_info.clone(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(taker.token_program.clone(), cpi_accounts, maker_signer);
+            token::transfer(cpi_ctx, amount)?;
+        },
+        OrderType::Sell => {
+            require_keys_eq!(
+                maker_destination.mint,
+                orderbook.base_mint,
+                DefiTradingError::TokenAccountMismatch
+            );
+            let user_payment_account = taker.user_payment_account
+                .ok_or(DefiTradingError::TakerAccountsRequired)?;
+
+            // Taker delivers tokens to the buying maker
+            let cpi_accounts = Transfer {
+                from: taker.user_token_account.to_account_info(),
+                to: maker_destination_info.clone(),
+                authority: taker.authority.clone(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(taker.token_program.clone(), cpi_accounts, taker.signer_seeds);
+            token::transfer(cpi_ctx, amount)?;
+
+            // Maker's escrowed payment goes to the taker and the platform
+            let cpi_accounts = Transfer {
+                from: maker_escrow_info.clone(),
+                to: user_payment_account.to_account_info(),
+                authority: maker_order_info.clone(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(taker.token_program.clone(), cpi_accounts, maker_signer);
+            token::transfer(cpi_ctx, seller_proceeds)?;
+
+            let cpi_accounts = Transfer {
+                from: maker_escrow_info.clone(),
+                to: platform_fee_account.to_account_info(),
+                authority: maker_order_info.clone(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(taker.token_program.clone(), cpi_accounts, maker_signer);
+            token::transfer(cpi_ctx, platform_fee)?;
+        }
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    maker_order.remaining_amount = maker_order.remaining_amount
+        .checked_sub(amount)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+    if maker_order.remaining_amount == 0 {
+        maker_order.status = OrderStatus::Filled;
+        maker_order.filled_at = Some(now);
+    } else {
+        maker_order.status = OrderStatus::PartiallyFilled;
+    }
+    maker_order.try_serialize(&mut &mut maker_order_info.try_borrow_mut_data()?[..])?;
+
+    record_trade(
+        platform,
+        orderbook,
+        taker,
+        maker_order.user,
+        maker_order.id,
+        head,
+        taker_side,
+        taker_order_id,
+        amount,
+        total_value,
+        platform_fee,
+    )?;
+
+    Ok((total_value, platform_fee))
+}
+
+// Settles one fill against a maker resting through an open orders account
+// and returns the fill's quote value and the platform fee charged
+#[allow(clippy::too_many_arguments)]
+fn fill_open_orders_maker<'info>(
+    platform: &mut Platform,
+    orderbook: &mut Market,
+    taker: &TakerAccounts<'_, 'info>,
+    open_orders: &mut OpenOrders,
+    maker: &[AccountInfo<'info>],
+    head: &BookNode,
+    taker_side: &OrderType,
+    taker_order_id: u64,
+    amount: u64,
+) -> Result<(u64, u64)> {
+    let (open_orders_info, base_vault_info, quote_vault_info) = (&maker[0], &maker[1], &maker[2]);
+    check_market_vault(base_vault_info, orderbook.company_id, &orderbook.base_mint)?;
+    check_market_vault(quote_vault_info, orderbook.company_id, &orderbook.quote_mint)?;
+    let index = open_orders.find_order(head.order_id).ok_or(DefiTradingError::OrderNotFound)?;
+
+    let platform_fee_account = taker.platform_fee_account
+        .ok_or(DefiTradingError::TakerAccountsRequired)?;
+    // A buying taker pays the fill rounded up; a buying maker pays exactly
+    // the quote the fill unlocks, as with escrows
+    let total_value = match taker_side {
+        OrderType::Buy => taker_buy_cost(amount, head.price, orderbook.base_decimals)?,
+        OrderType::Sell => buy_escrow_release(
+            open_orders.orders[index].remaining_amount,
+            amount,
+            head.price,
+            orderbook.base_decimals,
+        )?,
+    };
+    let platform_fee = fee_amount(total_value, platform.platform_fee)?;
+    let seller_proceeds = total_value
+        .checked_sub(platform_fee)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+
+    let company_id_bytes = orderbook.company_id.to_le_bytes();
+    let vault_seeds = &[b"orderbook".as_ref(), company_id_bytes.as_ref(), &[orderbook.bump]];
+    let vault_signer = &[&vault_seeds[..]];
+
+    match taker_side {
+        OrderType::Buy => {
+            // Taker pays into the quote vault and the platform, and receives
+            // the maker's locked tokens
+            pay_from_taker(taker, quote_vault_info.clone(), orderbook.quote_mint, seller_proceeds)?;
+            pay_from_taker(
+                taker,
+                platform_fee_account.to_account_info(),
+                platform_fee_account.mint,
+                platform_fee,
+            )?;
+            move_tokens(
+                base_vault_info.clone(),
+                taker.user_token_account.to_account_info(),
+                taker.orderbook.clone(),
+                vault_signer,
+                taker.token_program.clone(),
+                amount,
+            )?;
+            open_orders.fill(index, amount, seller_proceeds, orderbook.base_decimals)?;
+        },
+        OrderType::Sell => {
+            let user_payment_account = taker.user_payment_account
+                .ok_or(DefiTradingError::TakerAccountsRequired)?;
+
+            // Taker delivers tokens into the base vault, and the maker's
+            // locked quote pays the taker and the platform
+            move_tokens(
+                taker.user_token_account.to_account_info(),
+                base_vault_info.clone(),
+                taker.authority.clone(),
+                taker.signer_seeds,
+                taker.token_program.clone(),
+                amount,
+            )?;
+            move_tokens(
+                quote_vault_info.clone(),
+                user_payment_account.to_account_info(),
+                taker.orderbook.clone(),
+                vault_signer,
+                taker.token_program.clone(),
+                seller_proceeds,
+            )?;
+            move_tokens(
+                quote_vault_info.clone(),
+                platform_fee_account.to_account_info(),
+                taker.orderbook.clone(),
+                vault_signer,
+                taker.token_program.clone(),
+                platform_fee,
+            )?;
+            open_orders.fill(index, amount, amount, orderbook.base_decimals)?;
+        }
+    }
+    open_orders.try_serialize(&mut &mut open_orders_info.try_borrow_mut_data()?[..])?;
+
+    record_trade(
+        platform,
+        orderbook,
+        taker,
+        open_orders.owner,
+        head.order_id,
+        head,
+        taker_side,
+        taker_order_id,
+        amount,
+        total_value,
+        platform_fee,
+    )?;
+
+    Ok((total_value, platform_fee))
+}
+
+// Counts a fill rejected by the price band, halting the market once the
+// breaches within the window reach the limit
+fn record_band_breach(orderbook: &mut Market, price: u64, reference_price: u64, now: i64) -> Result<()> {
+    let tripped = orderbook.circuit_breaker.record_breach(now)?;
+    emit!(PriceBandBreached {
+        company_id: orderbook.company_id,
+        price,
+        reference_price,
+        band_bps: orderbook.circuit_breaker.config.band_bps,
+        breaches: if tripped {
+            orderbook.circuit_breaker.config.max_breaches
+        } else {
+            orderbook.circuit_breaker.breaches
+        },
+        timestamp: now,
+    });
+    if tripped {
+        emit!(CircuitBreakerTripped {
+            company_id: orderbook.company_id,
+            price,
+            reference_price,
+            halted_until: orderbook.circuit_breaker.halted_until,
+            timestamp: now,
+        });
+    }
+    Ok(())
+}
+
+// Updates market and platform statistics for one fill and emits it
+#[allow(clippy::too_many_arguments)]
+fn record_trade(
+    platform: &mut Platform,
+    orderbook: &mut Market,
+    taker: &TakerAccounts,
+    maker_user: Pubkey,
+    maker_order_id: u64,
+    head: &BookNode,
+    taker_side: &OrderType,
+    taker_order_id: u64,
+    amount: u64,
+    total_value: u64,
+    platform_fee: u64,
+) -> Result<()> {
+    orderbook.last_trade_price = head.price;
+    orderbook.circuit_breaker.record_fill(head.price);
+    orderbook.total_volume = orderbook.total_volume
+        .checked_add(amount)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let trade_id = platform.total_trades
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    platform.total_trades = trade_id;
+
+    let (buyer, seller, buy_order_id, sell_order_id) = match taker_side {
+        OrderType::Buy => (taker.user.key(), maker_user, taker_order_id, maker_order_id),
+        OrderType::Sell => (maker_user, taker.user.key(), maker_order_id, taker_order_id),
+    };
+    emit!(OrdersMatched {
+        trade_id,
+        buyer,
+        seller,
+        company_id: orderbook.company_id,
+        amount,
+        price: head.price,
+        total_value,
+        platform_fee,
+        buy_order_id,
+        sell_order_id,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Applies the taker's self-trade prevention mode against a maker of the same
+// owner or trading account. Nothing trades: the maker is cancelled or
+// shrunk, with the released escrow refunded to the maker's destination.
+// Returns the taker size cancelled.
+#[allow(clippy::too_many_arguments)]
+fn prevent_self_trade<'info>(
+    book: &mut OrderBookSlab,
+    orderbook: &mut Market,
+    taker: &TakerAccounts<'_, 'info>,
+    maker_order: &mut EnhancedOrder,
+    maker: &[AccountInfo<'info>],
+    head: &BookNode,
+    taker_order_id: u64,
+    taker_remaining: u64,
+) -> Result<u64> {
+    // Locked seed liquidity is never cancelled, so the taker gives way
+    let (maker_cancelled_amount, taker_cancelled_amount) =
+        if orderbook.is_locked_order(maker_order.id, Clock::get()?.unix_timestamp) {
+            (0, taker_remaining)
+        } else {
+            self_trade_amounts(&taker.self_trade_prevention, head.quantity, taker_remaining)
+        };
+
+    if maker_cancelled_amount > 0 {
+        let (maker_order_info, maker_escrow_info, maker_destination_info) = (&maker[0], &maker[1], &maker[2]);
+        check_maker_escrow(maker_order, maker_escrow_info)?;
+        let maker_escrow = TokenAccount::try_deserialize(&mut &maker_escrow_info.try_borrow_data()?[..])?;
+        let maker_destination = load_maker_destination(maker_order, maker_destination_info)?;
+        require_keys_eq!(maker_destination.mint, maker_escrow.mint, DefiTradingError::TokenAccountMismatch);
+
+        let cancels_maker = maker_cancelled_amount == maker_order.remaining_amount;
+        let refund = match maker_order.order_type {
+            OrderType::Sell => maker_cancelled_amount,
+            OrderType::Buy if cancels_maker => maker_escrow.amount,
+            OrderType::Buy => buy_escrow_release(
+                maker_order.remaining_amount,
+                maker_cancelled_amount,
+                maker_order.price,
+                orderbook.base_decimals,
+            )?,
+        };
+
+        let maker_company_bytes = maker_order.company_id.to_le_bytes();
+        let maker_id_bytes = maker_order.id.to_le_bytes();
+        let maker_seeds = &[
+            b"enhanced_order",
+            maker_company_bytes.as_ref(),
+            maker_order.user.as_ref(),
+            maker_id_bytes.as_ref(),
+            &[maker_order.bump],
+        ];
+        let maker_signer = &[&maker_seeds[..]];
+        if refund > 0 {
+            let cpi_accounts = Transfer {
+                from: maker_escrow_info.clone(),
+                to: maker_destination_info.clone(),
+                authority: maker_order_info.clone(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(taker.token_program.clone(), cpi_accounts, maker_signer);
+            token::transfer(cpi_ctx, refund)?;
+        }
+
+        if cancels_maker {
+            release_order(book, maker_order)?;
+        } else {
+            book.reduce(&maker_order.order_type, maker_order.id, maker_cancelled_amount)?;
+            maker_order.remaining_amount -= maker_cancelled_amount;
+        }
+        maker_order.try_serialize(&mut &mut maker_order_info.try_borrow_mut_data()?[..])?;
+    }
+
+    emit!(SelfTradePrevented {
+        company_id: orderbook.company_id,
+        taker_order_id,
+        maker_order_id: maker_order.id,
+        user: taker.user.key(),
+        mode: self_trade_prevention_name(&taker.self_trade_prevention),
+        taker_cancelled_amount,
+        maker_cancelled_amount,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(taker_cancelled_amount)
+}
+
+// Self-trade prevention against an open orders maker. The cancelled part of
+// the maker is unlocked back into its free balance.
+#[allow(clippy::too_many_arguments)]
+fn prevent_open_orders_self_trade<'info>(
+    book: &mut OrderBookSlab,
+    orderbook: &mut Market,
+    taker: &TakerAccounts<'_, 'info>,
+    open_orders: &mut OpenOrders,
+    open_orders_info: &AccountInfo<'info>,
+    head: &BookNode,
+    taker_order_id: u64,
+    taker_remaining: u64,
+) -> Result<u64> {
+    let (maker_cancelled_amount, taker_cancelled_amount) =
+        self_trade_amounts(&taker.self_trade_prevention, head.quantity, taker_remaining);
+
+    if maker_cancelled_amount > 0 {
+        let index = open_orders.find_order(head.order_id).ok_or(DefiTradingError::OrderNotFound)?;
+        let order_type = open_orders.orders[index].order_type.clone();
+        open_orders.unlock(index, maker_cancelled_amount, orderbook.base_decimals)?;
+        if maker_cancelled_amount == head.quantity {
+            release_book_order(book, &order_type, head.order_id)?;
+        } else {
+            book.reduce(&order_type, head.order_id, maker_cancelled_amount)?;
+        }
+        open_orders.try_serialize(&mut &mut open_orders_info.try_borrow_mut_data()?[..])?;
+    }
+
+    emit!(SelfTradePrevented {
+        company_id: orderbook.company_id,
+        taker_order_id,
+        maker_order_id: head.order_id,
+        user: taker.user.key(),
+        mode: self_trade_prevention_name(&taker.self_trade_prevention),
+        taker_cancelled_amount,
+        maker_cancelled_amount,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(taker_cancelled_amount)
+}
+
+// Maker and taker sizes cancelled by each self-trade prevention mode
+fn self_trade_amounts(mode: &SelfTradePrevention, maker_quantity: u64, taker_remaining: u64) -> (u64, u64) {
+    match mode {
+        SelfTradePrevention::CancelMaker => (maker_quantity, 0),
+        SelfTradePrevention::CancelTaker => (0, taker_remaining),
+        SelfTradePrevention::CancelBoth => (maker_quantity, taker_remaining),
+        SelfTradePrevention::DecrementAndCancel => {
+            let overlap = taker_remaining.min(maker_quantity);
+            (overlap, overlap)
+        }
+    }
+}
+
+fn self_trade_prevention_name(mode: &SelfTradePrevention) -> String {
+    match mode {
+        SelfTradePrevention::CancelMaker => "CancelMaker".to_string(),
+        SelfTradePrevention::CancelTaker => "CancelTaker".to_string(),
+        SelfTradePrevention::CancelBoth => "CancelBoth".to_string(),
+        SelfTradePrevention::DecrementAndCancel => "DecrementAndCancel".to_string(),
+    }
+}
+
+// Checks that the escrow passed for a maker is the maker order's own
+fn check_maker_escrow(maker_order: &EnhancedOrder, maker_escrow_info: &AccountInfo) -> Result<()> {
+    let maker_company_bytes = maker_order.company_id.to_le_bytes();
+    let maker_id_bytes = maker_order.id.to_le_bytes();
+    let (escrow_key, _) = Pubkey::find_program_address(
+        &[
+            b"enhanced_order_escrow",
+            maker_company_bytes.as_ref(),
+            maker_order.user.as_ref(),
+            maker_id_bytes.as_ref(),
+        ],
+        &crate::ID,
+    );
+    require_keys_eq!(maker_escrow_info.key(), escrow_key, DefiTradingError::TokenAccountMismatch);
+    Ok(())
+}
+
+// Reads the token account passed to pay the maker, which the maker must own
+fn load_maker_destination(maker_order: &EnhancedOrder, maker_destination_info: &AccountInfo) -> Result<TokenAccount> {
+    require_keys_eq!(*maker_destination_info.owner, token::ID, DefiTradingError::TokenAccountMismatch);
+    let maker_destination = TokenAccount::try_deserialize(&mut &maker_destination_info.try_borrow_data()?[..])?;
+    require_keys_eq!(maker_destination.owner, maker_order.user, DefiTradingError::TokenAccountMismatch);
+    Ok(maker_destination)
+}
+
+// Moves taker payment into `destination`. A signing user may pay in native
+// SOL; an escrowing program authority always pays from its SPL account.
+fn pay_from_taker<'info>(
+    taker: &TakerAccounts<'_, 'info>,
+    destination: AccountInfo<'info>,
+    destination_mint: Pubkey,
+    amount: u64,
+) -> Result<()> {
+    if taker.signer_seeds.is_empty() {
+        return collect_payment(
+            taker.authority.clone(),
+            taker.user_payment_account,
+            destination,
+            destination_mint,
+            taker.token_program.clone(),
+            taker.system_program.clone(),
+            amount,
+        );
+    }
+
+    let payment_account = taker.user_payment_account
+        .ok_or(DefiTradingError::TakerAccountsRequired)?;
+    move_tokens(
+        payment_account.to_account_info(),
+        destination,
+        taker.authority.clone(),
+        taker.signer_seeds,
+        taker.token_program.clone(),
+        amount,
+    )
+}
+
+// Signed SPL transfer that does nothing for zero amounts, or when both sides
+// are the same market vault because maker and taker trade through it
+fn move_tokens<'info>(
+    from: AccountInfo<'info>,
+    to: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    token_program: AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 || from.key() == to.key() {
+        return Ok(());
+    }
+    let cpi_accounts = Transfer {
+        from,
+        to,
+        authority,
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer_seeds);
+    token::transfer(cpi_ctx, amount)
+}
+
+// Checks that a vault passed for an open orders maker is the market's vault
+// for `mint`
+pub(crate) fn check_market_vault(vault_info: &AccountInfo, company_id: u64, mint: &Pubkey) -> Result<()> {
+    let (vault_key, _) = Pubkey::find_program_address(
+        &[b"market_vault", company_id.to_le_bytes().as_ref(), mint.as_ref()],
+        &crate::ID,
+    );
+    require_keys_eq!(vault_info.key(), vault_key, DefiTradingError::InvalidMarketVault);
+    Ok(())
+}
+
+// Reads an open orders account passed for the maker at the head of the book
+fn load_maker_open_orders(
+    open_orders_info: &AccountInfo,
+    head: &BookNode,
+    company_id: u64,
+) -> Result<OpenOrders> {
+    require_keys_eq!(*open_orders_info.owner, crate::ID, DefiTradingError::OrderNotFound);
+    require!(open_orders_info.is_writable, DefiTradingError::OrderNotFound);
+    require_keys_eq!(open_orders_info.key(), head.open_orders, DefiTradingError::OrderNotAtTopOfBook);
+    let open_orders = OpenOrders::try_deserialize(&mut &open_orders_info.try_borrow_data()?[..])?;
+    require_keys_eq!(open_orders.owner, head.owner, DefiTradingError::OrderNotAtTopOfBook);
+    require!(open_orders.company_id == company_id, DefiTradingError::InvalidCompanyData);
+    Ok(open_orders)
+}
+
+// Reads a maker order passed as a remaining account and checks that it is
+// the resting order at the head of the book
+fn load_maker_order(
+    maker_order_info: &AccountInfo,
+    head: &BookNode,
+    company_id: u64,
+) -> Result<EnhancedOrder> {
+    require_keys_eq!(*maker_order_info.owner, crate::ID, DefiTradingError::OrderNotFound);
+    require!(maker_order_info.is_writable, DefiTradingError::OrderNotFound);
+    let maker_order = EnhancedOrder::try_deserialize(&mut &maker_order_info.try_borrow_data()?[..])?;
+
+    require!(
+        maker_order.id == head.order_id && maker_order.user == head.owner,
+        DefiTradingError::OrderNotAtTopOfBook
+    );
+    require!(maker_order.company_id == company_id, DefiTradingError::InvalidCompanyData);
+
+    let maker_company_bytes = maker_order.company_id.to_le_bytes();
+    let maker_id_bytes = maker_order.id.to_le_bytes();
+    let expected_key = Pubkey::create_program_address(
+        &[
+            b"enhanced_order",
+            maker_company_bytes.as_ref(),
+            maker_order.user.as_ref(),
+            maker_id_bytes.as_ref(),
+            &[maker_order.bump],
+        ],
+        &crate::ID,
+    )
+    .map_err(|_| error!(DefiTradingError::OrderNotFound))?;
+    require_keys_eq!(maker_order_info.key(), expected_key, DefiTradingError::OrderNotFound);
+
+    Ok(maker_order)
+}
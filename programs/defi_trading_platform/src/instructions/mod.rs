@@ -7,24 +7,47 @@ use crate::events::*;
 pub mod platform;
 pub mod company;
 pub mod token_offering;
+pub mod bonding_curve;
 pub mod trading;
 pub mod enhanced_trading;
 pub mod portfolio;
 pub mod escrow;
+pub mod matching;
+pub mod trigger_orders;
+pub mod trading_account;
+pub mod open_orders;
+pub mod market;
 
 pub use platform::*;
 pub use company::*;
 pub use token_offering::*;
+pub use bonding_curve::*;
 pub use trading::*;
 pub use enhanced_trading::*;
 pub use portfolio::*;
 pub use escrow::*;
+pub use trigger_orders::*;
+pub use trading_account::*;
+pub use open_orders::*;
+pub use market::*;
 
 // Re-export all instruction functions
-pub use platform::{initialize_platform, pause_platform, unpause_platform, update_platform_fee, verify_company};
+pub use platform::{initialize_platform, pause_platform, unpause_platform, update_platform_fee, verify_company, migrate_platform};
 pub use company::{register_company, admin_create_company, distribute_tokens, transfer_to_recipient};
-pub use token_offering::{create_token_offering, participate_in_offering};
-pub use trading::{create_sell_order, create_buy_order, execute_trade, cancel_order};
-pub use enhanced_trading::{create_limit_order, create_market_order, match_orders, calculate_market_depth};
-pub use portfolio::{create_portfolio, update_portfolio};
-pub use escrow::{create_escrow, release_escrow};
\ No newline at end of file
+pub use token_offering::{
+    create_token_offering, participate_in_offering, add_offering_round,
+    create_dutch_auction_offering,
+    claim_auction_tokens, create_subscription_offering, claim_allocation,
+    withdraw_offering_proceeds, cancel_offering, claim_offering_refund, amend_offering,
+    set_offering_lockup, open_offering_lock, unlock_offering_tokens,
+    migrate_offering, migrate_participation,
+};
+pub use bonding_curve::{create_bonding_curve, buy_from_curve, sell_to_curve};
+pub use trading::{execute_trade, cancel_order, amend_order};
+pub use enhanced_trading::{create_limit_order, create_market_order, match_orders, initialize_order_book, configure_market, configure_circuit_breaker, cancel_expired_order, cancel_enhanced_order, cancel_all_orders, amend_enhanced_order, migrate_enhanced_order, calculate_market_depth};
+pub use portfolio::{create_portfolio, update_portfolio, migrate_holding};
+pub use escrow::{create_escrow, release_escrow, cancel_escrow};
+pub use trigger_orders::{create_trigger_order, trigger_order, cancel_trigger_order, configure_price_feed, update_price_feed};
+pub use trading_account::{create_trading_account, link_wallet, unlink_wallet};
+pub use open_orders::{create_open_orders, deposit_funds, place_order, cancel_open_order, settle_funds};
+pub use market::{create_market, migrate_orderbook};
\ No newline at end of file
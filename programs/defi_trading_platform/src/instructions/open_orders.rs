@@ -0,0 +1,584 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, spl_token::native_mint, Mint, Token, TokenAccount, Transfer};
+use crate::state::*;
+use crate::errors::*;
+use crate::events::*;
+use crate::pricing::*;
+use crate::native_sol::*;
+use super::enhanced_trading::{
+    check_time_in_force, limit_order_max_fills, record_eviction, release_book_order, sync_book_state,
+    time_in_force_name, trading_account_key,
+};
+use super::matching::*;
+
+// Deposit/settle trading. A user keeps one OpenOrders account per market;
+// funds are deposited once into the market vaults, which are owned by the
+// Market account. Orders placed through it lock part of the free
+// balance and need no order or escrow accounts of their own, fills credit
+// the free balance, and settle_funds pays the free balance out.
+
+pub fn create_open_orders(ctx: Context<CreateOpenOrders>) -> Result<()> {
+    let owner = ctx.accounts.owner.key();
+    let trading_account = trading_account_key(&owner, ctx.accounts.trading_account.as_ref())?;
+    let open_orders = &mut ctx.accounts.open_orders;
+    
+    open_orders.owner = owner;
+    open_orders.trading_account = trading_account;
+    open_orders.company_id = ctx.accounts.orderbook.company_id;
+    open_orders.quote_mint = ctx.accounts.quote_mint.key();
+    open_orders.base_free = 0;
+    open_orders.base_locked = 0;
+    open_orders.quote_free = 0;
+    open_orders.quote_locked = 0;
+    open_orders.orders = Default::default();
+    open_orders.created_at = Clock::get()?.unix_timestamp;
+    open_orders.bump = ctx.bumps.open_orders;
+    
+    emit!(OpenOrdersCreated {
+        open_orders: open_orders.key(),
+        owner: open_orders.owner,
+        company_id: open_orders.company_id,
+        quote_mint: open_orders.quote_mint,
+        trading_account,
+        timestamp: open_orders.created_at,
+    });
+    
+    Ok(())
+}
+
+// Moves funds into the market vaults and credits them as free balance.
+// Quote is wrapped from native SOL when no payment account is given.
+pub fn deposit_funds(ctx: Context<DepositFunds>, base_amount: u64, quote_amount: u64) -> Result<()> {
+    require!(!ctx.accounts.platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(base_amount > 0 || quote_amount > 0, DefiTradingError::InvalidTradeAmount);
+    
+    if base_amount > 0 {
+        let owner_token_account = ctx.accounts.owner_token_account.as_ref()
+            .ok_or(DefiTradingError::TokenAccountMismatch)?;
+        let cpi_accounts = Transfer {
+            from: owner_token_account.to_account_info(),
+            to: ctx.accounts.base_vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, base_amount)?;
+    }
+    if quote_amount > 0 {
+        collect_payment(
+            ctx.accounts.owner.to_account_info(),
+            ctx.accounts.owner_payment_account.as_ref(),
+            ctx.accounts.quote_vault.to_account_info(),
+            ctx.accounts.quote_vault.mint,
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            quote_amount,
+        )?;
+    }
+    
+    let open_orders = &mut ctx.accounts.open_orders;
+    open_orders.base_free = open_orders.base_free
+        .checked_add(base_amount)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    open_orders.quote_free = open_orders.quote_free
+        .checked_add(quote_amount)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    
+    emit!(FundsDeposited {
+        open_orders: open_orders.key(),
+        owner: open_orders.owner,
+        company_id: open_orders.company_id,
+        base_amount,
+        quote_amount,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+// Limit order paid from the open orders free balance. It takes liquidity
+// like create_limit_order, and the remainder rests with its funds locked.
+#[allow(clippy::too_many_arguments)]
+pub fn place_order<'info>(
+    ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+    order_type: OrderType,
+    amount: u64,
+    price: u64,
+    max_fills: u8,
+    time_in_force: TimeInForce,
+    expires_at: Option<i64>,
+    self_trade_prevention: SelfTradePrevention,
+) -> Result<()> {
+    let orderbook_info = ctx.accounts.orderbook.to_account_info();
+    let platform = &mut ctx.accounts.platform;
+    let orderbook = &mut ctx.accounts.orderbook;
+    
+    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(amount > 0, DefiTradingError::InvalidOrderParams);
+    require!(price > 0, DefiTradingError::InvalidOrderParams);
+    orderbook.market_config.check_limit_order(amount, price, orderbook.base_decimals)?;
+    let now = Clock::get()?.unix_timestamp;
+    check_time_in_force(&time_in_force, expires_at, now)?;
+    
+    let order_id = orderbook.last_order_id
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let owner_key = ctx.accounts.owner.key();
+    let open_orders_key = ctx.accounts.open_orders.key();
+    let mut book = ctx.accounts.order_book.load_mut()?;
+    let maker_side = match order_type {
+        OrderType::Buy => OrderType::Sell,
+        OrderType::Sell => OrderType::Buy,
+    };
+    book.remove_expired_heads(&maker_side, now);
+    let max_fills = limit_order_max_fills(&book, &order_type, price, &time_in_force, max_fills, now)?;
+    
+    // The whole order must be covered by free funds up front, with room for
+    // the rounding of every fill it may take
+    match order_type {
+        OrderType::Sell => require!(
+            ctx.accounts.open_orders.base_free >= amount,
+            DefiTradingError::InsufficientTokens
+        ),
+        OrderType::Buy => require!(
+            ctx.accounts.open_orders.quote_free >= taker_buy_budget(amount, price, orderbook.base_decimals, max_fills)?,
+            DefiTradingError::InsufficientFunds
+        ),
+    }
+    
+    // The market vaults stand in for the owner's wallet, signed for by the
+    // orderbook
+    let company_id_bytes = orderbook.company_id.to_le_bytes();
+    let seeds = &[b"orderbook".as_ref(), company_id_bytes.as_ref(), &[orderbook.bump]];
+    let signer = &[&seeds[..]];
+    let taker = TakerAccounts {
+        user: ctx.accounts.owner.to_account_info(),
+        authority: orderbook_info.clone(),
+        signer_seeds: signer,
+        orderbook: orderbook_info,
+        self_trade_key: ctx.accounts.open_orders.self_trade_key(),
+        self_trade_prevention,
+        user_token_account: &ctx.accounts.base_vault,
+        user_payment_account: Some(&ctx.accounts.quote_vault),
+        platform_fee_account: ctx.accounts.platform_fee_account.as_ref(),
+        token_program: ctx.accounts.token_program.to_account_info(),
+        system_program: ctx.accounts.system_program.to_account_info(),
+    };
+    let summary = match_against_book(
+        &mut book,
+        orderbook,
+        platform,
+        &taker,
+        ctx.remaining_accounts,
+        &order_type,
+        order_id,
+        price,
+        amount,
+        max_fills,
+    )?;
+    let remaining_amount = amount - summary.filled_amount - summary.cancelled_amount;
+    if time_in_force == TimeInForce::FillOrKill {
+        require!(summary.filled_amount == amount, DefiTradingError::FillOrKillNotFilled);
+    }
+    let rest_amount = if time_in_force == TimeInForce::ImmediateOrCancel || summary.taker_cancelled {
+        0
+    } else {
+        remaining_amount
+    };
+    
+    // Self-trade prevention may have rewritten this account as a maker
+    ctx.accounts.open_orders.reload()?;
+    let open_orders = &mut ctx.accounts.open_orders;
+    match order_type {
+        OrderType::Buy => {
+            open_orders.quote_free = open_orders.quote_free
+                .checked_sub(summary.quote_amount)
+                .ok_or(DefiTradingError::InsufficientFunds)?;
+            open_orders.base_free = open_orders.base_free
+                .checked_add(summary.filled_amount)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        },
+        OrderType::Sell => {
+            let proceeds = summary.quote_amount
+                .checked_sub(summary.platform_fees)
+                .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+            open_orders.base_free = open_orders.base_free
+                .checked_sub(summary.filled_amount)
+                .ok_or(DefiTradingError::InsufficientTokens)?;
+            open_orders.quote_free = open_orders.quote_free
+                .checked_add(proceeds)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        }
+    }
+    
+    // Rest the remainder with its funds locked
+    if rest_amount > 0 {
+        open_orders.lock(&order_type, rest_amount, price, orderbook.base_decimals)?;
+        open_orders.add_order(OpenOrderSlot {
+            order_id,
+            order_type: order_type.clone(),
+            price,
+            remaining_amount: rest_amount,
+            expires_at: expires_at.unwrap_or(0),
+        })?;
+        let evicted = book.insert(&order_type, order_id, owner_key, open_orders_key, price, rest_amount, expires_at.unwrap_or(0))?;
+        record_eviction(orderbook, evicted, now)?;
+    }
+    orderbook.last_order_id = order_id;
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
+    
+    emit!(LimitOrderCreated {
+        order_id,
+        user: owner_key,
+        company_id: orderbook.company_id,
+        order_type: match order_type {
+            OrderType::Buy => "Buy".to_string(),
+            OrderType::Sell => "Sell".to_string(),
+        },
+        amount,
+        price,
+        filled_amount: summary.filled_amount,
+        fills: summary.fills,
+        time_in_force: time_in_force_name(&time_in_force),
+        expires_at,
+        timestamp: now,
+    });
+    
+    Ok(())
+}
+
+// Cancels an order resting through an open orders account, including one
+// matching already dropped from the book after it expired. Its locked funds
+// become free again.
+pub fn cancel_open_order(ctx: Context<CancelOpenOrder>, order_id: u64) -> Result<()> {
+    let orderbook = &mut ctx.accounts.orderbook;
+    let open_orders = &mut ctx.accounts.open_orders;
+    
+    let index = open_orders.find_order(order_id).ok_or(DefiTradingError::OrderNotFound)?;
+    let slot = open_orders.orders[index].clone();
+    
+    let mut book = ctx.accounts.order_book.load_mut()?;
+    release_book_order(&mut book, &slot.order_type, order_id)?;
+    open_orders.unlock(index, slot.remaining_amount, orderbook.base_decimals)?;
+    let now = Clock::get()?.unix_timestamp;
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
+    
+    emit!(OrderCancelled {
+        order_id,
+        user: open_orders.owner,
+        company_id: open_orders.company_id,
+        timestamp: orderbook.last_updated,
+    });
+    
+    Ok(())
+}
+
+// Pays the whole free balance out of the market vaults. Quote in a wrapped
+// SOL vault is paid as native SOL when no payment account is given.
+pub fn settle_funds(ctx: Context<SettleFunds>) -> Result<()> {
+    let orderbook = &ctx.accounts.orderbook;
+    let base_amount = ctx.accounts.open_orders.base_free;
+    let quote_amount = ctx.accounts.open_orders.quote_free;
+    
+    let company_id_bytes = orderbook.company_id.to_le_bytes();
+    let seeds = &[b"orderbook".as_ref(), company_id_bytes.as_ref(), &[orderbook.bump]];
+    let signer = &[&seeds[..]];
+    
+    if base_amount > 0 {
+        let owner_token_account = ctx.accounts.owner_token_account.as_ref()
+            .ok_or(DefiTradingError::TokenAccountMismatch)?;
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.base_vault.to_account_info(),
+            to: owner_token_account.to_account_info(),
+            authority: orderbook.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, base_amount)?;
+    }
+    // A temporary wSOL account is always closed again
+    if quote_amount > 0 || ctx.accounts.temp_wsol_account.is_some() {
+        return_payment(
+            &ctx.accounts.quote_vault,
+            orderbook.to_account_info(),
+            signer,
+            ctx.accounts.owner.to_account_info(),
+            ctx.accounts.owner_payment_account.as_ref(),
+            ctx.accounts.temp_wsol_account.as_ref(),
+            ctx.accounts.token_program.to_account_info(),
+            quote_amount,
+        )?;
+    }
+    
+    let open_orders = &mut ctx.accounts.open_orders;
+    open_orders.base_free = 0;
+    open_orders.quote_free = 0;
+    
+    emit!(FundsSettled {
+        open_orders: open_orders.key(),
+        owner: open_orders.owner,
+        company_id: open_orders.company_id,
+        base_amount,
+        quote_amount,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CreateOpenOrders<'info> {
+    #[account(
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        init,
+        payer = owner,
+        space = OpenOrders::LEN,
+        seeds = [b"open_orders", orderbook.company_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+    
+    // The first user of a market creates its vaults
+    #[account(
+        init_if_needed,
+        payer = owner,
+        token::mint = token_mint,
+        token::authority = orderbook,
+        seeds = [b"market_vault", orderbook.company_id.to_le_bytes().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub base_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        init_if_needed,
+        payer = owner,
+        token::mint = quote_mint,
+        token::authority = orderbook,
+        seeds = [b"market_vault", orderbook.company_id.to_le_bytes().as_ref(), quote_mint.key().as_ref()],
+        bump
+    )]
+    pub quote_vault: Account<'info, TokenAccount>,
+    
+    #[account(address = orderbook.base_mint)]
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(address = orderbook.quote_mint)]
+    pub quote_mint: Account<'info, Mint>,
+    
+    // Set to trade for a trading account the owner is linked to
+    pub trading_account: Option<Account<'info, TradingAccount>>,
+    
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct DepositFunds<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"open_orders", orderbook.company_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump = open_orders.bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+    
+    #[account(
+        mut,
+        seeds = [b"market_vault", orderbook.company_id.to_le_bytes().as_ref(), orderbook.base_mint.as_ref()],
+        bump
+    )]
+    pub base_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"market_vault", orderbook.company_id.to_le_bytes().as_ref(), orderbook.quote_mint.as_ref()],
+        bump
+    )]
+    pub quote_vault: Account<'info, TokenAccount>,
+    
+    // Required to deposit company tokens
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == orderbook.base_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub owner_token_account: Option<Account<'info, TokenAccount>>,
+    
+    // Omit to deposit quote in native SOL
+    #[account(
+        mut,
+        constraint = owner_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub owner_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(
+        mut,
+        seeds = [b"open_orders", orderbook.company_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump = open_orders.bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+    
+    #[account(
+        mut,
+        seeds = [b"market_vault", orderbook.company_id.to_le_bytes().as_ref(), orderbook.base_mint.as_ref()],
+        bump
+    )]
+    pub base_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"market_vault", orderbook.company_id.to_le_bytes().as_ref(), orderbook.quote_mint.as_ref()],
+        bump
+    )]
+    pub quote_vault: Account<'info, TokenAccount>,
+    
+    // Required when the order takes liquidity from the book
+    #[account(
+        mut,
+        constraint = platform_fee_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub platform_fee_account: Option<Account<'info, TokenAccount>>,
+    
+    pub owner: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOpenOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    #[account(
+        mut,
+        seeds = [b"open_orders", orderbook.company_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump = open_orders.bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+    
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleFunds<'info> {
+    #[account(
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"open_orders", orderbook.company_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump = open_orders.bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+    
+    #[account(
+        mut,
+        seeds = [b"market_vault", orderbook.company_id.to_le_bytes().as_ref(), orderbook.base_mint.as_ref()],
+        bump
+    )]
+    pub base_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"market_vault", orderbook.company_id.to_le_bytes().as_ref(), orderbook.quote_mint.as_ref()],
+        bump
+    )]
+    pub quote_vault: Account<'info, TokenAccount>,
+    
+    // Required when there are company tokens to settle
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == orderbook.base_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub owner_token_account: Option<Account<'info, TokenAccount>>,
+    
+    // Omit to settle a wrapped SOL quote balance in native SOL
+    #[account(
+        mut,
+        constraint = owner_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub owner_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = owner,
+        token::mint = native_mint,
+        token::authority = owner,
+        seeds = [TEMP_WSOL_SEED, owner.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
 use crate::state::*;
 use crate::errors::*;
 use crate::events::*;
@@ -13,6 +14,8 @@ pub fn initialize_platform(ctx: Context<InitializePlatform>) -> Result<()> {
     platform.platform_fee = 100; // 1% default fee
     platform.is_paused = false;
     platform.bump = ctx.bumps.platform;
+    platform.last_order_id = 0;
+    platform.last_escrow_id = 0;
 
     emit!(PlatformInitialized {
         authority: platform.authority,
@@ -89,6 +92,74 @@ pub fn verify_company(ctx: Context<VerifyCompany>, company_id: u64) -> Result<()
     Ok(())
 }
 
+// Grows a platform created before the order and escrow sequences. Legacy
+// orders and escrows took their ids from total_trades, so both sequences
+// start past every id handed out that way.
+pub fn migrate_platform(ctx: Context<MigratePlatform>) -> Result<()> {
+    let platform_info = ctx.accounts.platform.to_account_info();
+    
+    grow_legacy_account(
+        &platform_info,
+        &ctx.accounts.authority.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        Platform::LEGACY_LEN,
+        Platform::LEN,
+    )?;
+    
+    // The new fields read as zero until they are set below
+    let mut platform = Platform::try_deserialize(&mut &platform_info.try_borrow_data()?[..])?;
+    require!(
+        ctx.accounts.authority.key() == platform.authority,
+        DefiTradingError::Unauthorized
+    );
+    
+    let next_legacy_id = platform.total_trades
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    platform.last_order_id = next_legacy_id;
+    platform.last_escrow_id = next_legacy_id;
+    platform.try_serialize(&mut &mut platform_info.try_borrow_mut_data()?[..])?;
+    
+    emit!(PlatformMigrated {
+        last_order_id: platform.last_order_id,
+        last_escrow_id: platform.last_escrow_id,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+// Grows an account of this program from its legacy size to its current
+// one, with the payer covering the extra rent. Fields appended after the
+// legacy layout read as zero until the caller sets them.
+pub(crate) fn grow_legacy_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    legacy_len: usize,
+    len: usize,
+) -> Result<()> {
+    require_keys_eq!(*account.owner, crate::ID, DefiTradingError::Unauthorized);
+    require!(
+        account.data_len() == legacy_len,
+        DefiTradingError::AccountAlreadyMigrated
+    );
+    
+    let rent = Rent::get()?.minimum_balance(len);
+    let top_up = rent.saturating_sub(account.lamports());
+    if top_up > 0 {
+        let cpi_accounts = Transfer {
+            from: payer.clone(),
+            to: account.clone(),
+        };
+        let cpi_ctx = CpiContext::new(system_program.clone(), cpi_accounts);
+        system_program::transfer(cpi_ctx, top_up)?;
+    }
+    account.resize(len)?;
+    
+    Ok(())
+}
+
 #[derive(Accounts)]
 pub struct InitializePlatform<'info> {
     #[account(
@@ -106,6 +177,22 @@ pub struct InitializePlatform<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct MigratePlatform<'info> {
+    /// CHECK: Old platform layout, checked and rewritten in migrate_platform
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump
+    )]
+    pub platform: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct PausePlatform<'info> {
     #[account(
@@ -1,7 +1,10 @@
 use anchor_lang::prelude::*;
+use anchor_spl::token::Mint;
 use crate::state::*;
 use crate::errors::*;
 use crate::events::*;
+use crate::pricing::*;
+use super::platform::grow_legacy_account;
 
 pub fn create_portfolio(ctx: Context<CreatePortfolio>) -> Result<()> {
     let portfolio = &mut ctx.accounts.portfolio;
@@ -78,15 +81,14 @@ pub fn update_holding_after_trade(
 ) -> Result<()> {
     let holding = &mut ctx.accounts.holding;
     let portfolio = &mut ctx.accounts.portfolio;
+    let base_decimals = ctx.accounts.token_mint.decimals;
     
     let current_timestamp = Clock::get()?.unix_timestamp;
     
     if is_buy {
         // Buying tokens
         let amount_bought = amount_change as u64;
-        let cost = amount_bought
-            .checked_mul(price)
-            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        let cost = quote_for_base(amount_bought, price, base_decimals, Rounding::Up)?;
         
         let new_total_invested = holding.total_invested
             .checked_add(cost)
@@ -98,9 +100,7 @@ pub fn update_holding_after_trade(
         
         // Calculate new average price
         let new_average_price = if new_amount > 0 {
-            new_total_invested
-                .checked_div(new_amount)
-                .ok_or(DefiTradingError::DivisionByZero)?
+            unit_price(new_total_invested, new_amount, base_decimals)?
         } else {
             0
         };
@@ -108,9 +108,12 @@ pub fn update_holding_after_trade(
         holding.amount = new_amount;
         holding.average_price = new_average_price;
         holding.total_invested = new_total_invested;
-        holding.current_value = new_amount
-            .checked_mul(price)
-            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        holding.current_value = quote_for_base(
+            new_amount,
+            price,
+            base_decimals,
+            Rounding::Down,
+        )?;
         holding.profit_loss = (holding.current_value as i64)
             .checked_sub(holding.total_invested as i64)
             .ok_or(DefiTradingError::ArithmeticUnderflow)?;
@@ -118,12 +121,16 @@ pub fn update_holding_after_trade(
         // Selling tokens
         let amount_sold = (-amount_change) as u64;
         
-        require!(holding.amount >= amount_sold, DefiTradingError::InsufficientTokens);
+        // Tokens still under an offering lockup cannot be sold
+        require!(holding.free_amount() >= amount_sold, DefiTradingError::InsufficientTokens);
         
         // Calculate cost basis of sold tokens
-        let cost_basis_sold = amount_sold
-            .checked_mul(holding.average_price)
-            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        let cost_basis_sold = quote_for_base(
+            amount_sold,
+            holding.average_price,
+            base_decimals,
+            Rounding::Down,
+        )?;
         
         holding.amount = holding.amount
             .checked_sub(amount_sold)
@@ -131,9 +138,12 @@ pub fn update_holding_after_trade(
         holding.total_invested = holding.total_invested
             .checked_sub(cost_basis_sold)
             .ok_or(DefiTradingError::ArithmeticUnderflow)?;
-        holding.current_value = holding.amount
-            .checked_mul(price)
-            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        holding.current_value = quote_for_base(
+            holding.amount,
+            price,
+            base_decimals,
+            Rounding::Down,
+        )?;
         holding.profit_loss = (holding.current_value as i64)
             .checked_sub(holding.total_invested as i64)
             .ok_or(DefiTradingError::ArithmeticUnderflow)?;
@@ -149,6 +159,7 @@ pub fn update_holding_after_trade(
         company_id: holding.company_id,
         token_mint: holding.token_mint,
         amount: holding.amount,
+        locked_amount: holding.locked_amount,
         average_price: holding.average_price,
         total_invested: holding.total_invested,
         current_value: holding.current_value,
@@ -167,11 +178,10 @@ pub fn create_or_update_holding(
 ) -> Result<()> {
     let holding = &mut ctx.accounts.holding;
     let portfolio = &mut ctx.accounts.portfolio;
+    let base_decimals = ctx.accounts.token_mint.decimals;
     
     let current_timestamp = Clock::get()?.unix_timestamp;
-    let cost = amount
-        .checked_mul(price)
-        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let cost = quote_for_base(amount, price, base_decimals, Rounding::Up)?;
     
     // Initialize or update holding
     if holding.amount == 0 {
@@ -180,6 +190,7 @@ pub fn create_or_update_holding(
         holding.company_id = company_id;
         holding.token_mint = ctx.accounts.company.token_mint;
         holding.amount = amount;
+        holding.locked_amount = 0;
         holding.average_price = price;
         holding.total_invested = cost;
         holding.current_value = cost;
@@ -201,16 +212,17 @@ pub fn create_or_update_holding(
             .checked_add(amount)
             .ok_or(DefiTradingError::ArithmeticOverflow)?;
         
-        let new_average_price = new_total_invested
-            .checked_div(new_amount)
-            .ok_or(DefiTradingError::DivisionByZero)?;
+        let new_average_price = unit_price(new_total_invested, new_amount, base_decimals)?;
         
         holding.amount = new_amount;
         holding.average_price = new_average_price;
         holding.total_invested = new_total_invested;
-        holding.current_value = new_amount
-            .checked_mul(price)
-            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        holding.current_value = quote_for_base(
+            new_amount,
+            price,
+            base_decimals,
+            Rounding::Down,
+        )?;
         holding.profit_loss = (holding.current_value as i64)
             .checked_sub(holding.total_invested as i64)
             .ok_or(DefiTradingError::ArithmeticUnderflow)?;
@@ -224,6 +236,7 @@ pub fn create_or_update_holding(
         company_id: holding.company_id,
         token_mint: holding.token_mint,
         amount: holding.amount,
+        locked_amount: holding.locked_amount,
         average_price: holding.average_price,
         total_invested: holding.total_invested,
         current_value: holding.current_value,
@@ -234,6 +247,32 @@ pub fn create_or_update_holding(
     Ok(())
 }
 
+// Grows a holding created before offering lockups. Anyone can pay for the
+// migration; a legacy holding has nothing locked.
+pub fn migrate_holding(ctx: Context<MigrateHolding>, _user: Pubkey, _company_id: u64) -> Result<()> {
+    let holding_info = ctx.accounts.holding.to_account_info();
+    
+    grow_legacy_account(
+        &holding_info,
+        &ctx.accounts.payer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        Holding::LEGACY_LEN,
+        Holding::LEN,
+    )?;
+    
+    let mut holding = Holding::try_deserialize(&mut &holding_info.try_borrow_data()?[..])?;
+    holding.locked_amount = 0;
+    holding.try_serialize(&mut &mut holding_info.try_borrow_mut_data()?[..])?;
+    
+    emit!(HoldingMigrated {
+        user: holding.user,
+        company_id: holding.company_id,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
 #[derive(Accounts)]
 pub struct CreatePortfolio<'info> {
     #[account(
@@ -293,6 +332,9 @@ pub struct UpdateHolding<'info> {
     )]
     pub portfolio: Account<'info, Portfolio>,
     
+    #[account(address = holding.token_mint)]
+    pub token_mint: Account<'info, Mint>,
+    
     pub user: Signer<'info>,
 }
 
@@ -325,8 +367,32 @@ pub struct CreateOrUpdateHolding<'info> {
     )]
     pub company: Account<'info, Company>,
     
+    #[account(address = company.token_mint)]
+    pub token_mint: Account<'info, Mint>,
+    
     #[account(mut)]
     pub user: Signer<'info>,
     
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey, company_id: u64)]
+pub struct MigrateHolding<'info> {
+    /// CHECK: Old holding layout, checked and rewritten in migrate_holding
+    #[account(
+        mut,
+        seeds = [
+            b"holding",
+            user.as_ref(),
+            company_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub holding: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
     pub system_program: Program<'info, System>,
 }
\ No newline at end of file
@@ -1,83 +1,61 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Mint, Transfer, MintTo};
+use anchor_lang::solana_program::hash::hashv;
+use anchor_spl::token::{self, spl_token::native_mint, Token, TokenAccount, Mint, Transfer, MintTo, Burn};
 use crate::state::*;
 use crate::errors::*;
 use crate::events::*;
+use crate::pricing::*;
+use crate::native_sol::*;
+use super::platform::grow_legacy_account;
 
 pub fn create_token_offering(
     ctx: Context<CreateTokenOffering>,
     total_supply: u64,
     price_per_token: u64,
+    min_investment: u64,
+    max_investment: u64,
     offering_start: i64,
     offering_end: i64,
 ) -> Result<()> {
-    let platform = &mut ctx.accounts.platform;
-    let company = &mut ctx.accounts.company;
-    let offering = &mut ctx.accounts.token_offering;
-    
-    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
-    require!(total_supply > 0, DefiTradingError::InvalidOfferingParams);
     require!(price_per_token > 0, DefiTradingError::InvalidOfferingParams);
-    require!(offering_start > Clock::get()?.unix_timestamp, DefiTradingError::InvalidTimestamp);
-    require!(offering_end > offering_start, DefiTradingError::InvalidTimestamp);
-    require!(
-        ctx.accounts.authority.key() == company.authority,
-        DefiTradingError::Unauthorized
-    );
-
-    let offering_id = platform.total_offerings + 1;
-    
-    offering.id = offering_id;
-    offering.company_id = company.id;
-    offering.company_authority = company.authority;
-    offering.token_mint = company.token_mint;
-    offering.total_supply = total_supply;
-    offering.remaining_supply = total_supply;
-    offering.price_per_token = price_per_token;
-    offering.offering_start = offering_start;
-    offering.offering_end = offering_end;
-    offering.total_raised = 0;
-    offering.participants_count = 0;
-    offering.status = OfferingStatus::Pending;
-    offering.created_at = Clock::get()?.unix_timestamp;
-    offering.bump = ctx.bumps.token_offering;
 
-    // Mint tokens to the offering escrow account
-    let company_id_bytes = company.id.to_le_bytes();
-    let company_bump = company.bump;
-    
-    // Update company total supply first
-    company.total_supply = company.total_supply
-        .checked_add(total_supply)
-        .ok_or(DefiTradingError::ArithmeticOverflow)?;
-
-    platform.total_offerings = offering_id;
-
-    let seeds = &[
-        b"company",
-        company_id_bytes.as_ref(),
-        &[company_bump],
-    ];
-    let signer = &[&seeds[..]];
-    
-    let company_info = ctx.accounts.company.to_account_info();
-    let cpi_accounts = MintTo {
-        mint: ctx.accounts.token_mint.to_account_info(),
-        to: ctx.accounts.offering_token_account.to_account_info(),
-        authority: company_info,
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-    
-    token::mint_to(cpi_ctx, total_supply)?;
+    init_offering(
+        &mut ctx.accounts.platform,
+        &mut ctx.accounts.company,
+        &mut ctx.accounts.token_offering,
+        ctx.accounts.authority.key(),
+        ctx.accounts.token_mint.decimals,
+        OfferingTerms {
+            kind: OfferingKind::FixedPrice,
+            total_supply,
+            price_per_token,
+            floor_price: 0,
+            payment_vault: ctx.accounts.offering_payment_vault.key(),
+            min_investment,
+            max_investment,
+            offering_start,
+            offering_end,
+        },
+        ctx.bumps.token_offering,
+    )?;
+    mint_offering_supply(
+        &ctx.accounts.company,
+        ctx.accounts.token_mint.to_account_info(),
+        ctx.accounts.offering_token_account.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        total_supply,
+    )?;
 
+    let offering = &ctx.accounts.token_offering;
     emit!(TokenOfferingCreated {
-        offering_id,
+        offering_id: offering.id,
         company_id: offering.company_id,
         company_authority: offering.company_authority,
         token_mint: offering.token_mint,
         total_supply,
         price_per_token,
+        min_investment,
+        max_investment,
         offering_start,
         offering_end,
         timestamp: offering.created_at,
@@ -89,6 +67,7 @@ pub fn create_token_offering(
 pub fn participate_in_offering(
     ctx: Context<ParticipateInOffering>,
     amount: u64,
+    proof: Vec<[u8; 32]>,
 ) -> Result<()> {
     // Store account info before any mutations
     let offering_account_info = ctx.accounts.token_offering.to_account_info();
@@ -106,15 +85,85 @@ pub fn participate_in_offering(
         DefiTradingError::OfferingEnded
     );
     require!(amount > 0, DefiTradingError::InvalidOfferingParams);
-    
-    let tokens_to_receive = amount
-        .checked_div(offering.price_per_token)
-        .ok_or(DefiTradingError::DivisionByZero)?;
-    
     require!(
-        tokens_to_receive <= offering.remaining_supply,
-        DefiTradingError::InsufficientTokens
+        offering.status != OfferingStatus::Cancelled,
+        DefiTradingError::OfferingCancelled
+    );
+
+    let is_new_participant = participation.user == Pubkey::default();
+
+    // Payments are held in the offering's payment vault until the offering
+    // is finalized, so they can be refunded if it is cancelled. Fixed-price
+    // offerings created before they had a vault paid the platform directly.
+    if offering.payment_vault != Pubkey::default() {
+        require_keys_eq!(
+            ctx.accounts.platform_payment_account.key(),
+            offering.payment_vault,
+            DefiTradingError::TokenAccountMismatch
+        );
+    }
+
+    // Offerings with presale rounds take their price, wallet cap and
+    // allowlist from whichever round is currently open
+    let round_index = offering.active_round(clock.unix_timestamp);
+    if !offering.rounds.is_empty() {
+        require!(round_index.is_some(), DefiTradingError::NoActiveRound);
+    }
+    let entry_price = match round_index {
+        Some(index) => {
+            let round = &offering.rounds[index];
+            if round.allowlist_root != [0u8; 32] {
+                let leaf = hashv(&[ctx.accounts.user.key().as_ref()]).to_bytes();
+                require!(
+                    verify_allowlist_proof(&proof, round.allowlist_root, leaf),
+                    DefiTradingError::NotOnAllowlist
+                );
+            }
+            round.price_per_token
+        },
+        None => offering.current_price(clock.unix_timestamp),
+    };
+
+    // Subscription commitments receive no tokens until claim_allocation
+    // splits the supply across all commitments after the deadline. Otherwise
+    // only the cost of the whole token atoms bought is charged, so any dust
+    // left over from `amount` stays with the investor.
+    let (tokens_to_receive, cost) = if offering.kind == OfferingKind::Subscription {
+        (0, amount)
+    } else {
+        let tokens = base_for_quote(amount, entry_price, offering.base_decimals)?;
+        require!(tokens > 0, DefiTradingError::InvalidOfferingParams);
+        require!(
+            tokens <= offering.remaining_supply,
+            DefiTradingError::InsufficientTokens
+        );
+        let cost = quote_for_base(tokens, entry_price, offering.base_decimals, Rounding::Up)?;
+        (tokens, cost)
+    };
+
+    // Limits apply to the investor's running total, so top-ups are allowed
+    // as long as the cumulative amount stays within bounds
+    let total_invested = participation.amount_invested
+        .checked_add(cost)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    require!(
+        total_invested >= offering.min_investment,
+        DefiTradingError::MinimumInvestmentNotMet
+    );
+    require!(
+        offering.max_investment == 0 || total_invested <= offering.max_investment,
+        DefiTradingError::MaximumInvestmentExceeded
     );
+    if let Some(index) = round_index {
+        let round_invested = participation.round_invested[index]
+            .checked_add(cost)
+            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        let max_per_wallet = offering.rounds[index].max_per_wallet;
+        require!(
+            max_per_wallet == 0 || round_invested <= max_per_wallet,
+            DefiTradingError::RoundWalletCapExceeded
+        );
+    }
 
     // Store values before mutations
     let offering_id = offering.id;
@@ -125,168 +174,1288 @@ pub fn participate_in_offering(
         offering.status = OfferingStatus::Active;
     }
 
-    // Transfer payment from user to platform
-    let cpi_accounts = Transfer {
-        from: ctx.accounts.user_payment_account.to_account_info(),
-        to: ctx.accounts.platform_payment_account.to_account_info(),
-        authority: ctx.accounts.user.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-    
-    token::transfer(cpi_ctx, amount)?;
+    // Transfer payment from user to platform, wrapping native SOL if no
+    // payment account was given
+    collect_payment(
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.user_payment_account.as_ref(),
+        ctx.accounts.platform_payment_account.to_account_info(),
+        ctx.accounts.platform_payment_account.mint,
+        ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.system_program.to_account_info(),
+        cost,
+    )?;
 
-    // Transfer tokens from offering to user
-    let offering_id_bytes = offering_id.to_le_bytes();
-    let seeds = &[
-        b"token_offering",
-        offering_id_bytes.as_ref(),
-        &[offering_bump],
-    ];
-    let signer = &[&seeds[..]];
-    
-    let cpi_accounts = Transfer {
-        from: ctx.accounts.offering_token_account.to_account_info(),
-        to: ctx.accounts.user_token_account.to_account_info(),
-        authority: offering_account_info,
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-    
-    token::transfer(cpi_ctx, tokens_to_receive)?;
+    // Transfer tokens from offering to user; auction and subscription
+    // allocations are delivered once the offering is finalized
+    match offering.kind {
+        OfferingKind::FixedPrice => {
+            let offering_id_bytes = offering_id.to_le_bytes();
+            let seeds = &[
+                b"token_offering",
+                offering_id_bytes.as_ref(),
+                &[offering_bump],
+            ];
+            let signer = &[&seeds[..]];
+            
+            let destination = offering_token_destination(
+                offering,
+                ctx.accounts.user_token_account.to_account_info(),
+                ctx.accounts.offering_lock_account.as_ref().map(|lock| lock.to_account_info()),
+            )?;
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.offering_token_account.to_account_info(),
+                to: destination,
+                authority: offering_account_info,
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            
+            token::transfer(cpi_ctx, tokens_to_receive)?;
+
+            if offering.lockup_duration > 0 {
+                record_locked_tokens(participation, ctx.accounts.holding.as_mut(), tokens_to_receive)?;
+            }
+        },
+        OfferingKind::DutchAuction => {
+            offering.clearing_price = entry_price;
+        },
+        OfferingKind::Subscription => {}
+    }
 
     // Update offering state
     offering.remaining_supply = offering.remaining_supply
         .checked_sub(tokens_to_receive)
         .ok_or(DefiTradingError::ArithmeticUnderflow)?;
     offering.total_raised = offering.total_raised
-        .checked_add(amount)
-        .ok_or(DefiTradingError::ArithmeticOverflow)?;
-    offering.participants_count = offering.participants_count
-        .checked_add(1)
+        .checked_add(cost)
         .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    if is_new_participant {
+        offering.participants_count = offering.participants_count
+            .checked_add(1)
+            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    }
 
     // Check if offering is completed
     if offering.remaining_supply == 0 {
         offering.status = OfferingStatus::Completed;
     }
 
+    let (round_tokens_sold, round_amount_raised) = match round_index {
+        Some(index) => {
+            let round = &mut offering.rounds[index];
+            round.tokens_sold = round.tokens_sold
+                .checked_add(tokens_to_receive)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            round.amount_raised = round.amount_raised
+                .checked_add(cost)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            participation.round_invested[index] = participation.round_invested[index]
+                .checked_add(cost)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            (round.tokens_sold, round.amount_raised)
+        },
+        None => (0, 0),
+    };
+
     // Record participation
-    participation.user = ctx.accounts.user.key();
-    participation.offering_id = offering.id;
-    participation.company_id = offering.company_id;
-    participation.amount_invested = amount;
-    participation.tokens_received = tokens_to_receive;
-    participation.participated_at = clock.unix_timestamp;
-    participation.bump = ctx.bumps.participation;
+    if is_new_participant {
+        participation.user = ctx.accounts.user.key();
+        participation.offering_id = offering.id;
+        participation.company_id = offering.company_id;
+        participation.settled = false;
+        participation.participated_at = clock.unix_timestamp;
+        participation.bump = ctx.bumps.participation;
+    }
+    participation.amount_invested = total_invested;
+    participation.tokens_received = participation.tokens_received
+        .checked_add(tokens_to_receive)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    participation.purchases_count = participation.purchases_count
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    participation.last_participated_at = clock.unix_timestamp;
 
     emit!(OfferingParticipated {
         offering_id: offering.id,
         company_id: offering.company_id,
         user: ctx.accounts.user.key(),
-        amount_invested: amount,
+        amount_invested: cost,
         tokens_received: tokens_to_receive,
+        total_invested: participation.amount_invested,
+        total_tokens_received: participation.tokens_received,
+        locked_amount: participation.locked_amount,
+        round_index: round_index.map(|index| index as u8),
+        round_tokens_sold,
+        round_amount_raised,
         timestamp: clock.unix_timestamp,
     });
 
     Ok(())
 }
 
-#[derive(Accounts)]
-pub struct CreateTokenOffering<'info> {
-    #[account(
-        mut,
-        seeds = [b"platform"],
-        bump = platform.bump
-    )]
-    pub platform: Account<'info, Platform>,
-    
-    #[account(
-        mut,
-        seeds = [b"company", company.id.to_le_bytes().as_ref()],
-        bump = company.bump
-    )]
-    pub company: Account<'info, Company>,
-    
-    #[account(
-        init,
-        payer = authority,
-        space = TokenOffering::LEN,
-        seeds = [
-            b"token_offering",
-            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub token_offering: Account<'info, TokenOffering>,
-    
-    #[account(
-        mut,
-        seeds = [b"token_mint", company.id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub token_mint: Account<'info, Mint>,
-    
-    #[account(
-        init,
-        payer = authority,
-        token::mint = token_mint,
-        token::authority = token_offering,
-        seeds = [
-            b"offering_tokens",
-            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub offering_token_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+// Append a presale round (e.g. allowlist presale, then public) to a
+// fixed-price offering before it opens
+pub fn add_offering_round(
+    ctx: Context<AddOfferingRound>,
+    price_per_token: u64,
+    round_start: i64,
+    round_end: i64,
+    max_per_wallet: u64,
+    allowlist_root: [u8; 32],
+) -> Result<()> {
+    let offering = &mut ctx.accounts.token_offering;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == offering.company_authority,
+        DefiTradingError::Unauthorized
+    );
+    require!(
+        offering.kind == OfferingKind::FixedPrice,
+        DefiTradingError::InvalidOfferingKind
+    );
+    require!(
+        clock.unix_timestamp < offering.offering_start,
+        DefiTradingError::InvalidTimestamp
+    );
+    require!(
+        offering.rounds.len() < TokenOffering::MAX_ROUNDS,
+        DefiTradingError::TooManyOfferingRounds
+    );
+    require!(price_per_token > 0, DefiTradingError::InvalidOfferingParams);
+    require!(round_end > round_start, DefiTradingError::InvalidTimestamp);
+    require!(
+        round_start >= offering.offering_start && round_end <= offering.offering_end,
+        DefiTradingError::InvalidTimestamp
+    );
+    // Rounds run back to back in the order they were added
+    if let Some(previous) = offering.rounds.last() {
+        require!(round_start > previous.round_end, DefiTradingError::InvalidTimestamp);
+    }
+
+    offering.rounds.push(OfferingRound {
+        price_per_token,
+        round_start,
+        round_end,
+        max_per_wallet,
+        allowlist_root,
+        tokens_sold: 0,
+        amount_raised: 0,
+    });
+
+    emit!(OfferingRoundAdded {
+        offering_id: offering.id,
+        round_index: (offering.rounds.len() - 1) as u8,
+        price_per_token,
+        round_start,
+        round_end,
+        max_per_wallet,
+        allowlist_root,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct ParticipateInOffering<'info> {
-    #[account(
-        mut,
-        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
-        bump = token_offering.bump
-    )]
-    pub token_offering: Account<'info, TokenOffering>,
-    
-    #[account(
-        init,
-        payer = user,
-        space = OfferingParticipation::LEN,
-        seeds = [
-            b"participation",
-            user.key().as_ref(),
-            token_offering.id.to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub participation: Account<'info, OfferingParticipation>,
-    
-    #[account(
-        mut,
-        seeds = [
-            b"offering_tokens",
-            token_offering.id.to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub offering_token_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub user_payment_account: Account<'info, TokenAccount>,
+// Leaves are sha256(wallet); each level hashes the sorted pair of nodes
+fn verify_allowlist_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let mut computed = leaf;
+    for node in proof.iter() {
+        computed = if computed <= *node {
+            hashv(&[&computed, node]).to_bytes()
+        } else {
+            hashv(&[node, &computed]).to_bytes()
+        };
+    }
+    computed == root
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_dutch_auction_offering(
+    ctx: Context<CreateDutchAuctionOffering>,
+    total_supply: u64,
+    start_price: u64,
+    floor_price: u64,
+    min_investment: u64,
+    max_investment: u64,
+    offering_start: i64,
+    offering_end: i64,
+) -> Result<()> {
+    require!(floor_price > 0, DefiTradingError::InvalidOfferingParams);
+    require!(start_price > floor_price, DefiTradingError::InvalidOfferingParams);
+
+    init_offering(
+        &mut ctx.accounts.platform,
+        &mut ctx.accounts.company,
+        &mut ctx.accounts.token_offering,
+        ctx.accounts.authority.key(),
+        ctx.accounts.token_mint.decimals,
+        OfferingTerms {
+            kind: OfferingKind::DutchAuction,
+            total_supply,
+            price_per_token: start_price,
+            floor_price,
+            payment_vault: ctx.accounts.offering_payment_vault.key(),
+            min_investment,
+            max_investment,
+            offering_start,
+            offering_end,
+        },
+        ctx.bumps.token_offering,
+    )?;
+    mint_offering_supply(
+        &ctx.accounts.company,
+        ctx.accounts.token_mint.to_account_info(),
+        ctx.accounts.offering_token_account.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        total_supply,
+    )?;
+
+    let offering = &ctx.accounts.token_offering;
+    emit!(DutchAuctionCreated {
+        offering_id: offering.id,
+        company_id: offering.company_id,
+        company_authority: offering.company_authority,
+        token_mint: offering.token_mint,
+        payment_mint: ctx.accounts.payment_mint.key(),
+        total_supply,
+        start_price,
+        floor_price,
+        offering_start,
+        offering_end,
+        timestamp: offering.created_at,
+    });
+
+    Ok(())
+}
+
+// Deliver an investor's auction allocation and refund the difference between
+// what they paid and the final clearing price
+pub fn claim_auction_tokens(ctx: Context<ClaimAuctionTokens>) -> Result<()> {
+    let offering_account_info = ctx.accounts.token_offering.to_account_info();
+
+    let offering = &ctx.accounts.token_offering;
+    let participation = &mut ctx.accounts.participation;
+    let clock = Clock::get()?;
+
+    require!(
+        offering.kind == OfferingKind::DutchAuction,
+        DefiTradingError::InvalidOfferingKind
+    );
+    require!(
+        offering.status != OfferingStatus::Cancelled,
+        DefiTradingError::OfferingCancelled
+    );
+    require!(
+        offering.status == OfferingStatus::Completed || clock.unix_timestamp > offering.offering_end,
+        DefiTradingError::OfferingNotFinalized
+    );
+    require!(!participation.settled, DefiTradingError::AlreadyClaimed);
+    require!(
+        participation.user == ctx.accounts.user.key(),
+        DefiTradingError::Unauthorized
+    );
+
+    let clearing_price = offering.clearing_price;
+    let tokens_to_receive = participation.tokens_received;
+    let settled_cost = quote_for_base(
+        tokens_to_receive,
+        clearing_price,
+        offering.base_decimals,
+        Rounding::Up,
+    )?;
+    let refund_amount = participation.amount_invested
+        .checked_sub(settled_cost)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+
+    let offering_id_bytes = offering.id.to_le_bytes();
+    let seeds = &[
+        b"token_offering",
+        offering_id_bytes.as_ref(),
+        &[offering.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let destination = offering_token_destination(
+        offering,
+        ctx.accounts.user_token_account.to_account_info(),
+        ctx.accounts.offering_lock_account.as_ref().map(|lock| lock.to_account_info()),
+    )?;
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.offering_token_account.to_account_info(),
+        to: destination,
+        authority: offering_account_info.clone(),
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
     
-    #[account(mut)]
+    token::transfer(cpi_ctx, tokens_to_receive)?;
+
+    if offering.lockup_duration > 0 {
+        record_locked_tokens(participation, ctx.accounts.holding.as_mut(), tokens_to_receive)?;
+    }
+
+    return_payment(
+        &ctx.accounts.offering_payment_vault,
+        offering_account_info,
+        signer,
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.user_payment_account.as_ref(),
+        ctx.accounts.temp_wsol_account.as_ref(),
+        ctx.accounts.token_program.to_account_info(),
+        refund_amount,
+    )?;
+
+    participation.settled = true;
+
+    emit!(AuctionTokensClaimed {
+        offering_id: offering.id,
+        user: participation.user,
+        tokens_received: tokens_to_receive,
+        clearing_price,
+        refund_amount,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn create_subscription_offering(
+    ctx: Context<CreateSubscriptionOffering>,
+    total_supply: u64,
+    price_per_token: u64,
+    min_investment: u64,
+    max_investment: u64,
+    offering_start: i64,
+    offering_end: i64,
+) -> Result<()> {
+    require!(price_per_token > 0, DefiTradingError::InvalidOfferingParams);
+
+    init_offering(
+        &mut ctx.accounts.platform,
+        &mut ctx.accounts.company,
+        &mut ctx.accounts.token_offering,
+        ctx.accounts.authority.key(),
+        ctx.accounts.token_mint.decimals,
+        OfferingTerms {
+            kind: OfferingKind::Subscription,
+            total_supply,
+            price_per_token,
+            floor_price: 0,
+            payment_vault: ctx.accounts.offering_payment_vault.key(),
+            min_investment,
+            max_investment,
+            offering_start,
+            offering_end,
+        },
+        ctx.bumps.token_offering,
+    )?;
+    mint_offering_supply(
+        &ctx.accounts.company,
+        ctx.accounts.token_mint.to_account_info(),
+        ctx.accounts.offering_token_account.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        total_supply,
+    )?;
+
+    let offering = &ctx.accounts.token_offering;
+    emit!(SubscriptionOfferingCreated {
+        offering_id: offering.id,
+        company_id: offering.company_id,
+        company_authority: offering.company_authority,
+        token_mint: offering.token_mint,
+        payment_mint: ctx.accounts.payment_mint.key(),
+        total_supply,
+        price_per_token,
+        offering_start,
+        offering_end,
+        timestamp: offering.created_at,
+    });
+
+    Ok(())
+}
+
+// Allocate an investor's share of a subscription offering. If commitments
+// exceed supply, tokens are split pro-rata to the amount committed and the
+// unallocated funds are refunded
+pub fn claim_allocation(ctx: Context<ClaimAllocation>) -> Result<()> {
+    let offering_account_info = ctx.accounts.token_offering.to_account_info();
+
+    let offering = &mut ctx.accounts.token_offering;
+    let participation = &mut ctx.accounts.participation;
+    let clock = Clock::get()?;
+
+    require!(
+        offering.kind == OfferingKind::Subscription,
+        DefiTradingError::InvalidOfferingKind
+    );
+    require!(
+        offering.status != OfferingStatus::Cancelled,
+        DefiTradingError::OfferingCancelled
+    );
+    require!(
+        clock.unix_timestamp > offering.offering_end,
+        DefiTradingError::OfferingNotFinalized
+    );
+    require!(!participation.settled, DefiTradingError::AlreadyClaimed);
+    require!(
+        participation.user == ctx.accounts.user.key(),
+        DefiTradingError::Unauthorized
+    );
+
+    offering.settle_subscription()?;
+
+    // Each investor gets their pro-rata share of the allocated tokens and
+    // pays the same share of the settled proceeds, rounded in the platform's
+    // favor so the claims never draw more than the vault holds
+    let committed = participation.amount_invested;
+    let tokens_allocated = pro_rata_share(
+        offering.subscription_allocated()?,
+        committed,
+        offering.total_raised,
+        Rounding::Down,
+    )?;
+    let settled_cost = pro_rata_share(
+        offering.settled_proceeds,
+        committed,
+        offering.total_raised,
+        Rounding::Up,
+    )?;
+    let refund_amount = committed
+        .checked_sub(settled_cost)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+
+    let offering_id_bytes = offering.id.to_le_bytes();
+    let offering_bump = offering.bump;
+    let seeds = &[
+        b"token_offering",
+        offering_id_bytes.as_ref(),
+        &[offering_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    if tokens_allocated > 0 {
+        let destination = offering_token_destination(
+            offering,
+            ctx.accounts.user_token_account.to_account_info(),
+            ctx.accounts.offering_lock_account.as_ref().map(|lock| lock.to_account_info()),
+        )?;
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.offering_token_account.to_account_info(),
+            to: destination,
+            authority: offering_account_info.clone(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        
+        token::transfer(cpi_ctx, tokens_allocated)?;
+
+        if offering.lockup_duration > 0 {
+            record_locked_tokens(participation, ctx.accounts.holding.as_mut(), tokens_allocated)?;
+        }
+    }
+
+    return_payment(
+        &ctx.accounts.offering_payment_vault,
+        offering_account_info,
+        signer,
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.user_payment_account.as_ref(),
+        ctx.accounts.temp_wsol_account.as_ref(),
+        ctx.accounts.token_program.to_account_info(),
+        refund_amount,
+    )?;
+
+    offering.remaining_supply = offering.remaining_supply
+        .checked_sub(tokens_allocated)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+
+    participation.tokens_received = tokens_allocated;
+    participation.settled = true;
+
+    emit!(AllocationClaimed {
+        offering_id: offering.id,
+        user: participation.user,
+        amount_committed: committed,
+        tokens_allocated,
+        refund_amount,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Company withdraws an offering's proceeds from the payment vault once it is finalized
+pub fn withdraw_offering_proceeds(ctx: Context<WithdrawOfferingProceeds>) -> Result<()> {
+    let offering_account_info = ctx.accounts.token_offering.to_account_info();
+
+    let offering = &mut ctx.accounts.token_offering;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == offering.company_authority,
+        DefiTradingError::Unauthorized
+    );
+    require!(
+        offering.status != OfferingStatus::Cancelled,
+        DefiTradingError::OfferingCancelled
+    );
+    require!(
+        offering.status == OfferingStatus::Completed || clock.unix_timestamp > offering.offering_end,
+        DefiTradingError::OfferingNotFinalized
+    );
+
+    // Fixed-price purchases are paid in full, auction costs are fixed by the
+    // clearing price and subscription proceeds by the allocation made when
+    // the offering settles
+    let total_proceeds = match offering.kind {
+        OfferingKind::FixedPrice => offering.total_raised,
+        OfferingKind::DutchAuction => {
+            let tokens_sold = offering.total_supply
+                .checked_sub(offering.remaining_supply)
+                .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+            quote_for_base(
+                tokens_sold,
+                offering.clearing_price,
+                offering.base_decimals,
+                Rounding::Down,
+            )?
+        },
+        OfferingKind::Subscription => {
+            offering.settle_subscription()?;
+            offering.settled_proceeds
+        },
+    };
+    let amount = total_proceeds
+        .checked_sub(offering.proceeds_withdrawn)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+    require!(amount > 0, DefiTradingError::InsufficientFunds);
+
+    let offering_id_bytes = offering.id.to_le_bytes();
+    let seeds = &[
+        b"token_offering",
+        offering_id_bytes.as_ref(),
+        &[offering.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.offering_payment_vault.to_account_info(),
+        to: ctx.accounts.proceeds_account.to_account_info(),
+        authority: offering_account_info,
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    
+    token::transfer(cpi_ctx, amount)?;
+
+    offering.proceeds_withdrawn = total_proceeds;
+
+    emit!(OfferingProceedsWithdrawn {
+        offering_id: offering.id,
+        company_id: offering.company_id,
+        amount,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Cancel an offering. Before it opens the minted supply is simply burned;
+// once it has started, unsold supply is burned and investors whose funds are
+// held in the payment vault reclaim them with claim_offering_refund.
+pub fn cancel_offering(ctx: Context<CancelOffering>) -> Result<()> {
+    let offering_account_info = ctx.accounts.token_offering.to_account_info();
+
+    let offering = &mut ctx.accounts.token_offering;
+    let company = &mut ctx.accounts.company;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == offering.company_authority,
+        DefiTradingError::Unauthorized
+    );
+    require!(
+        offering.status == OfferingStatus::Pending || offering.status == OfferingStatus::Active,
+        DefiTradingError::OfferingNotActive
+    );
+    require!(
+        clock.unix_timestamp <= offering.offering_end,
+        DefiTradingError::OfferingEnded
+    );
+
+    let started = clock.unix_timestamp >= offering.offering_start;
+    let refund_mode = started
+        && offering.total_raised > 0
+        && offering.payment_vault != Pubkey::default();
+    let tokens_burned = ctx.accounts.offering_token_account.amount;
+
+    if tokens_burned > 0 {
+        let offering_id_bytes = offering.id.to_le_bytes();
+        let seeds = &[
+            b"token_offering",
+            offering_id_bytes.as_ref(),
+            &[offering.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.token_mint.to_account_info(),
+            from: ctx.accounts.offering_token_account.to_account_info(),
+            authority: offering_account_info,
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        
+        token::burn(cpi_ctx, tokens_burned)?;
+
+        company.total_supply = company.total_supply
+            .checked_sub(tokens_burned)
+            .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+    }
+
+    offering.remaining_supply = 0;
+    offering.status = OfferingStatus::Cancelled;
+
+    emit!(OfferingCancelled {
+        offering_id: offering.id,
+        company_id: offering.company_id,
+        tokens_burned,
+        refund_mode,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Return an investor's vaulted funds after their offering was cancelled.
+// Tokens already delivered by a fixed-price offering are burned in exchange.
+pub fn claim_offering_refund(ctx: Context<ClaimOfferingRefund>) -> Result<()> {
+    let offering_account_info = ctx.accounts.token_offering.to_account_info();
+
+    let offering = &ctx.accounts.token_offering;
+    let participation = &mut ctx.accounts.participation;
+
+    require!(
+        offering.status == OfferingStatus::Cancelled,
+        DefiTradingError::OfferingNotCancelled
+    );
+    require!(!participation.settled, DefiTradingError::AlreadyClaimed);
+    require!(
+        participation.user == ctx.accounts.user.key(),
+        DefiTradingError::Unauthorized
+    );
+
+    let refund_amount = participation.amount_invested;
+
+    let offering_id_bytes = offering.id.to_le_bytes();
+    let seeds = &[
+        b"token_offering",
+        offering_id_bytes.as_ref(),
+        &[offering.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let tokens_burned = participation.tokens_received;
+    if tokens_burned > 0 {
+        let token_mint = ctx.accounts.token_mint.as_ref()
+            .ok_or(DefiTradingError::RefundTokensRequired)?;
+        let company = ctx.accounts.company.as_mut()
+            .ok_or(DefiTradingError::RefundTokensRequired)?;
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+
+        // Locked tokens are still held by the offering
+        let locked_amount = participation.locked_amount;
+        if locked_amount > 0 {
+            let lock_account = ctx.accounts.offering_lock_account.as_ref()
+                .ok_or(DefiTradingError::LockAccountRequired)?;
+            let cpi_accounts = Burn {
+                mint: token_mint.to_account_info(),
+                from: lock_account.to_account_info(),
+                authority: offering_account_info.clone(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer);
+            token::burn(cpi_ctx, locked_amount)?;
+
+            // The burned tokens leave the holding along with their lock
+            participation.locked_amount = 0;
+            if let Some(holding) = ctx.accounts.holding.as_mut() {
+                holding.amount = holding.amount.saturating_sub(locked_amount);
+                holding.locked_amount = holding.locked_amount.saturating_sub(locked_amount);
+            }
+        }
+
+        let unlocked_amount = tokens_burned
+            .checked_sub(locked_amount)
+            .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+        if unlocked_amount > 0 {
+            let user_token_account = ctx.accounts.user_token_account.as_ref()
+                .ok_or(DefiTradingError::RefundTokensRequired)?;
+            let cpi_accounts = Burn {
+                mint: token_mint.to_account_info(),
+                from: user_token_account.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            token::burn(cpi_ctx, unlocked_amount)?;
+        }
+
+        company.total_supply = company.total_supply
+            .checked_sub(tokens_burned)
+            .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+    }
+
+    return_payment(
+        &ctx.accounts.offering_payment_vault,
+        offering_account_info,
+        signer,
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.user_payment_account.as_ref(),
+        ctx.accounts.temp_wsol_account.as_ref(),
+        ctx.accounts.token_program.to_account_info(),
+        refund_amount,
+    )?;
+
+    participation.settled = true;
+
+    emit!(OfferingRefunded {
+        offering_id: offering.id,
+        user: participation.user,
+        amount: refund_amount,
+        tokens_burned,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Amend a live offering. The end can only be extended, by at most
+// MAX_END_EXTENSION per amendment, and the price can only be lowered.
+// Dutch auctions cannot be amended, and offerings sold in rounds keep their
+// round prices.
+pub fn amend_offering(
+    ctx: Context<AmendOffering>,
+    new_offering_end: Option<i64>,
+    new_price_per_token: Option<u64>,
+) -> Result<()> {
+    let offering = &mut ctx.accounts.token_offering;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == offering.company_authority,
+        DefiTradingError::Unauthorized
+    );
+    require!(
+        offering.status == OfferingStatus::Pending || offering.status == OfferingStatus::Active,
+        DefiTradingError::OfferingNotActive
+    );
+    require!(
+        clock.unix_timestamp <= offering.offering_end,
+        DefiTradingError::OfferingEnded
+    );
+    require!(
+        new_offering_end.is_some() || new_price_per_token.is_some(),
+        DefiTradingError::InvalidOfferingAmendment
+    );
+
+    let old_offering_end = offering.offering_end;
+    let old_price_per_token = offering.price_per_token;
+
+    if let Some(new_end) = new_offering_end {
+        require!(new_end > offering.offering_end, DefiTradingError::InvalidOfferingAmendment);
+        require!(
+            new_end - offering.offering_end <= TokenOffering::MAX_END_EXTENSION,
+            DefiTradingError::InvalidOfferingAmendment
+        );
+        // Moving the end would change a Dutch auction's price curve mid-flight
+        require!(
+            offering.kind != OfferingKind::DutchAuction,
+            DefiTradingError::InvalidOfferingKind
+        );
+        offering.offering_end = new_end;
+    }
+
+    if let Some(new_price) = new_price_per_token {
+        require!(
+            offering.kind != OfferingKind::DutchAuction,
+            DefiTradingError::InvalidOfferingKind
+        );
+        require!(offering.rounds.is_empty(), DefiTradingError::InvalidOfferingAmendment);
+        require!(
+            new_price > 0 && new_price < offering.price_per_token,
+            DefiTradingError::InvalidOfferingAmendment
+        );
+        offering.price_per_token = new_price;
+    }
+
+    emit!(OfferingAmended {
+        offering_id: offering.id,
+        company_id: offering.company_id,
+        old_offering_end,
+        new_offering_end: offering.offering_end,
+        old_price_per_token,
+        new_price_per_token: offering.price_per_token,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Require purchased tokens to sit in a per-investor lock account until
+// `lockup_duration` seconds after the offering ends
+pub fn set_offering_lockup(ctx: Context<SetOfferingLockup>, lockup_duration: i64) -> Result<()> {
+    let offering = &mut ctx.accounts.token_offering;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == offering.company_authority,
+        DefiTradingError::Unauthorized
+    );
+    require!(
+        clock.unix_timestamp < offering.offering_start,
+        DefiTradingError::InvalidTimestamp
+    );
+    require!(
+        (0..=TokenOffering::MAX_LOCKUP_DURATION).contains(&lockup_duration),
+        DefiTradingError::InvalidOfferingParams
+    );
+
+    offering.lockup_duration = lockup_duration;
+
+    emit!(OfferingLockupSet {
+        offering_id: offering.id,
+        lockup_duration,
+        unlocks_at: offering.lockup_ends_at(),
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Create the investor's lock account for an offering with a lockup, and the
+// holding its locked tokens are recorded in if the investor has none yet
+pub fn open_offering_lock(ctx: Context<OpenOfferingLock>) -> Result<()> {
+    let offering = &ctx.accounts.token_offering;
+    require!(offering.lockup_duration > 0, DefiTradingError::InvalidOfferingParams);
+
+    let holding = &mut ctx.accounts.holding;
+    if holding.user == Pubkey::default() {
+        holding.user = ctx.accounts.user.key();
+        holding.company_id = offering.company_id;
+        holding.token_mint = offering.token_mint;
+        holding.amount = 0;
+        holding.locked_amount = 0;
+        holding.average_price = 0;
+        holding.total_invested = 0;
+        holding.current_value = 0;
+        holding.profit_loss = 0;
+        holding.last_updated = Clock::get()?.unix_timestamp;
+        holding.bump = ctx.bumps.holding;
+    }
+
+    Ok(())
+}
+
+// Release an investor's locked offering tokens once the lockup has elapsed
+pub fn unlock_offering_tokens(ctx: Context<UnlockOfferingTokens>) -> Result<()> {
+    let offering_account_info = ctx.accounts.token_offering.to_account_info();
+
+    let offering = &ctx.accounts.token_offering;
+    let participation = &mut ctx.accounts.participation;
+    let clock = Clock::get()?;
+
+    require!(
+        participation.user == ctx.accounts.user.key(),
+        DefiTradingError::Unauthorized
+    );
+    require!(
+        clock.unix_timestamp >= offering.lockup_ends_at(),
+        DefiTradingError::TokensStillLocked
+    );
+
+    let amount = participation.locked_amount;
+    require!(amount > 0, DefiTradingError::InsufficientTokens);
+
+    let offering_id_bytes = offering.id.to_le_bytes();
+    let seeds = &[
+        b"token_offering",
+        offering_id_bytes.as_ref(),
+        &[offering.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.offering_lock_account.to_account_info(),
+        to: ctx.accounts.user_token_account.to_account_info(),
+        authority: offering_account_info,
+    };
+    let cpi_program = ctx.accounts.token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    
+    token::transfer(cpi_ctx, amount)?;
+
+    participation.locked_amount = 0;
+    if let Some(holding) = ctx.accounts.holding.as_mut() {
+        holding.locked_amount = holding.locked_amount.saturating_sub(amount);
+    }
+
+    emit!(OfferingTokensUnlocked {
+        offering_id: offering.id,
+        user: participation.user,
+        amount,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Grows an offering created before offering kinds and the fields after
+// them. Legacy offerings were fixed-price, priced per token atom and paid
+// the platform directly, which is what the zeroed fields describe.
+pub fn migrate_offering(ctx: Context<MigrateOffering>, _offering_id: u64) -> Result<()> {
+    let offering_info = ctx.accounts.token_offering.to_account_info();
+
+    require!(
+        ctx.accounts.authority.key() == ctx.accounts.platform.authority,
+        DefiTradingError::Unauthorized
+    );
+    grow_legacy_account(
+        &offering_info,
+        &ctx.accounts.authority.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        TokenOffering::LEGACY_LEN,
+        TokenOffering::LEN,
+    )?;
+
+    // The new fields read as zero until they are set below
+    let mut offering = TokenOffering::try_deserialize(&mut &offering_info.try_borrow_data()?[..])?;
+    offering.base_decimals = LEGACY_PRICE_DECIMALS;
+    offering.kind = OfferingKind::FixedPrice;
+    offering.floor_price = 0;
+    offering.clearing_price = 0;
+    offering.payment_vault = Pubkey::default();
+    offering.settled_proceeds = 0;
+    offering.proceeds_withdrawn = 0;
+    offering.min_investment = 0;
+    offering.max_investment = 0;
+    offering.lockup_duration = 0;
+    offering.rounds = Vec::new();
+    offering.try_serialize(&mut &mut offering_info.try_borrow_mut_data()?[..])?;
+
+    emit!(OfferingMigrated {
+        offering_id: offering.id,
+        company_id: offering.company_id,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Grows a participation created before locked offering tokens and purchase
+// tracking. Anyone can pay for the migration; the new fields only get the
+// values a legacy participation implies.
+pub fn migrate_participation(
+    ctx: Context<MigrateParticipation>,
+    _user: Pubkey,
+    _offering_id: u64,
+) -> Result<()> {
+    let participation_info = ctx.accounts.participation.to_account_info();
+
+    grow_legacy_account(
+        &participation_info,
+        &ctx.accounts.payer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        OfferingParticipation::LEGACY_LEN,
+        OfferingParticipation::LEN,
+    )?;
+
+    // The new fields read as zero until they are set below. Legacy
+    // participations did not count purchases, so they count as one.
+    let mut participation = OfferingParticipation::try_deserialize(&mut &participation_info.try_borrow_data()?[..])?;
+    participation.locked_amount = 0;
+    participation.purchases_count = 1;
+    participation.round_invested = [0; TokenOffering::MAX_ROUNDS];
+    participation.settled = false;
+    participation.last_participated_at = participation.participated_at;
+    participation.try_serialize(&mut &mut participation_info.try_borrow_mut_data()?[..])?;
+
+    emit!(ParticipationMigrated {
+        offering_id: participation.offering_id,
+        user: participation.user,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Terms that differ between offering kinds
+struct OfferingTerms {
+    kind: OfferingKind,
+    total_supply: u64,
+    price_per_token: u64,
+    floor_price: u64,
+    payment_vault: Pubkey,
+    min_investment: u64,
+    max_investment: u64,
+    offering_start: i64,
+    offering_end: i64,
+}
+
+// Checks and records a new offering of any kind under the next offering id
+fn init_offering(
+    platform: &mut Platform,
+    company: &mut Company,
+    offering: &mut TokenOffering,
+    authority: Pubkey,
+    base_decimals: u8,
+    terms: OfferingTerms,
+    bump: u8,
+) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+
+    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(terms.total_supply > 0, DefiTradingError::InvalidOfferingParams);
+    require!(
+        terms.max_investment == 0 || terms.max_investment >= terms.min_investment,
+        DefiTradingError::InvalidOfferingParams
+    );
+    require!(terms.offering_start > now, DefiTradingError::InvalidTimestamp);
+    require!(terms.offering_end > terms.offering_start, DefiTradingError::InvalidTimestamp);
+    require!(authority == company.authority, DefiTradingError::Unauthorized);
+
+    let offering_id = platform.total_offerings
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+
+    offering.id = offering_id;
+    offering.company_id = company.id;
+    offering.company_authority = company.authority;
+    offering.token_mint = company.token_mint;
+    offering.base_decimals = base_decimals;
+    offering.kind = terms.kind;
+    offering.total_supply = terms.total_supply;
+    offering.remaining_supply = terms.total_supply;
+    offering.price_per_token = terms.price_per_token;
+    offering.floor_price = terms.floor_price;
+    offering.clearing_price = 0;
+    offering.payment_vault = terms.payment_vault;
+    offering.settled_proceeds = 0;
+    offering.proceeds_withdrawn = 0;
+    offering.min_investment = terms.min_investment;
+    offering.max_investment = terms.max_investment;
+    offering.offering_start = terms.offering_start;
+    offering.offering_end = terms.offering_end;
+    offering.total_raised = 0;
+    offering.participants_count = 0;
+    offering.status = OfferingStatus::Pending;
+    offering.rounds = Vec::new();
+    offering.lockup_duration = 0;
+    offering.created_at = now;
+    offering.bump = bump;
+
+    company.total_supply = company.total_supply
+        .checked_add(terms.total_supply)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+
+    platform.total_offerings = offering_id;
+
+    Ok(())
+}
+
+// Mints an offering's supply into its token account, signed by the company
+fn mint_offering_supply<'info>(
+    company: &Account<'info, Company>,
+    token_mint: AccountInfo<'info>,
+    offering_token_account: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let company_id_bytes = company.id.to_le_bytes();
+    let seeds = &[
+        b"company",
+        company_id_bytes.as_ref(),
+        &[company.bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    let cpi_accounts = MintTo {
+        mint: token_mint,
+        to: offering_token_account,
+        authority: company.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer);
+    
+    token::mint_to(cpi_ctx, amount)
+}
+
+// Purchased tokens go to the investor's lock account while a lockup applies
+fn offering_token_destination<'info>(
+    offering: &TokenOffering,
+    user_token_account: AccountInfo<'info>,
+    offering_lock_account: Option<AccountInfo<'info>>,
+) -> Result<AccountInfo<'info>> {
+    if offering.lockup_duration > 0 {
+        offering_lock_account.ok_or(error!(DefiTradingError::LockAccountRequired))
+    } else {
+        Ok(user_token_account)
+    }
+}
+
+// Locked tokens count towards the investor's holding, so the holding
+// opened alongside the lock account is required
+fn record_locked_tokens(
+    participation: &mut OfferingParticipation,
+    holding: Option<&mut Account<Holding>>,
+    amount: u64,
+) -> Result<()> {
+    let holding = holding.ok_or(DefiTradingError::HoldingNotFound)?;
+    participation.locked_amount = participation.locked_amount
+        .checked_add(amount)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    holding.amount = holding.amount
+        .checked_add(amount)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    holding.locked_amount = holding.locked_amount
+        .checked_add(amount)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CreateTokenOffering<'info> {
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"company", company.id.to_le_bytes().as_ref()],
+        bump = company.bump
+    )]
+    pub company: Account<'info, Company>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = TokenOffering::LEN,
+        seeds = [
+            b"token_offering",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        seeds = [b"token_mint", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = token_offering,
+        seeds = [
+            b"offering_tokens",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = payment_mint,
+        token::authority = token_offering,
+        seeds = [
+            b"offering_payment",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_payment_vault: Account<'info, TokenAccount>,
+    
+    pub payment_mint: Account<'info, Mint>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ParticipateInOffering<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = OfferingParticipation::LEN,
+        seeds = [
+            b"participation",
+            user.key().as_ref(),
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub participation: Account<'info, OfferingParticipation>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_tokens",
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_lock",
+            token_offering.id.to_le_bytes().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub offering_lock_account: Option<Account<'info, TokenAccount>>,
+    
+    // Required while the offering has a lockup
+    #[account(
+        mut,
+        seeds = [
+            b"holding",
+            user.key().as_ref(),
+            token_offering.company_id.to_le_bytes().as_ref()
+        ],
+        bump = holding.bump
+    )]
+    pub holding: Option<Account<'info, Holding>>,
+    
+    // Omit to pay in native SOL
+    #[account(mut)]
+    pub user_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(mut)]
     pub platform_payment_account: Account<'info, TokenAccount>,
     
     #[account(mut)]
@@ -295,4 +1464,723 @@ pub struct ParticipateInOffering<'info> {
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
     pub rent: Sysvar<'info, Rent>,
-}
\ No newline at end of file
+}
+
+#[derive(Accounts)]
+pub struct AddOfferingRound<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDutchAuctionOffering<'info> {
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"company", company.id.to_le_bytes().as_ref()],
+        bump = company.bump
+    )]
+    pub company: Account<'info, Company>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = TokenOffering::LEN,
+        seeds = [
+            b"token_offering",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        seeds = [b"token_mint", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = token_offering,
+        seeds = [
+            b"offering_tokens",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = payment_mint,
+        token::authority = token_offering,
+        seeds = [
+            b"offering_payment",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_payment_vault: Account<'info, TokenAccount>,
+    
+    pub payment_mint: Account<'info, Mint>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAuctionTokens<'info> {
+    #[account(
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"participation",
+            user.key().as_ref(),
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump = participation.bump
+    )]
+    pub participation: Account<'info, OfferingParticipation>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_tokens",
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = token_offering.payment_vault
+    )]
+    pub offering_payment_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == token_offering.token_mint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_lock",
+            token_offering.id.to_le_bytes().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub offering_lock_account: Option<Account<'info, TokenAccount>>,
+    
+    // Required while the offering has a lockup
+    #[account(
+        mut,
+        seeds = [
+            b"holding",
+            user.key().as_ref(),
+            token_offering.company_id.to_le_bytes().as_ref()
+        ],
+        bump = holding.bump
+    )]
+    pub holding: Option<Account<'info, Holding>>,
+    
+    // Omit, together with passing the temporary wSOL account, to be
+    // refunded in native SOL
+    #[account(
+        mut,
+        constraint = user_payment_account.mint == offering_payment_vault.mint
+    )]
+    pub user_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = native_mint,
+        token::authority = user,
+        seeds = [TEMP_WSOL_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CreateSubscriptionOffering<'info> {
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"company", company.id.to_le_bytes().as_ref()],
+        bump = company.bump
+    )]
+    pub company: Account<'info, Company>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = TokenOffering::LEN,
+        seeds = [
+            b"token_offering",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        seeds = [b"token_mint", company.id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = token_offering,
+        seeds = [
+            b"offering_tokens",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        token::mint = payment_mint,
+        token::authority = token_offering,
+        seeds = [
+            b"offering_payment",
+            platform.total_offerings.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_payment_vault: Account<'info, TokenAccount>,
+    
+    pub payment_mint: Account<'info, Mint>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAllocation<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"participation",
+            user.key().as_ref(),
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump = participation.bump
+    )]
+    pub participation: Account<'info, OfferingParticipation>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_tokens",
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = token_offering.payment_vault
+    )]
+    pub offering_payment_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == token_offering.token_mint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_lock",
+            token_offering.id.to_le_bytes().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub offering_lock_account: Option<Account<'info, TokenAccount>>,
+    
+    // Required while the offering has a lockup
+    #[account(
+        mut,
+        seeds = [
+            b"holding",
+            user.key().as_ref(),
+            token_offering.company_id.to_le_bytes().as_ref()
+        ],
+        bump = holding.bump
+    )]
+    pub holding: Option<Account<'info, Holding>>,
+    
+    // Omit, together with passing the temporary wSOL account, to be
+    // refunded in native SOL
+    #[account(
+        mut,
+        constraint = user_payment_account.mint == offering_payment_vault.mint
+    )]
+    pub user_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = native_mint,
+        token::authority = user,
+        seeds = [TEMP_WSOL_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawOfferingProceeds<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        address = token_offering.payment_vault
+    )]
+    pub offering_payment_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = proceeds_account.mint == offering_payment_vault.mint
+    )]
+    pub proceeds_account: Account<'info, TokenAccount>,
+    
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOffering<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        seeds = [b"company", token_offering.company_id.to_le_bytes().as_ref()],
+        bump = company.bump
+    )]
+    pub company: Account<'info, Company>,
+    
+    #[account(
+        mut,
+        address = token_offering.token_mint
+    )]
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_tokens",
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub offering_token_account: Account<'info, TokenAccount>,
+    
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimOfferingRefund<'info> {
+    #[account(
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"participation",
+            user.key().as_ref(),
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump = participation.bump
+    )]
+    pub participation: Account<'info, OfferingParticipation>,
+    
+    #[account(
+        mut,
+        address = token_offering.payment_vault
+    )]
+    pub offering_payment_vault: Account<'info, TokenAccount>,
+    
+    // Required when the investor received fixed-price tokens, which are
+    // burned in exchange for the refund
+    #[account(
+        mut,
+        seeds = [b"company", token_offering.company_id.to_le_bytes().as_ref()],
+        bump = company.bump
+    )]
+    pub company: Option<Account<'info, Company>>,
+    
+    #[account(
+        mut,
+        address = token_offering.token_mint
+    )]
+    pub token_mint: Option<Account<'info, Mint>>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == token_offering.token_mint
+    )]
+    pub user_token_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_lock",
+            token_offering.id.to_le_bytes().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub offering_lock_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"holding",
+            user.key().as_ref(),
+            token_offering.company_id.to_le_bytes().as_ref()
+        ],
+        bump = holding.bump
+    )]
+    pub holding: Option<Account<'info, Holding>>,
+    
+    // Omit, together with passing the temporary wSOL account, to be
+    // refunded in native SOL
+    #[account(
+        mut,
+        constraint = user_payment_account.mint == offering_payment_vault.mint
+    )]
+    pub user_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = native_mint,
+        token::authority = user,
+        seeds = [TEMP_WSOL_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AmendOffering<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetOfferingLockup<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OpenOfferingLock<'info> {
+    #[account(
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(address = token_offering.token_mint)]
+    pub token_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = token_mint,
+        token::authority = token_offering,
+        seeds = [
+            b"offering_lock",
+            token_offering.id.to_le_bytes().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub offering_lock_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = Holding::LEN,
+        seeds = [
+            b"holding",
+            user.key().as_ref(),
+            token_offering.company_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub holding: Account<'info, Holding>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct UnlockOfferingTokens<'info> {
+    #[account(
+        seeds = [b"token_offering", token_offering.id.to_le_bytes().as_ref()],
+        bump = token_offering.bump
+    )]
+    pub token_offering: Account<'info, TokenOffering>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"participation",
+            user.key().as_ref(),
+            token_offering.id.to_le_bytes().as_ref()
+        ],
+        bump = participation.bump
+    )]
+    pub participation: Account<'info, OfferingParticipation>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"offering_lock",
+            token_offering.id.to_le_bytes().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub offering_lock_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == token_offering.token_mint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"holding",
+            user.key().as_ref(),
+            token_offering.company_id.to_le_bytes().as_ref()
+        ],
+        bump = holding.bump
+    )]
+    pub holding: Option<Account<'info, Holding>>,
+    
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(offering_id: u64)]
+pub struct MigrateOffering<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    /// CHECK: Old offering layout, checked and rewritten in migrate_offering
+    #[account(
+        mut,
+        seeds = [b"token_offering", offering_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub token_offering: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey, offering_id: u64)]
+pub struct MigrateParticipation<'info> {
+    /// CHECK: Old participation layout, checked and rewritten in migrate_participation
+    #[account(
+        mut,
+        seeds = [
+            b"participation",
+            user.as_ref(),
+            offering_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub participation: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(wallet: &Pubkey) -> [u8; 32] {
+        hashv(&[wallet.as_ref()]).to_bytes()
+    }
+
+    fn parent(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        if a <= b {
+            hashv(&[&a, &b]).to_bytes()
+        } else {
+            hashv(&[&b, &a]).to_bytes()
+        }
+    }
+
+    // Four-wallet allowlist: its leaves, the two inner nodes and the root
+    fn allowlist() -> (Vec<[u8; 32]>, [u8; 32], [u8; 32], [u8; 32]) {
+        let leaves: Vec<[u8; 32]> = (1..=4u8)
+            .map(|byte| leaf(&Pubkey::new_from_array([byte; 32])))
+            .collect();
+        let left = parent(leaves[0], leaves[1]);
+        let right = parent(leaves[2], leaves[3]);
+        (leaves, left, right, parent(left, right))
+    }
+
+    #[test]
+    fn allowlist_proofs_hash_sorted_pairs() {
+        let (leaves, left, right, root) = allowlist();
+
+        // every wallet proves membership with its sibling and the other half,
+        // whichever side of each pair it hashes on
+        assert!(verify_allowlist_proof(&[leaves[1], right], root, leaves[0]));
+        assert!(verify_allowlist_proof(&[leaves[0], right], root, leaves[1]));
+        assert!(verify_allowlist_proof(&[leaves[3], left], root, leaves[2]));
+        assert!(verify_allowlist_proof(&[leaves[2], left], root, leaves[3]));
+        // a single-wallet allowlist is its own root
+        assert!(verify_allowlist_proof(&[], leaves[0], leaves[0]));
+    }
+
+    #[test]
+    fn allowlist_rejects_outsiders_and_bad_proofs() {
+        let (leaves, _, right, root) = allowlist();
+
+        let outsider = leaf(&Pubkey::new_from_array([9; 32]));
+        assert!(!verify_allowlist_proof(&[leaves[1], right], root, outsider));
+        // the right nodes in the wrong order, or a truncated proof
+        assert!(!verify_allowlist_proof(&[right, leaves[1]], root, leaves[0]));
+        assert!(!verify_allowlist_proof(&[leaves[1]], root, leaves[0]));
+        assert!(!verify_allowlist_proof(&[right], root, leaves[0]));
+    }
+}
@@ -1,129 +1,14 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_spl::token::{self, spl_token::native_mint, Token, TokenAccount, Mint, Transfer};
 use crate::state::*;
 use crate::errors::*;
 use crate::events::*;
+use crate::pricing::*;
+use crate::native_sol::*;
 
-pub fn create_sell_order(
-    ctx: Context<CreateSellOrder>,
-    amount: u64,
-    price: u64,
-) -> Result<()> {
-    let platform = &mut ctx.accounts.platform;
-    let order = &mut ctx.accounts.order;
-    
-    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
-    require!(amount > 0, DefiTradingError::InvalidOrderParams);
-    require!(price > 0, DefiTradingError::InvalidOrderParams);
-    
-    // Check if user has enough tokens
-    require!(
-        ctx.accounts.user_token_account.amount >= amount,
-        DefiTradingError::InsufficientTokens
-    );
-
-    let order_id = platform.total_trades + 1; // Using total_trades as order counter
-    
-    order.id = order_id;
-    order.user = ctx.accounts.user.key();
-    order.company_id = ctx.accounts.company.id;
-    order.token_mint = ctx.accounts.company.token_mint;
-    order.order_type = OrderType::Sell;
-    order.amount = amount;
-    order.remaining_amount = amount;
-    order.price = price;
-    order.status = OrderStatus::Active;
-    order.created_at = Clock::get()?.unix_timestamp;
-    order.filled_at = None;
-    order.bump = ctx.bumps.order;
-
-    // Escrow tokens from user
-    let cpi_accounts = Transfer {
-        from: ctx.accounts.user_token_account.to_account_info(),
-        to: ctx.accounts.order_escrow_account.to_account_info(),
-        authority: ctx.accounts.user.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-    
-    token::transfer(cpi_ctx, amount)?;
-
-    emit!(OrderCreated {
-        order_id,
-        user: order.user,
-        company_id: order.company_id,
-        token_mint: order.token_mint,
-        order_type: "Sell".to_string(),
-        amount,
-        price,
-        timestamp: order.created_at,
-    });
-
-    Ok(())
-}
-
-pub fn create_buy_order(
-    ctx: Context<CreateBuyOrder>,
-    amount: u64,
-    price: u64,
-) -> Result<()> {
-    let platform = &mut ctx.accounts.platform;
-    let order = &mut ctx.accounts.order;
-    
-    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
-    require!(amount > 0, DefiTradingError::InvalidOrderParams);
-    require!(price > 0, DefiTradingError::InvalidOrderParams);
-    
-    let total_cost = amount
-        .checked_mul(price)
-        .ok_or(DefiTradingError::ArithmeticOverflow)?;
-    
-    // Check if user has enough payment tokens
-    require!(
-        ctx.accounts.user_payment_account.amount >= total_cost,
-        DefiTradingError::InsufficientFunds
-    );
-
-    let order_id = platform.total_trades + 1;
-    
-    order.id = order_id;
-    order.user = ctx.accounts.user.key();
-    order.company_id = ctx.accounts.company.id;
-    order.token_mint = ctx.accounts.company.token_mint;
-    order.order_type = OrderType::Buy;
-    order.amount = amount;
-    order.remaining_amount = amount;
-    order.price = price;
-    order.status = OrderStatus::Active;
-    order.created_at = Clock::get()?.unix_timestamp;
-    order.filled_at = None;
-    order.bump = ctx.bumps.order;
-
-    // Escrow payment from user
-    let cpi_accounts = Transfer {
-        from: ctx.accounts.user_payment_account.to_account_info(),
-        to: ctx.accounts.order_escrow_account.to_account_info(),
-        authority: ctx.accounts.user.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-    
-    token::transfer(cpi_ctx, total_cost)?;
-
-    emit!(OrderCreated {
-        order_id,
-        user: order.user,
-        company_id: order.company_id,
-        token_mint: order.token_mint,
-        order_type: "Buy".to_string(),
-        amount,
-        price,
-        timestamp: order.created_at,
-    });
-
-    Ok(())
-}
-
+// Legacy trading only drains orders opened before the market instructions:
+// they can still be executed against each other, reduced or cancelled, but
+// no new ones are opened.
 pub fn execute_trade(
     ctx: Context<ExecuteTrade>,
     sell_order_id: u64,
@@ -151,19 +36,26 @@ pub fn execute_trade(
     require!(amount <= buy_order.remaining_amount, DefiTradingError::InvalidTradeAmount);
 
     let trade_price = sell_order.price; // Use sell order price
-    let total_value = amount
-        .checked_mul(trade_price)
-        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let total_value = quote_for_base(amount, trade_price, LEGACY_PRICE_DECIMALS, Rounding::Down)?;
     
-    let platform_fee = total_value
-        .checked_mul(platform.platform_fee as u64)
-        .ok_or(DefiTradingError::ArithmeticOverflow)?
-        .checked_div(10000)
-        .ok_or(DefiTradingError::DivisionByZero)?;
+    let platform_fee = fee_amount(total_value, platform.platform_fee)?;
     
     let seller_proceeds = total_value
         .checked_sub(platform_fee)
         .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+    
+    // The buy escrow was locked at the buy price; whatever the fill saved
+    // goes straight back to the buyer
+    let buy_remaining_after = buy_order.remaining_amount
+        .checked_sub(amount)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+    let price_improvement = buy_escrow_refund(
+        ctx.accounts.buy_order_escrow.amount,
+        total_value,
+        buy_remaining_after,
+        buy_order.price,
+        LEGACY_PRICE_DECIMALS,
+    )?;
 
     let trade_id = platform.total_trades + 1;
     // Store values before borrowing
@@ -234,12 +126,33 @@ pub fn execute_trade(
     let cpi_accounts = Transfer {
         from: ctx.accounts.buy_order_escrow.to_account_info(),
         to: ctx.accounts.platform_fee_account.to_account_info(),
-        authority: buy_order_info,
+        authority: buy_order_info.clone(),
     };
     let cpi_program = ctx.accounts.token_program.to_account_info();
     let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, buy_signer);
     
     token::transfer(cpi_ctx, platform_fee)?;
+    
+    // Refund price improvement to the buyer
+    if price_improvement > 0 {
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.buy_order_escrow.to_account_info(),
+            to: ctx.accounts.buyer_payment_account.to_account_info(),
+            authority: buy_order_info,
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, buy_signer);
+        
+        token::transfer(cpi_ctx, price_improvement)?;
+        
+        emit!(PriceImprovementRefunded {
+            order_id: buy_order_id,
+            user: buy_order_user,
+            company_id: buy_order.company_id,
+            amount: price_improvement,
+            timestamp: trade.executed_at,
+        });
+    }
 
     // Update orders
     sell_order.remaining_amount = sell_order.remaining_amount
@@ -311,17 +224,16 @@ pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
     
     let remaining_balance = ctx.accounts.order_escrow_account.amount;
     
-    if remaining_balance > 0 {
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.order_escrow_account.to_account_info(),
-            to: ctx.accounts.user_account.to_account_info(),
-            authority: order_info,
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        
-        token::transfer(cpi_ctx, remaining_balance)?;
-    }
+    return_payment(
+        &ctx.accounts.order_escrow_account,
+        order_info,
+        signer,
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.user_account.as_ref(),
+        ctx.accounts.temp_wsol_account.as_ref(),
+        ctx.accounts.token_program.to_account_info(),
+        remaining_balance,
+    )?;
 
     order.status = OrderStatus::Cancelled;
 
@@ -335,117 +247,86 @@ pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
     Ok(())
 }
 
-#[derive(Accounts)]
-pub struct CreateSellOrder<'info> {
-    #[account(
-        mut,
-        seeds = [b"platform"],
-        bump = platform.bump
-    )]
-    pub platform: Account<'info, Platform>,
-    
-    #[account(
-        seeds = [b"company", company.id.to_le_bytes().as_ref()],
-        bump = company.bump
-    )]
-    pub company: Account<'info, Company>,
-    
-    #[account(
-        init,
-        payer = user,
-        space = Order::LEN,
-        seeds = [
-            b"order",
-            user.key().as_ref(),
-            (platform.total_trades + 1).to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub order: Account<'info, Order>,
-    
-    #[account(
-        init,
-        payer = user,
-        token::mint = token_mint,
-        token::authority = order,
-        seeds = [
-            b"order_escrow",
-            user.key().as_ref(),
-            (platform.total_trades + 1).to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub order_escrow_account: Account<'info, TokenAccount>,
-    
-    #[account(
-        mut,
-        constraint = user_token_account.mint == company.token_mint
-    )]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
-    pub token_mint: Account<'info, anchor_spl::token::Mint>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
+// Reduces a legacy order's remaining size and refunds the escrow it no
+// longer needs. Reducing to zero cancels the order. Prices and sizes cannot
+// be raised; those orders move to the market instead.
+pub fn amend_order(
+    ctx: Context<AmendOrder>,
+    new_remaining_amount: u64,
+    new_price: u64,
+) -> Result<()> {
+    let order_info = ctx.accounts.order.to_account_info();
+    let order = &mut ctx.accounts.order;
     
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-}
+    require!(!ctx.accounts.platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(order.status == OrderStatus::Active || order.status == OrderStatus::PartiallyFilled,
+             DefiTradingError::OrderAlreadyCancelled);
+    require!(order.user == ctx.accounts.user.key(), DefiTradingError::Unauthorized);
+    require!(
+        new_price == order.price && new_remaining_amount < order.remaining_amount,
+        DefiTradingError::LegacyTradingDeprecated
+    );
 
-#[derive(Accounts)]
-pub struct CreateBuyOrder<'info> {
-    #[account(
-        mut,
-        seeds = [b"platform"],
-        bump = platform.bump
-    )]
-    pub platform: Account<'info, Platform>,
-    
-    #[account(
-        seeds = [b"company", company.id.to_le_bytes().as_ref()],
-        bump = company.bump
-    )]
-    pub company: Account<'info, Company>,
-    
-    #[account(
-        init,
-        payer = user,
-        space = Order::LEN,
-        seeds = [
-            b"order",
-            user.key().as_ref(),
-            (platform.total_trades + 1).to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub order: Account<'info, Order>,
-    
-    #[account(
-        init,
-        payer = user,
-        token::mint = payment_mint,
-        token::authority = order,
-        seeds = [
-            b"order_escrow",
-            user.key().as_ref(),
-            (platform.total_trades + 1).to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub order_escrow_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub user_payment_account: Account<'info, TokenAccount>,
-    
-    pub payment_mint: Account<'info, anchor_spl::token::Mint>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
+    let old_remaining_amount = order.remaining_amount;
+    let reduction = old_remaining_amount - new_remaining_amount;
+    let escrow_refunded = match order.order_type {
+        OrderType::Sell => reduction,
+        // A cancelled buy hands back whatever its escrow still holds
+        OrderType::Buy if new_remaining_amount == 0 => ctx.accounts.order_escrow_account.amount,
+        OrderType::Buy => buy_escrow_release(old_remaining_amount, reduction, order.price, LEGACY_PRICE_DECIMALS)?,
+    };
+
+    let order_id_bytes = order.id.to_le_bytes();
+    let seeds = &[
+        b"order",
+        order.user.as_ref(),
+        order_id_bytes.as_ref(),
+        &[order.bump],
+    ];
+    let signer = &[&seeds[..]];
     
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+    return_payment(
+        &ctx.accounts.order_escrow_account,
+        order_info,
+        signer,
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.user_account.as_ref(),
+        ctx.accounts.temp_wsol_account.as_ref(),
+        ctx.accounts.token_program.to_account_info(),
+        escrow_refunded,
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    if new_remaining_amount == 0 {
+        order.status = OrderStatus::Cancelled;
+        
+        emit!(OrderCancelled {
+            order_id: order.id,
+            user: order.user,
+            company_id: order.company_id,
+            timestamp: now,
+        });
+        return Ok(());
+    }
+
+    order.amount -= reduction;
+    order.remaining_amount = new_remaining_amount;
+
+    emit!(OrderAmended {
+        order_id: order.id,
+        user: order.user,
+        company_id: order.company_id,
+        old_price: order.price,
+        new_price,
+        old_remaining_amount,
+        new_remaining_amount,
+        escrow_topped_up: 0,
+        escrow_refunded,
+        priority_kept: true,
+        timestamp: now,
+    });
+
+    Ok(())
 }
 
 #[derive(Accounts)]
@@ -519,6 +400,13 @@ pub struct ExecuteTrade<'info> {
     #[account(mut)]
     pub seller_payment_account: Account<'info, TokenAccount>,
     
+    #[account(
+        mut,
+        constraint = buyer_payment_account.owner == buy_order.user @ DefiTradingError::TokenAccountMismatch,
+        constraint = buyer_payment_account.mint == buy_order_escrow.mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub buyer_payment_account: Account<'info, TokenAccount>,
+    
     #[account(mut)]
     pub platform_fee_account: Account<'info, TokenAccount>,
     
@@ -554,11 +442,88 @@ pub struct CancelOrder<'info> {
     )]
     pub order_escrow_account: Account<'info, TokenAccount>,
     
+    // Omit, together with passing the temporary wSOL account, to have a
+    // wrapped SOL escrow returned as native SOL
     #[account(mut)]
-    pub user_account: Account<'info, TokenAccount>,
+    pub user_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = native_mint,
+        token::authority = user,
+        seeds = [TEMP_WSOL_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
     
     #[account(mut)]
     pub user: Signer<'info>,
     
     pub token_program: Program<'info, Token>,
-}
\ No newline at end of file
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AmendOrder<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"order",
+            order.user.as_ref(),
+            order.id.to_le_bytes().as_ref()
+        ],
+        bump = order.bump
+    )]
+    pub order: Account<'info, Order>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"order_escrow",
+            order.user.as_ref(),
+            order.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_escrow_account: Account<'info, TokenAccount>,
+    
+    // Account in the escrow's mint that receives the refund. Omit, together
+    // with passing the temporary wSOL account, to have a wrapped SOL escrow
+    // refunded as native SOL.
+    #[account(
+        mut,
+        constraint = user_account.mint == order_escrow_account.mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = native_mint,
+        token::authority = user,
+        seeds = [TEMP_WSOL_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
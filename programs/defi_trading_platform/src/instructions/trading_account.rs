@@ -0,0 +1,113 @@
+use anchor_lang::prelude::*;
+use crate::state::*;
+use crate::errors::*;
+use crate::events::*;
+
+pub fn create_trading_account(ctx: Context<CreateTradingAccount>) -> Result<()> {
+    let trading_account = &mut ctx.accounts.trading_account;
+    
+    trading_account.owner = ctx.accounts.owner.key();
+    trading_account.wallets = Vec::new();
+    trading_account.created_at = Clock::get()?.unix_timestamp;
+    trading_account.bump = ctx.bumps.trading_account;
+    
+    emit!(TradingAccountCreated {
+        trading_account: trading_account.key(),
+        owner: trading_account.owner,
+        timestamp: trading_account.created_at,
+    });
+    
+    Ok(())
+}
+
+// Both the owner and the wallet sign, so nobody can be linked against their will
+pub fn link_wallet(ctx: Context<LinkWallet>) -> Result<()> {
+    let trading_account = &mut ctx.accounts.trading_account;
+    let wallet = ctx.accounts.wallet.key();
+    
+    require!(!trading_account.includes(&wallet), DefiTradingError::WalletAlreadyLinked);
+    require!(
+        trading_account.wallets.len() < TradingAccount::MAX_LINKED_WALLETS,
+        DefiTradingError::TooManyLinkedWallets
+    );
+    
+    trading_account.wallets.push(wallet);
+    
+    emit!(WalletLinked {
+        trading_account: trading_account.key(),
+        wallet,
+        linked: true,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+// Either the owner or the linked wallet itself may unlink
+pub fn unlink_wallet(ctx: Context<UnlinkWallet>, wallet: Pubkey) -> Result<()> {
+    let trading_account = &mut ctx.accounts.trading_account;
+    let signer = ctx.accounts.signer.key();
+    
+    require!(
+        signer == trading_account.owner || signer == wallet,
+        DefiTradingError::Unauthorized
+    );
+    let index = trading_account.wallets
+        .iter()
+        .position(|linked| *linked == wallet)
+        .ok_or(DefiTradingError::WalletNotLinked)?;
+    
+    trading_account.wallets.remove(index);
+    
+    emit!(WalletLinked {
+        trading_account: trading_account.key(),
+        wallet,
+        linked: false,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CreateTradingAccount<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = TradingAccount::LEN,
+        seeds = [b"trading_account", owner.key().as_ref()],
+        bump
+    )]
+    pub trading_account: Account<'info, TradingAccount>,
+    
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LinkWallet<'info> {
+    #[account(
+        mut,
+        seeds = [b"trading_account", owner.key().as_ref()],
+        bump = trading_account.bump
+    )]
+    pub trading_account: Account<'info, TradingAccount>,
+    
+    pub owner: Signer<'info>,
+    
+    pub wallet: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UnlinkWallet<'info> {
+    #[account(
+        mut,
+        seeds = [b"trading_account", trading_account.owner.as_ref()],
+        bump = trading_account.bump
+    )]
+    pub trading_account: Account<'info, TradingAccount>,
+    
+    pub signer: Signer<'info>,
+}
@@ -0,0 +1,725 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, spl_token::native_mint, CloseAccount, Token, TokenAccount, Mint, Transfer};
+use crate::state::*;
+use crate::errors::*;
+use crate::events::*;
+use crate::pricing::*;
+use crate::native_sol::*;
+use super::matching::*;
+use super::enhanced_trading::{sync_book_state, record_eviction};
+
+// Stop-loss and take-profit orders. Funds are escrowed when the trigger is
+// created; once the reference price crosses the trigger any keeper can fire
+// it, turning it into a live market or limit order and collecting the bounty.
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_trigger_order(
+    ctx: Context<CreateTriggerOrder>,
+    order_type: OrderType,
+    market_order_type: MarketOrderType,
+    trigger_direction: TriggerDirection,
+    trigger_price: u64,
+    amount: u64,
+    price: u64,
+    keeper_bounty: u64,
+) -> Result<()> {
+    let platform = &ctx.accounts.platform;
+    let orderbook = &mut ctx.accounts.orderbook;
+    
+    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
+    require!(amount > 0, DefiTradingError::InvalidOrderParams);
+    require!(trigger_price > 0, DefiTradingError::InvalidOrderParams);
+    // Buys escrow at `price`, so only market sells may leave it open
+    require!(
+        price > 0 || (order_type == OrderType::Sell && market_order_type == MarketOrderType::Market),
+        DefiTradingError::InvalidOrderParams
+    );
+    match market_order_type {
+        MarketOrderType::Limit => orderbook.market_config.check_limit_order(amount, price, orderbook.base_decimals)?,
+        MarketOrderType::Market => orderbook.market_config.check_amount(amount)?,
+    }
+    
+    let escrowed_amount = match order_type {
+        OrderType::Sell => {
+            let user_token_account = ctx.accounts.user_token_account.as_ref()
+                .ok_or(DefiTradingError::TokenAccountMismatch)?;
+            require!(user_token_account.amount >= amount, DefiTradingError::InsufficientTokens);
+    
+            let cpi_accounts = Transfer {
+                from: user_token_account.to_account_info(),
+                to: ctx.accounts.trigger_escrow.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+            token::transfer(cpi_ctx, amount)?;
+            amount
+        },
+        OrderType::Buy => {
+            // Fills are paid rounded up, so the escrow allows for the most
+            // fills a trigger can take; whatever is left is refunded
+            let total_cost = taker_buy_budget(amount, price, orderbook.base_decimals, u8::MAX)?;
+    
+            // Escrow payment, wrapping native SOL if no payment account was given
+            collect_payment(
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.user_payment_account.as_ref(),
+                ctx.accounts.trigger_escrow.to_account_info(),
+                ctx.accounts.trigger_escrow.mint,
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                total_cost,
+            )?;
+            total_cost
+        }
+    };
+    
+    // The bounty rides on the trigger account until a keeper closes it
+    if keeper_bounty > 0 {
+        let cpi_accounts = system_program::Transfer {
+            from: ctx.accounts.user.to_account_info(),
+            to: ctx.accounts.trigger_order.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+        system_program::transfer(cpi_ctx, keeper_bounty)?;
+    }
+    
+    let trigger_id = orderbook.last_trigger_order_id
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let now = Clock::get()?.unix_timestamp;
+    
+    let trigger = &mut ctx.accounts.trigger_order;
+    trigger.id = trigger_id;
+    trigger.user = ctx.accounts.user.key();
+    trigger.company_id = orderbook.company_id;
+    trigger.order_type = order_type.clone();
+    trigger.market_order_type = market_order_type.clone();
+    trigger.trigger_price = trigger_price;
+    trigger.trigger_direction = trigger_direction.clone();
+    trigger.amount = amount;
+    trigger.price = price;
+    trigger.escrowed_amount = escrowed_amount;
+    trigger.keeper_bounty = keeper_bounty;
+    trigger.created_at = now;
+    trigger.bump = ctx.bumps.trigger_order;
+    
+    orderbook.last_trigger_order_id = trigger_id;
+    
+    emit!(TriggerOrderCreated {
+        trigger_id,
+        user: trigger.user,
+        company_id: trigger.company_id,
+        order_type: match order_type {
+            OrderType::Buy => "Buy".to_string(),
+            OrderType::Sell => "Sell".to_string(),
+        },
+        market_order_type: match market_order_type {
+            MarketOrderType::Market => "Market".to_string(),
+            MarketOrderType::Limit => "Limit".to_string(),
+        },
+        trigger_price,
+        trigger_direction: match trigger_direction {
+            TriggerDirection::AtOrAbove => "AtOrAbove".to_string(),
+            TriggerDirection::AtOrBelow => "AtOrBelow".to_string(),
+        },
+        amount,
+        price,
+        escrowed_amount,
+        keeper_bounty,
+        timestamp: now,
+    });
+    
+    Ok(())
+}
+
+// Permissionless: converts a trigger whose price has been crossed into a live
+// order. The order takes liquidity from the makers passed as remaining
+// accounts; market orders drop any remainder while limit orders rest it in
+// the book. The trigger account, with its rent and bounty, goes to the keeper.
+pub fn trigger_order<'info>(
+    ctx: Context<'_, '_, '_, 'info, FireTriggerOrder<'info>>,
+    max_fills: u8,
+) -> Result<()> {
+    let trigger_info = ctx.accounts.trigger_order.to_account_info();
+    let orderbook_info = ctx.accounts.orderbook.to_account_info();
+    let trigger = &ctx.accounts.trigger_order;
+    let platform = &mut ctx.accounts.platform;
+    let orderbook = &mut ctx.accounts.orderbook;
+    
+    require!(!platform.is_paused, DefiTradingError::PlatformPaused);
+    
+    let now = Clock::get()?.unix_timestamp;
+    let reference_price = reference_price(orderbook, ctx.accounts.price_feed.as_ref(), now)?;
+    require!(
+        reference_price > 0 && trigger.is_triggered(reference_price),
+        DefiTradingError::TriggerNotReached
+    );
+    
+    let order_type = trigger.order_type.clone();
+    if order_type == OrderType::Buy {
+        require_keys_eq!(
+            ctx.accounts.owner_payment_account.mint,
+            ctx.accounts.trigger_escrow.mint,
+            DefiTradingError::TokenAccountMismatch
+        );
+    }
+    
+    let trigger_id_bytes = trigger.id.to_le_bytes();
+    let company_id_bytes = trigger.company_id.to_le_bytes();
+    let seeds = &[
+        b"trigger_order",
+        company_id_bytes.as_ref(),
+        trigger_id_bytes.as_ref(),
+        &[trigger.bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    let order_id = orderbook.last_order_id
+        .checked_add(1)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    let mut book = ctx.accounts.order_book.load_mut()?;
+    
+    // The escrow stands in for the owner's wallet on the side it pays from
+    let summary = {
+        let (user_token_account, user_payment_account) = match order_type {
+            OrderType::Buy => (&ctx.accounts.owner_token_account, &ctx.accounts.trigger_escrow),
+            OrderType::Sell => (&ctx.accounts.trigger_escrow, &ctx.accounts.owner_payment_account),
+        };
+        let taker = TakerAccounts {
+            user: ctx.accounts.owner.to_account_info(),
+            authority: trigger_info.clone(),
+            signer_seeds: signer,
+            orderbook: orderbook_info,
+            self_trade_key: trigger.user,
+            self_trade_prevention: SelfTradePrevention::CancelTaker,
+            user_token_account,
+            user_payment_account: Some(user_payment_account),
+            platform_fee_account: Some(&ctx.accounts.platform_fee_account),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        match_against_book(
+            &mut book,
+            orderbook,
+            platform,
+            &taker,
+            ctx.remaining_accounts,
+            &order_type,
+            order_id,
+            trigger.price,
+            trigger.amount,
+            max_fills,
+        )?
+    };
+    let remaining_amount = trigger.amount - summary.filled_amount - summary.cancelled_amount;
+    let rest_amount = match trigger.market_order_type {
+        MarketOrderType::Market => {
+            require!(summary.filled_amount > 0, DefiTradingError::NoLiquidity);
+            0
+        },
+        MarketOrderType::Limit if summary.taker_cancelled => 0,
+        MarketOrderType::Limit => remaining_amount,
+    };
+    
+    // Move what the resting remainder needs into its own order escrow
+    if rest_amount > 0 {
+        let order_escrow_account = ctx.accounts.order_escrow_account.as_ref()
+            .ok_or(DefiTradingError::OrderEscrowRequired)?;
+        let rest_escrow = match order_type {
+            OrderType::Sell => rest_amount,
+            OrderType::Buy => buy_escrow_required(rest_amount, trigger.price, orderbook.base_decimals)?,
+        };
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.trigger_escrow.to_account_info(),
+            to: order_escrow_account.to_account_info(),
+            authority: trigger_info.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, rest_escrow)?;
+    
+        let evicted = book.insert(&order_type, order_id, trigger.user, Pubkey::default(), trigger.price, rest_amount, 0)?;
+        record_eviction(orderbook, evicted, now)?;
+    }
+    
+    // Return whatever is left in the trigger escrow and close it
+    ctx.accounts.trigger_escrow.reload()?;
+    let leftover = ctx.accounts.trigger_escrow.amount;
+    if leftover > 0 {
+        let refund_account = match order_type {
+            OrderType::Buy => ctx.accounts.owner_payment_account.to_account_info(),
+            OrderType::Sell => ctx.accounts.owner_token_account.to_account_info(),
+        };
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.trigger_escrow.to_account_info(),
+            to: refund_account,
+            authority: trigger_info.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, leftover)?;
+    }
+    let cpi_accounts = CloseAccount {
+        account: ctx.accounts.trigger_escrow.to_account_info(),
+        destination: ctx.accounts.owner.to_account_info(),
+        authority: trigger_info,
+    };
+    let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+    token::close_account(cpi_ctx)?;
+    
+    orderbook.last_order_id = order_id;
+    
+    let order = &mut ctx.accounts.order;
+    order.id = order_id;
+    order.user = trigger.user;
+    order.company_id = trigger.company_id;
+    order.token_mint = orderbook.base_mint;
+    order.order_type = order_type.clone();
+    order.market_order_type = trigger.market_order_type.clone();
+    order.amount = trigger.amount;
+    order.remaining_amount = remaining_amount;
+    order.price = match trigger.market_order_type {
+        MarketOrderType::Market => unit_price(summary.quote_amount, summary.filled_amount, orderbook.base_decimals)?,
+        MarketOrderType::Limit => trigger.price,
+    };
+    order.time_in_force = match trigger.market_order_type {
+        MarketOrderType::Market => TimeInForce::ImmediateOrCancel,
+        MarketOrderType::Limit => TimeInForce::GoodTillCancelled,
+    };
+    order.expires_at = None;
+    order.self_trade_prevention = SelfTradePrevention::CancelTaker;
+    order.trading_account = Pubkey::default();
+    order.status = if summary.filled_amount == trigger.amount {
+        OrderStatus::Filled
+    } else if rest_amount == 0 {
+        OrderStatus::Cancelled
+    } else if summary.filled_amount > 0 {
+        OrderStatus::PartiallyFilled
+    } else {
+        OrderStatus::Active
+    };
+    order.created_at = now;
+    order.filled_at = if summary.filled_amount == trigger.amount { Some(now) } else { None };
+    order.bump = ctx.bumps.order;
+    
+    sync_book_state(orderbook, &book, now);
+    orderbook.last_updated = now;
+    
+    emit!(TriggerOrderFired {
+        trigger_id: trigger.id,
+        order_id,
+        user: trigger.user,
+        company_id: trigger.company_id,
+        reference_price,
+        filled_amount: summary.filled_amount,
+        rested_amount: rest_amount,
+        fills: summary.fills,
+        keeper: ctx.accounts.keeper.key(),
+        keeper_bounty: trigger.keeper_bounty,
+        timestamp: now,
+    });
+    
+    Ok(())
+}
+
+// Owner cancels an untriggered order; the escrow, rent and bounty are returned
+pub fn cancel_trigger_order(ctx: Context<CancelTriggerOrder>) -> Result<()> {
+    let trigger_info = ctx.accounts.trigger_order.to_account_info();
+    let trigger = &ctx.accounts.trigger_order;
+    
+    let trigger_id_bytes = trigger.id.to_le_bytes();
+    let company_id_bytes = trigger.company_id.to_le_bytes();
+    let seeds = &[
+        b"trigger_order",
+        company_id_bytes.as_ref(),
+        trigger_id_bytes.as_ref(),
+        &[trigger.bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    let refunded_amount = ctx.accounts.trigger_escrow.amount;
+    return_payment(
+        &ctx.accounts.trigger_escrow,
+        trigger_info.clone(),
+        signer,
+        ctx.accounts.user.to_account_info(),
+        ctx.accounts.user_account.as_ref(),
+        ctx.accounts.temp_wsol_account.as_ref(),
+        ctx.accounts.token_program.to_account_info(),
+        refunded_amount,
+    )?;
+    
+    let cpi_accounts = CloseAccount {
+        account: ctx.accounts.trigger_escrow.to_account_info(),
+        destination: ctx.accounts.user.to_account_info(),
+        authority: trigger_info,
+    };
+    let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+    token::close_account(cpi_ctx)?;
+    
+    emit!(TriggerOrderCancelled {
+        trigger_id: trigger.id,
+        user: trigger.user,
+        company_id: trigger.company_id,
+        refunded_amount,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+// Platform authority points a market's trigger orders at a price feed and
+// appoints the feeder allowed to update it
+pub fn configure_price_feed(
+    ctx: Context<ConfigurePriceFeed>,
+    feeder: Pubkey,
+    max_staleness: i64,
+) -> Result<()> {
+    require!(
+        ctx.accounts.authority.key() == ctx.accounts.platform.authority,
+        DefiTradingError::Unauthorized
+    );
+    require!(max_staleness > 0, DefiTradingError::InvalidTimestamp);
+    
+    let orderbook = &mut ctx.accounts.orderbook;
+    let price_feed = &mut ctx.accounts.price_feed;
+    price_feed.company_id = orderbook.company_id;
+    price_feed.feeder = feeder;
+    price_feed.max_staleness = max_staleness;
+    price_feed.bump = ctx.bumps.price_feed;
+    
+    orderbook.price_feed = price_feed.key();
+    
+    emit!(PriceFeedConfigured {
+        company_id: orderbook.company_id,
+        price_feed: price_feed.key(),
+        feeder,
+        max_staleness,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+pub fn update_price_feed(ctx: Context<UpdatePriceFeed>, price: u64) -> Result<()> {
+    require!(price > 0, DefiTradingError::InvalidOrderParams);
+    
+    let now = Clock::get()?.unix_timestamp;
+    let price_feed = &mut ctx.accounts.price_feed;
+    price_feed.price = price;
+    price_feed.updated_at = now;
+    
+    emit!(PriceFeedUpdated {
+        company_id: price_feed.company_id,
+        price,
+        timestamp: now,
+    });
+    
+    Ok(())
+}
+
+// Configured price feed if the market has one, otherwise the last trade
+fn reference_price(
+    orderbook: &Market,
+    price_feed: Option<&Account<PriceFeed>>,
+    now: i64,
+) -> Result<u64> {
+    if orderbook.price_feed == Pubkey::default() {
+        return Ok(orderbook.last_trade_price);
+    }
+    
+    let price_feed = price_feed.ok_or(DefiTradingError::PriceFeedRequired)?;
+    require_keys_eq!(price_feed.key(), orderbook.price_feed, DefiTradingError::PriceFeedRequired);
+    require!(
+        now - price_feed.updated_at <= price_feed.max_staleness,
+        DefiTradingError::StalePrice
+    );
+    Ok(price_feed.price)
+}
+
+#[derive(Accounts)]
+#[instruction(order_type: OrderType)]
+pub struct CreateTriggerOrder<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        init,
+        payer = user,
+        space = TriggerOrder::LEN,
+        seeds = [
+            b"trigger_order",
+            orderbook.company_id.to_le_bytes().as_ref(),
+            orderbook.last_trigger_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub trigger_order: Account<'info, TriggerOrder>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = escrow_mint,
+        token::authority = trigger_order,
+        seeds = [
+            b"trigger_order_escrow",
+            orderbook.company_id.to_le_bytes().as_ref(),
+            orderbook.last_trigger_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub trigger_escrow: Account<'info, TokenAccount>,
+    
+    // Company token for sells, quote mint for buys
+    #[account(address = orderbook.escrow_mint(&order_type) @ DefiTradingError::TokenAccountMismatch)]
+    pub escrow_mint: Account<'info, Mint>,
+    
+    // Required for sells
+    #[account(
+        mut,
+        constraint = user_token_account.mint == orderbook.base_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_token_account: Option<Account<'info, TokenAccount>>,
+    
+    // Omit to pay in native SOL
+    #[account(
+        mut,
+        constraint = user_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_payment_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct FireTriggerOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", trigger_order.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        mut,
+        seeds = [b"order_book", trigger_order.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBookSlab>,
+    
+    // Required when the market has a price feed configured
+    #[account(
+        seeds = [b"price_feed", trigger_order.company_id.to_le_bytes().as_ref()],
+        bump = price_feed.bump
+    )]
+    pub price_feed: Option<Account<'info, PriceFeed>>,
+    
+    #[account(
+        mut,
+        close = keeper,
+        seeds = [
+            b"trigger_order",
+            trigger_order.company_id.to_le_bytes().as_ref(),
+            trigger_order.id.to_le_bytes().as_ref()
+        ],
+        bump = trigger_order.bump
+    )]
+    pub trigger_order: Account<'info, TriggerOrder>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"trigger_order_escrow",
+            trigger_order.company_id.to_le_bytes().as_ref(),
+            trigger_order.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub trigger_escrow: Account<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = keeper,
+        space = EnhancedOrder::LEN,
+        seeds = [
+            b"enhanced_order",
+            trigger_order.company_id.to_le_bytes().as_ref(),
+            trigger_order.user.as_ref(),
+            orderbook.last_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order: Account<'info, EnhancedOrder>,
+    
+    // Required when a limit order rests a remainder
+    #[account(
+        init,
+        payer = keeper,
+        token::mint = escrow_mint,
+        token::authority = order,
+        seeds = [
+            b"enhanced_order_escrow",
+            trigger_order.company_id.to_le_bytes().as_ref(),
+            trigger_order.user.as_ref(),
+            orderbook.last_order_id.checked_add(1).unwrap().to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_escrow_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = trigger_escrow.mint)]
+    pub escrow_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == trigger_order.user @ DefiTradingError::TokenAccountMismatch,
+        constraint = owner_token_account.mint == orderbook.base_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = owner_payment_account.owner == trigger_order.user @ DefiTradingError::TokenAccountMismatch,
+        constraint = owner_payment_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub owner_payment_account: Account<'info, TokenAccount>,
+    
+    /// CHECK: Trigger owner, only receives the rent of the closed trigger escrow
+    #[account(
+        mut,
+        address = trigger_order.user
+    )]
+    pub owner: UncheckedAccount<'info>,
+    
+    #[account(
+        mut,
+        constraint = platform_fee_account.mint == orderbook.quote_mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub platform_fee_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CancelTriggerOrder<'info> {
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"trigger_order",
+            trigger_order.company_id.to_le_bytes().as_ref(),
+            trigger_order.id.to_le_bytes().as_ref()
+        ],
+        bump = trigger_order.bump,
+        constraint = trigger_order.user == user.key() @ DefiTradingError::Unauthorized
+    )]
+    pub trigger_order: Account<'info, TriggerOrder>,
+    
+    #[account(
+        mut,
+        seeds = [
+            b"trigger_order_escrow",
+            trigger_order.company_id.to_le_bytes().as_ref(),
+            trigger_order.id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub trigger_escrow: Account<'info, TokenAccount>,
+    
+    // Omit, together with passing the temporary wSOL account, to have a
+    // wrapped SOL escrow returned as native SOL
+    #[account(
+        mut,
+        constraint = user_account.mint == trigger_escrow.mint @ DefiTradingError::TokenAccountMismatch
+    )]
+    pub user_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        init,
+        payer = user,
+        token::mint = native_mint,
+        token::authority = user,
+        seeds = [TEMP_WSOL_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub temp_wsol_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(address = native_mint::ID)]
+    pub native_mint: Option<Account<'info, Mint>>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigurePriceFeed<'info> {
+    #[account(
+        seeds = [b"platform"],
+        bump = platform.bump
+    )]
+    pub platform: Account<'info, Platform>,
+    
+    #[account(
+        mut,
+        seeds = [b"orderbook", orderbook.company_id.to_le_bytes().as_ref()],
+        bump = orderbook.bump
+    )]
+    pub orderbook: Account<'info, Market>,
+    
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = PriceFeed::LEN,
+        seeds = [b"price_feed", orderbook.company_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePriceFeed<'info> {
+    #[account(
+        mut,
+        seeds = [b"price_feed", price_feed.company_id.to_le_bytes().as_ref()],
+        bump = price_feed.bump,
+        constraint = price_feed.feeder == feeder.key() @ DefiTradingError::Unauthorized
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+    
+    pub feeder: Signer<'info>,
+}
@@ -7,6 +7,8 @@ pub mod state;
 pub mod instructions;
 pub mod errors;
 pub mod events;
+pub mod pricing;
+pub mod native_sol;
 
 use state::*;
 use instructions::*;
@@ -39,6 +41,10 @@ pub mod defi_trading_platform {
         instructions::verify_company(ctx, company_id)
     }
 
+    pub fn migrate_platform(ctx: Context<MigratePlatform>) -> Result<()> {
+        instructions::migrate_platform(ctx)
+    }
+
     // Company management
     pub fn register_company(
         ctx: Context<RegisterCompany>, 
@@ -57,8 +63,9 @@ pub mod defi_trading_platform {
         description: String,
         initial_supply: u64,
         initial_price: u64,
+        market_config: Option<MarketConfig>,
     ) -> Result<()> {
-        instructions::admin_create_company(ctx, name, symbol, description, initial_supply, initial_price)
+        instructions::admin_create_company(ctx, name, symbol, description, initial_supply, initial_price, market_config)
     }
 
     // Token distribution functions
@@ -84,36 +91,190 @@ pub mod defi_trading_platform {
         ctx: Context<CreateTokenOffering>,
         total_supply: u64,
         price_per_token: u64,
+        min_investment: u64,
+        max_investment: u64,
         offering_start: i64,
         offering_end: i64,
     ) -> Result<()> {
-        instructions::create_token_offering(ctx, total_supply, price_per_token, offering_start, offering_end)
+        instructions::create_token_offering(
+            ctx,
+            total_supply,
+            price_per_token,
+            min_investment,
+            max_investment,
+            offering_start,
+            offering_end,
+        )
     }
 
     pub fn participate_in_offering(
         ctx: Context<ParticipateInOffering>,
         amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        instructions::participate_in_offering(ctx, amount, proof)
+    }
+
+    pub fn add_offering_round(
+        ctx: Context<AddOfferingRound>,
+        price_per_token: u64,
+        round_start: i64,
+        round_end: i64,
+        max_per_wallet: u64,
+        allowlist_root: [u8; 32],
+    ) -> Result<()> {
+        instructions::add_offering_round(
+            ctx,
+            price_per_token,
+            round_start,
+            round_end,
+            max_per_wallet,
+            allowlist_root,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_dutch_auction_offering(
+        ctx: Context<CreateDutchAuctionOffering>,
+        total_supply: u64,
+        start_price: u64,
+        floor_price: u64,
+        min_investment: u64,
+        max_investment: u64,
+        offering_start: i64,
+        offering_end: i64,
+    ) -> Result<()> {
+        instructions::create_dutch_auction_offering(
+            ctx,
+            total_supply,
+            start_price,
+            floor_price,
+            min_investment,
+            max_investment,
+            offering_start,
+            offering_end,
+        )
+    }
+
+    pub fn claim_auction_tokens(ctx: Context<ClaimAuctionTokens>) -> Result<()> {
+        instructions::claim_auction_tokens(ctx)
+    }
+
+    pub fn create_subscription_offering(
+        ctx: Context<CreateSubscriptionOffering>,
+        total_supply: u64,
+        price_per_token: u64,
+        min_investment: u64,
+        max_investment: u64,
+        offering_start: i64,
+        offering_end: i64,
+    ) -> Result<()> {
+        instructions::create_subscription_offering(
+            ctx,
+            total_supply,
+            price_per_token,
+            min_investment,
+            max_investment,
+            offering_start,
+            offering_end,
+        )
+    }
+
+    pub fn claim_allocation(ctx: Context<ClaimAllocation>) -> Result<()> {
+        instructions::claim_allocation(ctx)
+    }
+
+    pub fn withdraw_offering_proceeds(ctx: Context<WithdrawOfferingProceeds>) -> Result<()> {
+        instructions::withdraw_offering_proceeds(ctx)
+    }
+
+    pub fn cancel_offering(ctx: Context<CancelOffering>) -> Result<()> {
+        instructions::cancel_offering(ctx)
+    }
+
+    pub fn claim_offering_refund(ctx: Context<ClaimOfferingRefund>) -> Result<()> {
+        instructions::claim_offering_refund(ctx)
+    }
+
+    pub fn amend_offering(
+        ctx: Context<AmendOffering>,
+        new_offering_end: Option<i64>,
+        new_price_per_token: Option<u64>,
+    ) -> Result<()> {
+        instructions::amend_offering(ctx, new_offering_end, new_price_per_token)
+    }
+
+    pub fn set_offering_lockup(ctx: Context<SetOfferingLockup>, lockup_duration: i64) -> Result<()> {
+        instructions::set_offering_lockup(ctx, lockup_duration)
+    }
+
+    pub fn open_offering_lock(ctx: Context<OpenOfferingLock>) -> Result<()> {
+        instructions::open_offering_lock(ctx)
+    }
+
+    pub fn unlock_offering_tokens(ctx: Context<UnlockOfferingTokens>) -> Result<()> {
+        instructions::unlock_offering_tokens(ctx)
+    }
+
+    pub fn migrate_offering(ctx: Context<MigrateOffering>, offering_id: u64) -> Result<()> {
+        instructions::migrate_offering(ctx, offering_id)
+    }
+
+    pub fn migrate_participation(
+        ctx: Context<MigrateParticipation>,
+        user: Pubkey,
+        offering_id: u64,
+    ) -> Result<()> {
+        instructions::migrate_participation(ctx, user, offering_id)
+    }
+
+    // Bonding curve launches
+    pub fn create_bonding_curve(
+        ctx: Context<CreateBondingCurve>,
+        curve_kind: CurveKind,
+        base_price: u64,
+        curve_param: u64,
+        max_supply: u64,
+        graduation_threshold: u64,
+        seed_liquidity_bps: u16,
     ) -> Result<()> {
-        instructions::participate_in_offering(ctx, amount)
+        instructions::create_bonding_curve(
+            ctx,
+            curve_kind,
+            base_price,
+            curve_param,
+            max_supply,
+            graduation_threshold,
+            seed_liquidity_bps,
+        )
     }
 
-    // Trading functions
-    pub fn create_sell_order(
-        ctx: Context<CreateSellOrder>,
+    pub fn buy_from_curve(
+        ctx: Context<BuyFromCurve>,
         amount: u64,
-        price: u64,
+        max_quote_in: u64,
     ) -> Result<()> {
-        instructions::create_sell_order(ctx, amount, price)
+        instructions::buy_from_curve(ctx, amount, max_quote_in)
     }
 
-    pub fn create_buy_order(
-        ctx: Context<CreateBuyOrder>,
+    pub fn sell_to_curve(
+        ctx: Context<SellToCurve>,
         amount: u64,
-        price: u64,
+        min_quote_out: u64,
     ) -> Result<()> {
-        instructions::create_buy_order(ctx, amount, price)
+        instructions::sell_to_curve(ctx, amount, min_quote_out)
+    }
+
+    // Markets
+    pub fn create_market(ctx: Context<CreateMarket>, market_config: Option<MarketConfig>) -> Result<()> {
+        instructions::create_market(ctx, market_config)
+    }
+
+    pub fn migrate_orderbook(ctx: Context<MigrateOrderbook>, company_id: u64) -> Result<()> {
+        instructions::migrate_orderbook(ctx, company_id)
     }
 
+    // Legacy trading, deprecated: existing orders can only be executed, reduced or cancelled
     pub fn execute_trade(
         ctx: Context<ExecuteTrade>,
         sell_order_id: u64,
@@ -127,22 +288,52 @@ pub mod defi_trading_platform {
         instructions::cancel_order(ctx)
     }
 
+    pub fn amend_order(ctx: Context<AmendOrder>, new_remaining_amount: u64, new_price: u64) -> Result<()> {
+        instructions::amend_order(ctx, new_remaining_amount, new_price)
+    }
+
     // Enhanced trading functions
-    pub fn create_limit_order(
-        ctx: Context<CreateLimitOrder>,
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_limit_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateLimitOrder<'info>>,
         order_type: OrderType,
         amount: u64,
         price: u64,
+        max_fills: u8,
+        time_in_force: TimeInForce,
+        expires_at: Option<i64>,
+        self_trade_prevention: SelfTradePrevention,
     ) -> Result<()> {
-        instructions::create_limit_order(ctx, order_type, amount, price)
+        instructions::create_limit_order(
+            ctx,
+            order_type,
+            amount,
+            price,
+            max_fills,
+            time_in_force,
+            expires_at,
+            self_trade_prevention,
+        )
     }
 
-    pub fn create_market_order(
-        ctx: Context<CreateMarketOrder>,
+    pub fn create_market_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, CreateMarketOrder<'info>>,
         order_type: OrderType,
         amount: u64,
-    ) -> Result<()> {
-        instructions::create_market_order(ctx, order_type, amount)
+        worst_price: u64,
+        min_amount_out: u64,
+        max_fills: u8,
+        self_trade_prevention: SelfTradePrevention,
+    ) -> Result<MarketOrderFill> {
+        instructions::create_market_order(
+            ctx,
+            order_type,
+            amount,
+            worst_price,
+            min_amount_out,
+            max_fills,
+            self_trade_prevention,
+        )
     }
 
     pub fn match_orders(
@@ -154,6 +345,89 @@ pub mod defi_trading_platform {
         instructions::match_orders(ctx, buy_order_id, sell_order_id, amount)
     }
 
+    pub fn initialize_order_book(ctx: Context<InitializeOrderBook>) -> Result<()> {
+        instructions::initialize_order_book(ctx)
+    }
+
+    pub fn configure_market(ctx: Context<ConfigureMarket>, market_config: MarketConfig) -> Result<()> {
+        instructions::configure_market(ctx, market_config)
+    }
+
+    pub fn configure_circuit_breaker(ctx: Context<ConfigureCircuitBreaker>, config: CircuitBreakerConfig) -> Result<()> {
+        instructions::configure_circuit_breaker(ctx, config)
+    }
+
+    pub fn cancel_expired_order(ctx: Context<CancelExpiredOrder>) -> Result<()> {
+        instructions::cancel_expired_order(ctx)
+    }
+
+    pub fn cancel_enhanced_order(ctx: Context<CancelEnhancedOrder>) -> Result<()> {
+        instructions::cancel_enhanced_order(ctx)
+    }
+
+    pub fn cancel_all_orders<'info>(ctx: Context<'_, '_, '_, 'info, CancelAllOrders<'info>>) -> Result<()> {
+        instructions::cancel_all_orders(ctx)
+    }
+
+    pub fn amend_enhanced_order(
+        ctx: Context<AmendEnhancedOrder>,
+        new_remaining_amount: u64,
+        new_price: u64,
+    ) -> Result<()> {
+        instructions::amend_enhanced_order(ctx, new_remaining_amount, new_price)
+    }
+
+    pub fn migrate_enhanced_order(ctx: Context<MigrateEnhancedOrder>) -> Result<()> {
+        instructions::migrate_enhanced_order(ctx)
+    }
+
+    // Trigger orders
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_trigger_order(
+        ctx: Context<CreateTriggerOrder>,
+        order_type: OrderType,
+        market_order_type: MarketOrderType,
+        trigger_direction: TriggerDirection,
+        trigger_price: u64,
+        amount: u64,
+        price: u64,
+        keeper_bounty: u64,
+    ) -> Result<()> {
+        instructions::create_trigger_order(
+            ctx,
+            order_type,
+            market_order_type,
+            trigger_direction,
+            trigger_price,
+            amount,
+            price,
+            keeper_bounty,
+        )
+    }
+
+    pub fn trigger_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, FireTriggerOrder<'info>>,
+        max_fills: u8,
+    ) -> Result<()> {
+        instructions::trigger_order(ctx, max_fills)
+    }
+
+    pub fn cancel_trigger_order(ctx: Context<CancelTriggerOrder>) -> Result<()> {
+        instructions::cancel_trigger_order(ctx)
+    }
+
+    pub fn configure_price_feed(
+        ctx: Context<ConfigurePriceFeed>,
+        feeder: Pubkey,
+        max_staleness: i64,
+    ) -> Result<()> {
+        instructions::configure_price_feed(ctx, feeder, max_staleness)
+    }
+
+    pub fn update_price_feed(ctx: Context<UpdatePriceFeed>, price: u64) -> Result<()> {
+        instructions::update_price_feed(ctx, price)
+    }
+
     pub fn calculate_market_depth(
         ctx: Context<CalculateMarketDepth>,
         company_id: u64,
@@ -170,6 +444,10 @@ pub mod defi_trading_platform {
         instructions::update_portfolio(ctx)
     }
 
+    pub fn migrate_holding(ctx: Context<MigrateHolding>, user: Pubkey, company_id: u64) -> Result<()> {
+        instructions::migrate_holding(ctx, user, company_id)
+    }
+
     // Escrow functions
     pub fn create_escrow(
         ctx: Context<CreateEscrow>,
@@ -182,4 +460,61 @@ pub mod defi_trading_platform {
     pub fn release_escrow(ctx: Context<ReleaseEscrow>) -> Result<()> {
         instructions::release_escrow(ctx)
     }
+
+    pub fn cancel_escrow(ctx: Context<CancelEscrow>) -> Result<()> {
+        instructions::cancel_escrow(ctx)
+    }
+
+    // Trading accounts
+    pub fn create_trading_account(ctx: Context<CreateTradingAccount>) -> Result<()> {
+        instructions::create_trading_account(ctx)
+    }
+
+    pub fn link_wallet(ctx: Context<LinkWallet>) -> Result<()> {
+        instructions::link_wallet(ctx)
+    }
+
+    pub fn unlink_wallet(ctx: Context<UnlinkWallet>, wallet: Pubkey) -> Result<()> {
+        instructions::unlink_wallet(ctx, wallet)
+    }
+
+    // Open orders accounts
+    pub fn create_open_orders(ctx: Context<CreateOpenOrders>) -> Result<()> {
+        instructions::create_open_orders(ctx)
+    }
+
+    pub fn deposit_funds(ctx: Context<DepositFunds>, base_amount: u64, quote_amount: u64) -> Result<()> {
+        instructions::deposit_funds(ctx, base_amount, quote_amount)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+        order_type: OrderType,
+        amount: u64,
+        price: u64,
+        max_fills: u8,
+        time_in_force: TimeInForce,
+        expires_at: Option<i64>,
+        self_trade_prevention: SelfTradePrevention,
+    ) -> Result<()> {
+        instructions::place_order(
+            ctx,
+            order_type,
+            amount,
+            price,
+            max_fills,
+            time_in_force,
+            expires_at,
+            self_trade_prevention,
+        )
+    }
+
+    pub fn cancel_open_order(ctx: Context<CancelOpenOrder>, order_id: u64) -> Result<()> {
+        instructions::cancel_open_order(ctx, order_id)
+    }
+
+    pub fn settle_funds(ctx: Context<SettleFunds>) -> Result<()> {
+        instructions::settle_funds(ctx)
+    }
 }
@@ -0,0 +1,155 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use anchor_spl::token::{self, spl_token::native_mint, CloseAccount, SyncNative, TokenAccount, Transfer};
+use crate::errors::*;
+
+// Payment accounts are optional wherever a user pays in or gets refunded.
+// Without one the instruction settles in native SOL: lamports are wrapped
+// straight into the receiving wSOL account, and refunds are paid into a
+// temporary wSOL account that is closed back to the user's wallet.
+
+/// Seed of the temporary wSOL account used to unwrap refunds.
+pub const TEMP_WSOL_SEED: &[u8] = b"wsol_temp";
+
+/// Balance the user can pay from: the SPL payment account if given,
+/// otherwise the wallet's lamports.
+pub fn payment_balance(user: &AccountInfo, user_payment_account: Option<&Account<TokenAccount>>) -> u64 {
+    match user_payment_account {
+        Some(payment_account) => payment_account.amount,
+        None => user.lamports(),
+    }
+}
+
+/// Moves `amount` from the user into `destination`, wrapping native SOL
+/// when no SPL payment account is supplied.
+pub fn collect_payment<'info>(
+    user: AccountInfo<'info>,
+    user_payment_account: Option<&Account<'info, TokenAccount>>,
+    destination: AccountInfo<'info>,
+    destination_mint: Pubkey,
+    token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    require!(
+        payment_balance(&user, user_payment_account) >= amount,
+        DefiTradingError::InsufficientFunds
+    );
+    if amount == 0 {
+        return Ok(());
+    }
+
+    match user_payment_account {
+        Some(payment_account) => {
+            let cpi_accounts = Transfer {
+                from: payment_account.to_account_info(),
+                to: destination,
+                authority: user,
+            };
+            token::transfer(CpiContext::new(token_program, cpi_accounts), amount)
+        },
+        None => {
+            require_keys_eq!(destination_mint, native_mint::ID, DefiTradingError::NativeMintRequired);
+
+            let cpi_accounts = system_program::Transfer {
+                from: user,
+                to: destination.clone(),
+            };
+            system_program::transfer(CpiContext::new(system_program, cpi_accounts), amount)?;
+
+            let cpi_accounts = SyncNative { account: destination };
+            token::sync_native(CpiContext::new(token_program, cpi_accounts))
+        }
+    }
+}
+
+/// Pays `amount` out of a program-owned `vault` to the user, unwrapping to
+/// native SOL through `temp_wsol_account` when no SPL account is supplied.
+#[allow(clippy::too_many_arguments)]
+pub fn return_payment<'info>(
+    vault: &Account<'info, TokenAccount>,
+    vault_authority: AccountInfo<'info>,
+    signer: &[&[&[u8]]],
+    user: AccountInfo<'info>,
+    user_payment_account: Option<&Account<'info, TokenAccount>>,
+    temp_wsol_account: Option<&Account<'info, TokenAccount>>,
+    token_program: AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let destination = match (user_payment_account, temp_wsol_account) {
+        (Some(payment_account), _) => payment_account.to_account_info(),
+        (None, Some(temp_account)) => {
+            require_keys_eq!(vault.mint, native_mint::ID, DefiTradingError::NativeMintRequired);
+            temp_account.to_account_info()
+        },
+        (None, None) => return err!(DefiTradingError::NativeSolAccountsRequired),
+    };
+
+    if amount > 0 {
+        let cpi_accounts = Transfer {
+            from: vault.to_account_info(),
+            to: destination.clone(),
+            authority: vault_authority,
+        };
+        let cpi_ctx = CpiContext::new_with_signer(token_program.clone(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+    }
+
+    // Closing the temporary account releases the wrapped lamports and its
+    // rent to the user
+    if user_payment_account.is_none() {
+        let cpi_accounts = CloseAccount {
+            account: destination,
+            destination: user.clone(),
+            authority: user,
+        };
+        token::close_account(CpiContext::new(token_program, cpi_accounts))?;
+    }
+
+    Ok(())
+}
+
+/// Tops up or refunds a program-owned `escrow` so that it holds exactly
+/// `required`. Returns the amounts topped up and refunded.
+#[allow(clippy::too_many_arguments)]
+pub fn rebalance_escrow<'info>(
+    escrow: &Account<'info, TokenAccount>,
+    escrow_authority: AccountInfo<'info>,
+    signer: &[&[&[u8]]],
+    user: AccountInfo<'info>,
+    user_account: Option<&Account<'info, TokenAccount>>,
+    temp_wsol_account: Option<&Account<'info, TokenAccount>>,
+    token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    required: u64,
+) -> Result<(u64, u64)> {
+    let escrowed = escrow.amount;
+    if required > escrowed {
+        let top_up = required - escrowed;
+        collect_payment(
+            user,
+            user_account,
+            escrow.to_account_info(),
+            escrow.mint,
+            token_program,
+            system_program,
+            top_up,
+        )?;
+        Ok((top_up, 0))
+    } else if escrowed > required {
+        let refund = escrowed - required;
+        return_payment(
+            escrow,
+            escrow_authority,
+            signer,
+            user,
+            user_account,
+            temp_wsol_account,
+            token_program,
+            refund,
+        )?;
+        Ok((0, refund))
+    } else {
+        Ok((0, 0))
+    }
+}
@@ -0,0 +1,486 @@
+use anchor_lang::prelude::*;
+use crate::errors::*;
+use crate::state::CurveKind;
+
+// Prices across the platform are quote atoms per whole base token, i.e. per
+// 10^base_decimals base atoms. Markets, offerings and bonding curves record
+// the decimals of their token mint when they are created and price with
+// those. Prices are already counted in quote atoms, so the quote mint's
+// decimals never rescale them.
+//
+// Rounding always favors the platform: amounts a user pays in are rounded up,
+// amounts a user receives are rounded down and fees are rounded up. Fills
+// paid out of an escrow are rounded down so that the sum of partial fills can
+// never draw more than was locked up front.
+
+// Decimals of the token mints created for companies
+pub const COMPANY_TOKEN_DECIMALS: u8 = 6;
+
+// Legacy orders were priced in quote atoms per base atom, which is a price
+// per whole token of a token without decimals
+pub const LEGACY_PRICE_DECIMALS: u8 = 0;
+
+const BASIS_POINTS: u128 = 10_000;
+const WAD: u128 = 1_000_000_000_000_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+/// Number of base atoms in one whole base token.
+pub fn base_scale(base_decimals: u8) -> Result<u128> {
+    10u128
+        .checked_pow(base_decimals as u32)
+        .ok_or(error!(DefiTradingError::ArithmeticOverflow))
+}
+
+/// Quote atoms for `base_amount` base atoms at `price`.
+pub fn quote_for_base(
+    base_amount: u64,
+    price: u64,
+    base_decimals: u8,
+    rounding: Rounding,
+) -> Result<u64> {
+    let numerator = (base_amount as u128)
+        .checked_mul(price as u128)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    div_rounded(numerator, base_scale(base_decimals)?, rounding)
+}
+
+/// Quote a resting limit buy keeps escrowed for `remaining_amount` at `price`.
+pub fn buy_escrow_required(remaining_amount: u64, price: u64, base_decimals: u8) -> Result<u64> {
+    quote_for_base(remaining_amount, price, base_decimals, Rounding::Up)
+}
+
+/// Escrow a limit buy at `price` releases when `fill_amount` of its
+/// `remaining_amount` fills. Paying exactly this out of the escrow keeps it
+/// equal to `buy_escrow_required` for whatever is left.
+pub fn buy_escrow_release(
+    remaining_amount: u64,
+    fill_amount: u64,
+    price: u64,
+    base_decimals: u8,
+) -> Result<u64> {
+    let remaining_after = remaining_amount
+        .checked_sub(fill_amount)
+        .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+    buy_escrow_required(remaining_amount, price, base_decimals)?
+        .checked_sub(buy_escrow_required(remaining_after, price, base_decimals)?)
+        .ok_or(error!(DefiTradingError::ArithmeticUnderflow))
+}
+
+/// Price improvement to hand back once `paid` has left a limit buy escrow
+/// holding `escrowed`, so that what stays behind is exactly what the
+/// `remaining_after` still needs at `price`. Errors if the escrow is short.
+pub fn buy_escrow_refund(
+    escrowed: u64,
+    paid: u64,
+    remaining_after: u64,
+    price: u64,
+    base_decimals: u8,
+) -> Result<u64> {
+    let required = buy_escrow_required(remaining_after, price, base_decimals)?;
+    escrowed
+        .checked_sub(paid)
+        .and_then(|left| left.checked_sub(required))
+        .ok_or(error!(DefiTradingError::EscrowInvariantViolated))
+}
+
+/// Quote a buying taker pays for a fill of `fill_amount` at a maker's
+/// `price`. Rounded up, so the maker is never paid less than the fill is worth.
+pub fn taker_buy_cost(fill_amount: u64, price: u64, base_decimals: u8) -> Result<u64> {
+    quote_for_base(fill_amount, price, base_decimals, Rounding::Up)
+}
+
+/// Quote a buy of `amount` at up to `price` must have set aside to take at
+/// most `max_fills` fills and still escrow whatever rests. Each fill is paid
+/// rounded up, which can cost one atom more than its share of the order.
+pub fn taker_buy_budget(amount: u64, price: u64, base_decimals: u8, max_fills: u8) -> Result<u64> {
+    buy_escrow_required(amount, price, base_decimals)?
+        .checked_add(max_fills as u64)
+        .ok_or(error!(DefiTradingError::ArithmeticOverflow))
+}
+
+/// Base atoms purchasable with `quote_amount` at `price`, rounded down.
+pub fn base_for_quote(quote_amount: u64, price: u64, base_decimals: u8) -> Result<u64> {
+    let numerator = (quote_amount as u128)
+        .checked_mul(base_scale(base_decimals)?)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    div_rounded(numerator, price as u128, Rounding::Down)
+}
+
+/// Price implied by paying `quote_amount` for `base_amount`, rounded down.
+pub fn unit_price(quote_amount: u64, base_amount: u64, base_decimals: u8) -> Result<u64> {
+    let numerator = (quote_amount as u128)
+        .checked_mul(base_scale(base_decimals)?)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    div_rounded(numerator, base_amount as u128, Rounding::Down)
+}
+
+/// Platform fee on `value` at `fee_bps` basis points, rounded up.
+pub fn fee_amount(value: u64, fee_bps: u16) -> Result<u64> {
+    let numerator = (value as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    div_rounded(numerator, BASIS_POINTS, Rounding::Up)
+}
+
+/// The `part / whole` share of `amount`, e.g. one investor's slice of a pool.
+pub fn pro_rata_share(amount: u64, part: u64, whole: u64, rounding: Rounding) -> Result<u64> {
+    let numerator = (amount as u128)
+        .checked_mul(part as u128)
+        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+    div_rounded(numerator, whole as u128, rounding)
+}
+
+/// Quote atoms a bonding curve reserve holds once `supply` base atoms have
+/// been sold from it, rounded down. Curve trades settle against the
+/// difference of two reserve values, so the result of a trade never depends
+/// on how it is split up.
+///
+/// Linear curves price the next token at `base_price + curve_param * s`
+/// (s in whole tokens). Exponential curves multiply the price by
+/// `1 + curve_param / 10_000` for every whole token sold.
+pub fn curve_reserve(
+    kind: &CurveKind,
+    base_price: u64,
+    curve_param: u64,
+    supply: u64,
+    base_decimals: u8,
+) -> Result<u64> {
+    let scale = base_scale(base_decimals)?;
+    let supply = supply as u128;
+    let base_price = base_price as u128;
+    let curve_param = curve_param as u128;
+
+    let reserve = match kind {
+        CurveKind::Linear => {
+            // integral of the price over supply, kept in quote * scale units
+            let base_part = base_price
+                .checked_mul(supply)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            let slope_part = curve_param
+                .checked_mul(supply)
+                .and_then(|value| value.checked_mul(supply))
+                .ok_or(DefiTradingError::ArithmeticOverflow)?
+                / (2 * scale);
+            base_part
+                .checked_add(slope_part)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?
+                / scale
+        },
+        CurveKind::Exponential => {
+            // geometric series over whole tokens, plus the partial token
+            let whole_tokens = supply / scale;
+            let partial_atoms = supply % scale;
+            let growth = WAD + curve_param * WAD / BASIS_POINTS;
+            let factor = wad_pow(growth, whole_tokens)?;
+            let full_steps = if curve_param == 0 {
+                base_price
+                    .checked_mul(whole_tokens)
+                    .ok_or(DefiTradingError::ArithmeticOverflow)?
+            } else {
+                base_price
+                    .checked_mul(factor - WAD)
+                    .ok_or(DefiTradingError::ArithmeticOverflow)?
+                    / (growth - WAD)
+            };
+            let partial_step = base_price
+                .checked_mul(factor)
+                .and_then(|value| value.checked_mul(partial_atoms))
+                .ok_or(DefiTradingError::ArithmeticOverflow)?
+                / WAD
+                / scale;
+            full_steps
+                .checked_add(partial_step)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?
+        },
+    };
+    u64::try_from(reserve).map_err(|_| error!(DefiTradingError::ArithmeticOverflow))
+}
+
+/// Marginal bonding curve price once `supply` base atoms have been sold.
+pub fn curve_spot_price(
+    kind: &CurveKind,
+    base_price: u64,
+    curve_param: u64,
+    supply: u64,
+    base_decimals: u8,
+) -> Result<u64> {
+    let scale = base_scale(base_decimals)?;
+    let price = match kind {
+        CurveKind::Linear => (curve_param as u128)
+            .checked_mul(supply as u128)
+            .ok_or(DefiTradingError::ArithmeticOverflow)?
+            / scale
+            + base_price as u128,
+        CurveKind::Exponential => {
+            let growth = WAD + (curve_param as u128) * WAD / BASIS_POINTS;
+            let factor = wad_pow(growth, supply as u128 / scale)?;
+            (base_price as u128)
+                .checked_mul(factor)
+                .ok_or(DefiTradingError::ArithmeticOverflow)?
+                / WAD
+        },
+    };
+    u64::try_from(price).map_err(|_| error!(DefiTradingError::ArithmeticOverflow))
+}
+
+// Multiplies whole and fractional parts separately, so only a result past
+// u128 overflows rather than the intermediate a * b
+fn wad_mul(a: u128, b: u128) -> Result<u128> {
+    let (a_whole, a_fraction) = (a / WAD, a % WAD);
+    let (b_whole, b_fraction) = (b / WAD, b % WAD);
+    a_whole
+        .checked_mul(b_whole)
+        .and_then(|value| value.checked_mul(WAD))
+        .and_then(|value| value.checked_add(a_whole.checked_mul(b_fraction)?))
+        .and_then(|value| value.checked_add(a_fraction.checked_mul(b_whole)?))
+        .and_then(|value| value.checked_add(a_fraction * b_fraction / WAD))
+        .ok_or(error!(DefiTradingError::ArithmeticOverflow))
+}
+
+fn wad_pow(base: u128, exponent: u128) -> Result<u128> {
+    let mut result = WAD;
+    let mut power = base;
+    let mut remaining = exponent;
+    while remaining > 0 {
+        if remaining & 1 == 1 {
+            result = wad_mul(result, power)?;
+        }
+        remaining >>= 1;
+        if remaining > 0 {
+            power = wad_mul(power, power)?;
+        }
+    }
+    Ok(result)
+}
+
+fn div_rounded(numerator: u128, denominator: u128, rounding: Rounding) -> Result<u64> {
+    require!(denominator > 0, DefiTradingError::DivisionByZero);
+    let mut quotient = numerator / denominator;
+    if rounding == Rounding::Up && !numerator.is_multiple_of(denominator) {
+        quotient += 1;
+    }
+    u64::try_from(quotient).map_err(|_| error!(DefiTradingError::ArithmeticOverflow))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DECIMALS: u8 = COMPANY_TOKEN_DECIMALS;
+    const ONE_TOKEN: u64 = 1_000_000;
+
+    fn overflow() -> Error {
+        error!(DefiTradingError::ArithmeticOverflow)
+    }
+
+    #[test]
+    fn quote_for_base_rounds_in_requested_direction() {
+        // half a token at 3 atoms per token is 1.5 atoms
+        assert_eq!(quote_for_base(ONE_TOKEN / 2, 3, DECIMALS, Rounding::Down).unwrap(), 1);
+        assert_eq!(quote_for_base(ONE_TOKEN / 2, 3, DECIMALS, Rounding::Up).unwrap(), 2);
+        // exact results are never rounded up
+        assert_eq!(quote_for_base(2 * ONE_TOKEN, 3, DECIMALS, Rounding::Up).unwrap(), 6);
+        assert_eq!(quote_for_base(0, 3, DECIMALS, Rounding::Up).unwrap(), 0);
+    }
+
+    #[test]
+    fn base_for_quote_and_unit_price_round_down() {
+        assert_eq!(base_for_quote(10, 3, DECIMALS).unwrap(), 3_333_333);
+        assert_eq!(unit_price(10, 3 * ONE_TOKEN, DECIMALS).unwrap(), 3);
+        // whatever is bought never costs more than was offered
+        let tokens = base_for_quote(1_000_003, 7_000_001, DECIMALS).unwrap();
+        assert!(quote_for_base(tokens, 7_000_001, DECIMALS, Rounding::Up).unwrap() <= 1_000_003);
+    }
+
+    #[test]
+    fn legacy_prices_are_per_base_atom() {
+        assert_eq!(quote_for_base(5, 7, LEGACY_PRICE_DECIMALS, Rounding::Down).unwrap(), 35);
+        assert_eq!(base_for_quote(36, 7, LEGACY_PRICE_DECIMALS).unwrap(), 5);
+    }
+
+    #[test]
+    fn fees_round_up() {
+        assert_eq!(fee_amount(1, 1).unwrap(), 1);
+        assert_eq!(fee_amount(10_000, 30).unwrap(), 30);
+        assert_eq!(fee_amount(10_001, 30).unwrap(), 31);
+        assert_eq!(fee_amount(0, 30).unwrap(), 0);
+    }
+
+    #[test]
+    fn partial_buy_fills_never_release_more_than_escrowed() {
+        let (amount, price) = (1_000_001, 2_999_999);
+        let escrowed = buy_escrow_required(amount, price, DECIMALS).unwrap();
+        let mut remaining = amount;
+        let mut released = 0;
+        for fill in [1, 333_333, 7, 500_000, 166_660] {
+            released += buy_escrow_release(remaining, fill, price, DECIMALS).unwrap();
+            remaining -= fill;
+            assert_eq!(
+                escrowed - released,
+                buy_escrow_required(remaining, price, DECIMALS).unwrap()
+            );
+        }
+        assert_eq!(remaining, 0);
+        assert_eq!(released, escrowed);
+    }
+
+    #[test]
+    fn taker_buys_pay_rounded_up() {
+        // a third of a token at 2 atoms per token is 0.67 atoms
+        assert_eq!(taker_buy_cost(ONE_TOKEN / 3, 2, DECIMALS).unwrap(), 1);
+        assert_eq!(taker_buy_cost(ONE_TOKEN, 2, DECIMALS).unwrap(), 2);
+    }
+
+    #[test]
+    fn taker_buy_budget_covers_every_fill_and_the_rest() {
+        let price = 3;
+        let amount = ONE_TOKEN;
+        let fill = ONE_TOKEN / 6; // 0.5 atoms each
+        let max_fills = 5;
+        let budget = taker_buy_budget(amount, price, DECIMALS, max_fills).unwrap();
+
+        let mut paid = 0;
+        for _ in 0..max_fills {
+            paid += taker_buy_cost(fill, price, DECIMALS).unwrap();
+        }
+        let rest = amount - fill * max_fills as u64;
+        assert_eq!(paid, 5);
+        assert!(paid + buy_escrow_required(rest, price, DECIMALS).unwrap() <= budget);
+        // without the allowance the same fills would not fit
+        assert!(paid + buy_escrow_required(rest, price, DECIMALS).unwrap() > buy_escrow_required(amount, price, DECIMALS).unwrap());
+    }
+
+    #[test]
+    fn short_escrow_is_rejected() {
+        let required = buy_escrow_required(ONE_TOKEN, 10, DECIMALS).unwrap();
+        assert_eq!(buy_escrow_refund(required + 4, 3, 0, 10, DECIMALS).unwrap(), required + 1);
+        assert_eq!(
+            buy_escrow_refund(required, 1, ONE_TOKEN, 10, DECIMALS).unwrap_err(),
+            error!(DefiTradingError::EscrowInvariantViolated)
+        );
+    }
+
+    #[test]
+    fn pro_rata_shares_never_exceed_the_pool() {
+        let commitments = [1u64, 2, 3, 994];
+        let total: u64 = commitments.iter().sum();
+        let shares: u64 = commitments
+            .iter()
+            .map(|&part| pro_rata_share(101, part, total, Rounding::Down).unwrap())
+            .sum();
+        assert!(shares <= 101);
+        assert_eq!(pro_rata_share(101, 1, 1000, Rounding::Up).unwrap(), 1);
+    }
+
+    #[test]
+    fn results_past_u64_are_rejected() {
+        // the largest representable result still converts
+        assert_eq!(
+            quote_for_base(u64::MAX, ONE_TOKEN, DECIMALS, Rounding::Down).unwrap(),
+            u64::MAX
+        );
+        assert_eq!(
+            quote_for_base(u64::MAX, ONE_TOKEN + 1, DECIMALS, Rounding::Down).unwrap_err(),
+            overflow()
+        );
+        assert_eq!(quote_for_base(u64::MAX, u64::MAX, 0, Rounding::Up).unwrap_err(), overflow());
+        assert_eq!(base_for_quote(u64::MAX, 1, DECIMALS).unwrap_err(), overflow());
+        assert_eq!(fee_amount(u64::MAX, 10_000).unwrap(), u64::MAX);
+        assert!(base_scale(38).is_ok());
+        assert_eq!(base_scale(39).unwrap_err(), overflow());
+    }
+
+    #[test]
+    fn zero_prices_are_rejected() {
+        assert_eq!(
+            base_for_quote(1, 0, DECIMALS).unwrap_err(),
+            error!(DefiTradingError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn wad_pow_matches_repeated_multiplication() {
+        assert_eq!(wad_pow(3 * WAD, 0).unwrap(), WAD);
+        assert_eq!(wad_pow(2 * WAD, 10).unwrap(), 1024 * WAD);
+        assert_eq!(wad_pow(2 * WAD, 60).unwrap(), (1u128 << 60) * WAD);
+        let growth = WAD + WAD / 100;
+        let mut expected = WAD;
+        for exponent in 0..20 {
+            assert!(wad_pow(growth, exponent).unwrap().abs_diff(expected) <= exponent);
+            expected = wad_mul(expected, growth).unwrap();
+        }
+        assert_eq!(wad_pow(u128::MAX / 2, 2).unwrap_err(), overflow());
+    }
+
+    #[test]
+    fn linear_curve_reserve_is_the_price_integral() {
+        let linear = CurveKind::Linear;
+        // 3 tokens starting at 100 atoms, 10 atoms more per token sold
+        assert_eq!(curve_reserve(&linear, 100, 10, 3 * ONE_TOKEN, DECIMALS).unwrap(), 345);
+        assert_eq!(curve_reserve(&linear, 100, 0, 3 * ONE_TOKEN, DECIMALS).unwrap(), 300);
+        assert_eq!(curve_reserve(&linear, 100, 10, 0, DECIMALS).unwrap(), 0);
+        assert_eq!(curve_spot_price(&linear, 100, 10, 0, DECIMALS).unwrap(), 100);
+        assert_eq!(curve_spot_price(&linear, 100, 10, 3 * ONE_TOKEN, DECIMALS).unwrap(), 130);
+        assert_eq!(curve_spot_price(&linear, 100, 10, ONE_TOKEN / 2, DECIMALS).unwrap(), 105);
+    }
+
+    #[test]
+    fn exponential_curve_reserve_is_the_geometric_sum() {
+        let exponential = CurveKind::Exponential;
+        // price doubles with every whole token sold: 100 + 200 + 400
+        assert_eq!(curve_reserve(&exponential, 100, 10_000, 3 * ONE_TOKEN, DECIMALS).unwrap(), 700);
+        // half of the fourth token at 800
+        assert_eq!(
+            curve_reserve(&exponential, 100, 10_000, 3 * ONE_TOKEN + ONE_TOKEN / 2, DECIMALS).unwrap(),
+            1_100
+        );
+        assert_eq!(curve_reserve(&exponential, 100, 0, 3 * ONE_TOKEN, DECIMALS).unwrap(), 300);
+        assert_eq!(curve_spot_price(&exponential, 100, 10_000, 3 * ONE_TOKEN, DECIMALS).unwrap(), 800);
+        assert_eq!(curve_spot_price(&exponential, 100, 0, 3 * ONE_TOKEN, DECIMALS).unwrap(), 100);
+    }
+
+    #[test]
+    fn curve_trades_cost_between_the_spot_prices() {
+        for kind in [CurveKind::Linear, CurveKind::Exponential] {
+            let mut supply = 0;
+            let mut reserve = 0;
+            for amount in [ONE_TOKEN, 250_000, 1, 2 * ONE_TOKEN + 3] {
+                let new_reserve = curve_reserve(&kind, 1_000, 50, supply + amount, DECIMALS).unwrap();
+                let cost = new_reserve - reserve;
+                let low = quote_for_base(
+                    amount,
+                    curve_spot_price(&kind, 1_000, 50, supply, DECIMALS).unwrap(),
+                    DECIMALS,
+                    Rounding::Down,
+                ).unwrap();
+                let high = quote_for_base(
+                    amount,
+                    curve_spot_price(&kind, 1_000, 50, supply + amount, DECIMALS).unwrap(),
+                    DECIMALS,
+                    Rounding::Up,
+                ).unwrap();
+                assert!(low <= cost + 1 && cost <= high + 1);
+                supply += amount;
+                reserve = new_reserve;
+            }
+        }
+    }
+
+    #[test]
+    fn curves_past_u64_are_rejected() {
+        let exponential = CurveKind::Exponential;
+        assert_eq!(
+            curve_spot_price(&exponential, u64::MAX, 10_000, 200 * ONE_TOKEN, DECIMALS).unwrap_err(),
+            overflow()
+        );
+        assert_eq!(
+            curve_reserve(&CurveKind::Linear, u64::MAX, u64::MAX, u64::MAX, DECIMALS).unwrap_err(),
+            overflow()
+        );
+    }
+}
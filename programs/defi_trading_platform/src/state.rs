@@ -1,4 +1,7 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE;
+use crate::errors::*;
+use crate::pricing::{base_for_quote, buy_escrow_release, buy_escrow_required, quote_for_base, Rounding};
 
 #[account]
 pub struct Platform {
@@ -9,6 +12,10 @@ pub struct Platform {
     pub platform_fee: u16, // in basis points (e.g., 100 = 1%)
     pub is_paused: bool,
     pub bump: u8,
+    // Sequences come after bump so platforms created before them can be
+    // migrated in place
+    pub last_order_id: u64,  // legacy order ids
+    pub last_escrow_id: u64, // escrow ids
 }
 
 impl Platform {
@@ -19,7 +26,12 @@ impl Platform {
         8 + // total_trades
         2 + // platform_fee
         1 + // is_paused
-        1; // bump
+        1 + // bump
+        8 + // last_order_id
+        8; // last_escrow_id
+
+    // Size of platforms created before the order and escrow sequences
+    pub const LEGACY_LEN: usize = Platform::LEN - 8 - 8;
 }
 
 #[account]
@@ -62,7 +74,7 @@ pub struct TokenOffering {
     pub token_mint: Pubkey,
     pub total_supply: u64,
     pub remaining_supply: u64,
-    pub price_per_token: u64,
+    pub price_per_token: u64, // start price for Dutch auctions
     pub offering_start: i64,
     pub offering_end: i64,
     pub total_raised: u64,
@@ -70,9 +82,26 @@ pub struct TokenOffering {
     pub status: OfferingStatus,
     pub created_at: i64,
     pub bump: u8,
+    // Fields below come after bump so offerings created before them can be
+    // migrated in place
+    pub base_decimals: u8, // decimals of token_mint, prices are per 10^base_decimals atoms
+    pub kind: OfferingKind,
+    pub floor_price: u64, // Dutch auctions only
+    pub clearing_price: u64, // Dutch auctions only, lowest accepted bid price
+    pub payment_vault: Pubkey, // auctions and subscriptions, holds funds until claim
+    pub settled_proceeds: u64, // subscriptions only, fixed when the offering is settled
+    pub proceeds_withdrawn: u64,
+    pub min_investment: u64,
+    pub max_investment: u64, // per investor, 0 = no cap
+    pub lockup_duration: i64, // seconds after offering_end, 0 = tokens delivered unlocked
+    pub rounds: Vec<OfferingRound>, // optional presale rounds, fixed-price only
 }
 
 impl TokenOffering {
+    pub const MAX_ROUNDS: usize = 4;
+    pub const MAX_END_EXTENSION: i64 = 30 * 24 * 60 * 60; // 30 days per amendment
+    pub const MAX_LOCKUP_DURATION: i64 = 365 * 24 * 60 * 60;
+
     pub const LEN: usize = 8 + // discriminator
         8 + // id
         8 + // company_id
@@ -87,7 +116,107 @@ impl TokenOffering {
         8 + // participants_count
         1 + // status
         8 + // created_at
-        1; // bump
+        1 + // bump
+        1 + // base_decimals
+        1 + // kind
+        8 + // floor_price
+        8 + // clearing_price
+        32 + // payment_vault
+        8 + // settled_proceeds
+        8 + // proceeds_withdrawn
+        8 + // min_investment
+        8 + // max_investment
+        8 + // lockup_duration
+        4 + (OfferingRound::LEN * Self::MAX_ROUNDS); // rounds
+
+    // Size of offerings created before the fields that follow bump
+    pub const LEGACY_LEN: usize = TokenOffering::LEN - 1 - 1 - 8 * 2 - 32 - 8 * 5 -
+        (4 + OfferingRound::LEN * Self::MAX_ROUNDS);
+
+    /// Timestamp from which locked offering tokens can be released.
+    pub fn lockup_ends_at(&self) -> i64 {
+        self.offering_end.saturating_add(self.lockup_duration)
+    }
+
+    /// Index of the round whose window contains `now`, if any.
+    pub fn active_round(&self, now: i64) -> Option<usize> {
+        self.rounds
+            .iter()
+            .position(|round| now >= round.round_start && now <= round.round_end)
+    }
+
+    /// Tokens a subscription hands out across all commitments: everything
+    /// committed funds can buy, capped at the supply.
+    pub fn subscription_allocated(&self) -> Result<u64> {
+        let demand = base_for_quote(self.total_raised, self.price_per_token, self.base_decimals)?;
+        Ok(demand.min(self.total_supply))
+    }
+
+    /// Settles a subscription once its deadline has passed. The issuer's
+    /// proceeds are the allocated tokens at the offering price, so they are
+    /// known before any investor claims.
+    pub fn settle_subscription(&mut self) -> Result<()> {
+        if self.status == OfferingStatus::Completed {
+            return Ok(());
+        }
+        self.settled_proceeds = quote_for_base(
+            self.subscription_allocated()?,
+            self.price_per_token,
+            self.base_decimals,
+            Rounding::Down,
+        )?;
+        self.status = OfferingStatus::Completed;
+        Ok(())
+    }
+
+    /// Price per token at `now`. Dutch auctions decay linearly from
+    /// `price_per_token` at `offering_start` to `floor_price` at `offering_end`.
+    pub fn current_price(&self, now: i64) -> u64 {
+        match self.kind {
+            OfferingKind::FixedPrice | OfferingKind::Subscription => self.price_per_token,
+            OfferingKind::DutchAuction => {
+                if now <= self.offering_start {
+                    return self.price_per_token;
+                }
+                if now >= self.offering_end {
+                    return self.floor_price;
+                }
+                let elapsed = (now - self.offering_start) as u128;
+                let duration = (self.offering_end - self.offering_start) as u128;
+                let price_range = (self.price_per_token - self.floor_price) as u128;
+                let price_drop = price_range * elapsed / duration;
+                self.price_per_token - price_drop as u64
+            }
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct OfferingRound {
+    pub price_per_token: u64,
+    pub round_start: i64,
+    pub round_end: i64,
+    pub max_per_wallet: u64, // in payment tokens, 0 = no cap
+    pub allowlist_root: [u8; 32], // merkle root of allowed wallets, zeroed = public
+    pub tokens_sold: u64,
+    pub amount_raised: u64,
+}
+
+impl OfferingRound {
+    pub const LEN: usize = 8 + // price_per_token
+        8 + // round_start
+        8 + // round_end
+        8 + // max_per_wallet
+        32 + // allowlist_root
+        8 + // tokens_sold
+        8; // amount_raised
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum OfferingKind {
+    FixedPrice,
+    DutchAuction,
+    Subscription,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
@@ -214,6 +343,9 @@ pub struct Holding {
     pub profit_loss: i64,
     pub last_updated: i64,
     pub bump: u8,
+    // Comes after bump so holdings created before lockups can be migrated
+    // in place
+    pub locked_amount: u64, // offering tokens still under lockup
 }
 
 impl Holding {
@@ -227,7 +359,15 @@ impl Holding {
         8 + // current_value
         8 + // profit_loss
         8 + // last_updated
-        1; // bump
+        1 + // bump
+        8; // locked_amount
+
+    // Size of holdings created before locked_amount
+    pub const LEGACY_LEN: usize = Holding::LEN - 8;
+
+    pub fn free_amount(&self) -> u64 {
+        self.amount.saturating_sub(self.locked_amount)
+    }
 }
 
 #[account]
@@ -274,6 +414,13 @@ pub struct OfferingParticipation {
     pub tokens_received: u64,
     pub participated_at: i64,
     pub bump: u8,
+    // Fields below come after bump so participations created before them
+    // can be migrated in place
+    pub locked_amount: u64, // part of tokens_received still in the lock account
+    pub purchases_count: u64,
+    pub round_invested: [u64; TokenOffering::MAX_ROUNDS], // per round
+    pub settled: bool, // auctions and subscriptions: tokens claimed and refund paid
+    pub last_participated_at: i64,
 }
 
 impl OfferingParticipation {
@@ -284,30 +431,54 @@ impl OfferingParticipation {
         8 + // amount_invested
         8 + // tokens_received
         8 + // participated_at
-        1; // bump
+        1 + // bump
+        8 + // locked_amount
+        8 + // purchases_count
+        8 * TokenOffering::MAX_ROUNDS + // round_invested
+        1 + // settled
+        8; // last_participated_at
+
+    // Size of participations created before the fields that follow bump
+    pub const LEGACY_LEN: usize = OfferingParticipation::LEN - 8 - 8 - 8 * TokenOffering::MAX_ROUNDS - 1 - 8;
 }
 
 // New structs for enhanced orderbook functionality
 
-#[account]
-pub struct Orderbook {
+// Seed orders placed in the book when a bonding curve graduates
+pub const GRADUATION_ASK_ORDER_ID: u64 = 1;
+pub const GRADUATION_BID_ORDER_ID: u64 = 2;
+
+// Keeps the discriminator of its former name, Orderbook, so markets opened
+// before the rename still load
+#[account(discriminator = [43, 34, 25, 113, 195, 69, 72, 7])]
+pub struct Market {
     pub company_id: u64,
-    pub token_mint: Pubkey,
-    pub total_buy_orders: u64,
-    pub total_sell_orders: u64,
-    pub best_bid: u64,  // highest buy price
-    pub best_ask: u64,  // lowest sell price
+    pub base_mint: Pubkey,
+    pub total_buy_orders: u64,  // unexpired bids resting in the book
+    pub total_sell_orders: u64, // unexpired asks resting in the book
+    pub best_bid: u64,  // highest unexpired buy price, 0 when there is none
+    pub best_ask: u64,  // lowest unexpired sell price, 0 when there is none
     pub last_trade_price: u64,
     pub total_volume: u64,
     pub created_at: i64,
     pub last_updated: i64,
     pub bump: u8,
+    // Fields below come after bump so markets opened before them can be
+    // migrated in place
+    pub base_decimals: u8, // decimals of base_mint, prices are per 10^base_decimals atoms
+    pub last_order_id: u64, // book order ids, never reused when orders are cancelled
+    pub price_feed: Pubkey, // default when trigger orders follow last_trade_price
+    pub last_trigger_order_id: u64,
+    pub market_config: MarketConfig,
+    pub circuit_breaker: CircuitBreaker,
+    pub quote_mint: Pubkey, // mint of prices, payments and fees
+    pub liquidity_locked_until: i64, // 0 when the market has no locked seed orders
 }
 
-impl Orderbook {
+impl Market {
     pub const LEN: usize = 8 + // discriminator
         8 + // company_id
-        32 + // token_mint
+        32 + // base_mint
         8 + // total_buy_orders
         8 + // total_sell_orders
         8 + // best_bid
@@ -316,7 +487,450 @@ impl Orderbook {
         8 + // total_volume
         8 + // created_at
         8 + // last_updated
-        1; // bump
+        1 + // bump
+        1 + // base_decimals
+        8 + // last_order_id
+        32 + // price_feed
+        8 + // last_trigger_order_id
+        MarketConfig::LEN + // market_config
+        CircuitBreaker::LEN + // circuit_breaker
+        32 + // quote_mint
+        8; // liquidity_locked_until
+
+    // Size of markets opened before the fields that follow bump
+    pub const LEGACY_LEN: usize = Market::LEN - 1 - 8 - 32 - 8 - MarketConfig::LEN - CircuitBreaker::LEN - 32 - 8;
+    
+    // Mint an order of this side escrows: base for asks, quote for bids
+    pub fn escrow_mint(&self, side: &OrderType) -> Pubkey {
+        match side {
+            OrderType::Sell => self.base_mint,
+            OrderType::Buy => self.quote_mint,
+        }
+    }
+    
+    // Graduation seed orders only leave the book by filling until the
+    // liquidity lock ends
+    pub fn is_locked_order(&self, order_id: u64, now: i64) -> bool {
+        now < self.liquidity_locked_until
+            && (order_id == GRADUATION_ASK_ORDER_ID || order_id == GRADUATION_BID_ORDER_ID)
+    }
+}
+
+// Order size and price granularity of a market. Tick and lot sizes of 1
+// allow any price and amount.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct MarketConfig {
+    pub tick_size: u64,     // prices must be a multiple of this
+    pub lot_size: u64,      // base amounts must be a multiple of this
+    pub min_base_size: u64, // smallest order in base atoms
+    pub min_notional: u64,  // smallest order value in quote atoms
+}
+
+impl MarketConfig {
+    pub const LEN: usize = 8 + // tick_size
+        8 + // lot_size
+        8 + // min_base_size
+        8; // min_notional
+
+    // Smallest order value of markets opened without a configuration
+    pub const DEFAULT_MIN_NOTIONAL: u64 = 1_000_000;
+
+    pub const DEFAULT: MarketConfig = MarketConfig {
+        tick_size: 1,
+        lot_size: 1,
+        min_base_size: 0,
+        min_notional: Self::DEFAULT_MIN_NOTIONAL,
+    };
+
+    // The book holds a bounded number of orders per side, so every market
+    // needs a minimum order value to keep it from being filled with dust
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.tick_size > 0 && self.lot_size > 0,
+            DefiTradingError::InvalidMarketConfig
+        );
+        require!(self.min_notional > 0, DefiTradingError::InvalidMarketConfig);
+        require!(
+            self.min_base_size.is_multiple_of(self.lot_size),
+            DefiTradingError::InvalidMarketConfig
+        );
+        Ok(())
+    }
+
+    pub fn check_price(&self, price: u64) -> Result<()> {
+        require!(price.is_multiple_of(self.tick_size), DefiTradingError::PriceNotOnTick);
+        Ok(())
+    }
+
+    pub fn check_lot(&self, amount: u64) -> Result<()> {
+        require!(amount.is_multiple_of(self.lot_size), DefiTradingError::AmountNotOnLot);
+        Ok(())
+    }
+
+    pub fn check_amount(&self, amount: u64) -> Result<()> {
+        self.check_lot(amount)?;
+        require!(amount >= self.min_base_size, DefiTradingError::OrderBelowMinimumSize);
+        Ok(())
+    }
+
+    pub fn check_notional(&self, quote_amount: u64) -> Result<()> {
+        require!(quote_amount >= self.min_notional, DefiTradingError::OrderBelowMinimumNotional);
+        Ok(())
+    }
+
+    // Full check of a limit order resting at `price`
+    pub fn check_limit_order(&self, amount: u64, price: u64, base_decimals: u8) -> Result<()> {
+        self.check_price(price)?;
+        self.check_amount(amount)?;
+        self.check_notional(quote_for_base(amount, price, base_decimals, Rounding::Down)?)
+    }
+}
+
+// Limits on how far a fill may move from the market's reference price. A
+// band of 0 bps turns price bands and the circuit breaker off.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    pub band_bps: u16,            // widest allowed move from the reference price
+    pub reference: BandReference,
+    pub max_breaches: u8,         // breaches within breach_window that halt the market
+    pub breach_window: i64,       // seconds
+    pub halt_duration: i64,       // seconds
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BandReference {
+    LastTrade,
+    RollingAverage,
+}
+
+// Price band configuration of a market and the state of its breaker
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub config: CircuitBreakerConfig,
+    pub rolling_average: u64,   // moving average of fill prices
+    pub breaches: u8,           // breaches since window_started_at
+    pub window_started_at: i64,
+    pub halted_until: i64,      // 0 when the market has never been halted
+}
+
+impl CircuitBreakerConfig {
+    pub const LEN: usize = 2 + // band_bps
+        1 + // reference
+        1 + // max_breaches
+        8 + // breach_window
+        8; // halt_duration
+
+    pub fn validate(&self) -> Result<()> {
+        require!(self.band_bps <= 10_000, DefiTradingError::InvalidCircuitBreakerConfig);
+        if self.band_bps > 0 {
+            require!(
+                self.max_breaches > 0 && self.breach_window > 0 && self.halt_duration > 0,
+                DefiTradingError::InvalidCircuitBreakerConfig
+            );
+        }
+        Ok(())
+    }
+}
+
+impl CircuitBreaker {
+    pub const LEN: usize = CircuitBreakerConfig::LEN + // config
+        8 + // rolling_average
+        1 + // breaches
+        8 + // window_started_at
+        8; // halted_until
+
+    // Number of fills the rolling average is taken over
+    pub const ROLLING_AVERAGE_FILLS: u128 = 20;
+
+    pub const DISABLED: CircuitBreaker = CircuitBreaker {
+        config: CircuitBreakerConfig {
+            band_bps: 0,
+            reference: BandReference::LastTrade,
+            max_breaches: 0,
+            breach_window: 0,
+            halt_duration: 0,
+        },
+        rolling_average: 0,
+        breaches: 0,
+        window_started_at: 0,
+        halted_until: 0,
+    };
+
+    pub fn check_not_halted(&self, now: i64) -> Result<()> {
+        require!(now >= self.halted_until, DefiTradingError::MarketHalted);
+        Ok(())
+    }
+
+    pub fn reference_price(&self, last_trade_price: u64) -> u64 {
+        match self.config.reference {
+            BandReference::LastTrade => last_trade_price,
+            BandReference::RollingAverage => self.rolling_average,
+        }
+    }
+
+    // Always true while the band is off or there is no reference price yet
+    pub fn in_band(&self, price: u64, reference_price: u64) -> bool {
+        if self.config.band_bps == 0 || reference_price == 0 {
+            return true;
+        }
+        let distance = price.abs_diff(reference_price) as u128;
+        distance * 10_000 <= reference_price as u128 * self.config.band_bps as u128
+    }
+
+    pub fn record_fill(&mut self, price: u64) {
+        self.rolling_average = if self.rolling_average == 0 {
+            price
+        } else {
+            ((self.rolling_average as u128 * (Self::ROLLING_AVERAGE_FILLS - 1) + price as u128)
+                / Self::ROLLING_AVERAGE_FILLS) as u64
+        };
+    }
+
+    // Counts a rejected fill and returns true when it halts the market
+    pub fn record_breach(&mut self, now: i64) -> Result<bool> {
+        if self.breaches == 0 || now - self.window_started_at >= self.config.breach_window {
+            self.breaches = 0;
+            self.window_started_at = now;
+        }
+        self.breaches = self.breaches.saturating_add(1);
+        if self.breaches < self.config.max_breaches {
+            return Ok(false);
+        }
+        self.halted_until = now
+            .checked_add(self.config.halt_duration)
+            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        self.breaches = 0;
+        Ok(true)
+    }
+}
+
+// Resting limit orders of one market, kept sorted in price-time priority:
+// bids by highest price first, asks by lowest price first, and orders at the
+// same price by the sequence number assigned when they entered the book.
+#[account(zero_copy)]
+pub struct OrderBookSlab {
+    pub orderbook: Pubkey,
+    pub next_sequence: u64,
+    pub bid_count: u64,
+    pub ask_count: u64,
+    pub bids: [BookNode; OrderBookSlab::MAX_ORDERS_PER_SIDE],
+    pub asks: [BookNode; OrderBookSlab::MAX_ORDERS_PER_SIDE],
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct BookNode {
+    pub order_id: u64,
+    pub owner: Pubkey,
+    pub open_orders: Pubkey, // default when the order has its own EnhancedOrder account
+    pub price: u64,
+    pub quantity: u64, // unfilled base amount
+    pub sequence: u64,
+    pub expires_at: i64, // 0 when the order does not expire
+}
+
+impl OrderBookSlab {
+    // Sized so the slab fits the 10,240 bytes an account created through a
+    // CPI can have
+    pub const MAX_ORDERS_PER_SIDE: usize = 48;
+    pub const LEN: usize = 8 + // discriminator
+        32 + // orderbook
+        8 + // next_sequence
+        8 + // bid_count
+        8 + // ask_count
+        BookNode::LEN * Self::MAX_ORDERS_PER_SIDE + // bids
+        BookNode::LEN * Self::MAX_ORDERS_PER_SIDE; // asks
+
+    /// Resting orders on one side, best first.
+    pub fn orders(&self, side: &OrderType) -> &[BookNode] {
+        match side {
+            OrderType::Buy => &self.bids[..self.bid_count as usize],
+            OrderType::Sell => &self.asks[..self.ask_count as usize],
+        }
+    }
+
+    /// Order with priority on one side.
+    pub fn head(&self, side: &OrderType) -> Option<&BookNode> {
+        self.orders(side).first()
+    }
+
+    /// Best price among the unexpired orders on one side, 0 when there are
+    /// none.
+    pub fn best_price(&self, side: &OrderType, now: i64) -> u64 {
+        self.orders(side)
+            .iter()
+            .find(|node| !node.is_expired(now))
+            .map_or(0, |node| node.price)
+    }
+
+    /// Number of unexpired orders on one side.
+    pub fn live_count(&self, side: &OrderType, now: i64) -> u64 {
+        self.orders(side).iter().filter(|node| !node.is_expired(now)).count() as u64
+    }
+
+    pub fn find(&self, side: &OrderType, order_id: u64) -> Option<usize> {
+        self.orders(side).iter().position(|node| node.order_id == order_id)
+    }
+
+    /// Adds an order behind every order at an equal or better price. A full
+    /// side makes room by evicting its worst-priced order, which is
+    /// returned, but only for an order priced strictly better than it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert(
+        &mut self,
+        side: &OrderType,
+        order_id: u64,
+        owner: Pubkey,
+        open_orders: Pubkey,
+        price: u64,
+        quantity: u64,
+        expires_at: i64,
+    ) -> Result<Option<BookNode>> {
+        require!(self.find(side, order_id).is_none(), DefiTradingError::InvalidOrderParams);
+
+        let evicted = match self.orders(side).last().copied() {
+            Some(worst) if self.orders(side).len() == Self::MAX_ORDERS_PER_SIDE => {
+                let improves = match side {
+                    OrderType::Buy => price > worst.price,
+                    OrderType::Sell => price < worst.price,
+                };
+                require!(improves, DefiTradingError::OrderBookFull);
+                Some(self.remove(side, worst.order_id)?)
+            },
+            _ => None,
+        };
+
+        let sequence = self.next_sequence;
+        let (nodes, count) = self.side_mut(side);
+        let len = *count as usize;
+        let index = nodes[..len]
+            .iter()
+            .position(|node| match side {
+                OrderType::Buy => node.price < price,
+                OrderType::Sell => node.price > price,
+            })
+            .unwrap_or(len);
+        nodes.copy_within(index..len, index + 1);
+        nodes[index] = BookNode {
+            order_id,
+            owner,
+            open_orders,
+            price,
+            quantity,
+            sequence,
+            expires_at,
+        };
+        *count += 1;
+
+        self.next_sequence = sequence
+            .checked_add(1)
+            .ok_or(DefiTradingError::ArithmeticOverflow)?;
+        Ok(evicted)
+    }
+
+    /// Takes `quantity` off a resting order, removing it once fully filled.
+    pub fn reduce(&mut self, side: &OrderType, order_id: u64, quantity: u64) -> Result<()> {
+        let index = self.find(side, order_id).ok_or(DefiTradingError::OrderNotInBook)?;
+        let (nodes, _) = self.side_mut(side);
+        nodes[index].quantity = nodes[index].quantity
+            .checked_sub(quantity)
+            .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+        if nodes[index].quantity == 0 {
+            self.remove(side, order_id)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, side: &OrderType, order_id: u64) -> Result<BookNode> {
+        let index = self.find(side, order_id).ok_or(DefiTradingError::OrderNotInBook)?;
+        let (nodes, count) = self.side_mut(side);
+        let len = *count as usize;
+        let node = nodes[index];
+        nodes.copy_within(index + 1..len, index);
+        nodes[len - 1] = BookNode::default();
+        *count -= 1;
+        Ok(node)
+    }
+
+    /// Drops expired orders from the front of one side so the best price is
+    /// always fillable. Their escrows are refunded by cancel_expired_order,
+    /// or released by cancel_open_order for open orders accounts.
+    pub fn remove_expired_heads(&mut self, side: &OrderType, now: i64) {
+        while let Some(head) = self.head(side).copied() {
+            if !head.is_expired(now) {
+                break;
+            }
+            let (nodes, count) = self.side_mut(side);
+            let len = *count as usize;
+            nodes.copy_within(1..len, 0);
+            nodes[len - 1] = BookNode::default();
+            *count -= 1;
+        }
+    }
+
+    /// Unexpired orders on one side grouped by price, best first, up to
+    /// `max_levels` levels.
+    pub fn levels(&self, side: &OrderType, max_levels: usize, now: i64) -> Result<Vec<PriceLevelData>> {
+        let level_side = match side {
+            OrderType::Buy => OrderSide::Buy,
+            OrderType::Sell => OrderSide::Sell,
+        };
+        let mut levels: Vec<PriceLevelData> = Vec::new();
+        for node in self.orders(side).iter().filter(|node| !node.is_expired(now)) {
+            match levels.last_mut() {
+                Some(level) if level.price == node.price => {
+                    level.amount = level.amount
+                        .checked_add(node.quantity)
+                        .ok_or(DefiTradingError::ArithmeticOverflow)?;
+                    level.orders_count += 1;
+                },
+                _ => {
+                    if levels.len() == max_levels {
+                        break;
+                    }
+                    levels.push(PriceLevelData {
+                        price: node.price,
+                        amount: node.quantity,
+                        orders_count: 1,
+                        side: level_side.clone(),
+                    });
+                },
+            }
+        }
+        Ok(levels)
+    }
+
+    /// Unfilled base amount of the unexpired orders on one side.
+    pub fn total_quantity(&self, side: &OrderType, now: i64) -> Result<u64> {
+        self.orders(side)
+            .iter()
+            .filter(|node| !node.is_expired(now))
+            .try_fold(0u64, |total, node| total.checked_add(node.quantity))
+            .ok_or(error!(DefiTradingError::ArithmeticOverflow))
+    }
+
+    fn side_mut(&mut self, side: &OrderType) -> (&mut [BookNode; Self::MAX_ORDERS_PER_SIDE], &mut u64) {
+        match side {
+            OrderType::Buy => (&mut self.bids, &mut self.bid_count),
+            OrderType::Sell => (&mut self.asks, &mut self.ask_count),
+        }
+    }
+}
+
+const _: () = assert!(OrderBookSlab::LEN <= MAX_PERMITTED_DATA_INCREASE);
+
+impl BookNode {
+    pub const LEN: usize = 8 + // order_id
+        32 + // owner
+        32 + // open_orders
+        8 + // price
+        8 + // quantity
+        8 + // sequence
+        8; // expires_at
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && now >= self.expires_at
+    }
 }
 
 #[account]
@@ -363,6 +977,10 @@ pub struct EnhancedOrder {
     pub amount: u64,
     pub remaining_amount: u64,
     pub price: u64,  // For limit orders, 0 for market orders
+    pub time_in_force: TimeInForce,
+    pub expires_at: Option<i64>, // good-till-time orders only
+    pub self_trade_prevention: SelfTradePrevention,
+    pub trading_account: Pubkey, // default when the order is not placed for a trading account
     pub status: OrderStatus,
     pub created_at: i64,
     pub filled_at: Option<i64>,
@@ -380,10 +998,352 @@ impl EnhancedOrder {
         8 + // amount
         8 + // remaining_amount
         8 + // price
+        1 + // time_in_force
+        1 + 8 + // expires_at (Option<i64>)
+        1 + // self_trade_prevention
+        32 + // trading_account
         1 + // status
         8 + // created_at
         1 + 8 + // filled_at (Option<i64>)
         1; // bump
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    // Orders sharing this key never trade with each other
+    pub fn self_trade_key(&self) -> Pubkey {
+        if self.trading_account == Pubkey::default() {
+            self.user
+        } else {
+            self.trading_account
+        }
+    }
+}
+
+// Layout of orders created before time in force, self-trade prevention and
+// trading accounts, at their address without the market. Shares the
+// EnhancedOrder discriminator, so it is only read by migrate_enhanced_order.
+#[account(discriminator = EnhancedOrder::DISCRIMINATOR)]
+pub struct LegacyEnhancedOrder {
+    pub id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub token_mint: Pubkey,
+    pub order_type: OrderType,
+    pub market_order_type: MarketOrderType,
+    pub amount: u64,
+    pub remaining_amount: u64,
+    pub price: u64,
+    pub status: OrderStatus,
+    pub created_at: i64,
+    pub filled_at: Option<i64>,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum TimeInForce {
+    GoodTillCancelled,
+    ImmediateOrCancel,
+    FillOrKill,
+    PostOnly, // rejected if it would take liquidity
+    GoodTillTime,
+}
+
+// Stop-loss or take-profit order held back until the market's reference
+// price crosses the trigger. Funds are escrowed when it is created and the
+// keeper bounty is held as extra lamports on this account.
+#[account]
+pub struct TriggerOrder {
+    pub id: u64,
+    pub user: Pubkey,
+    pub company_id: u64,
+    pub order_type: OrderType,
+    pub market_order_type: MarketOrderType, // order placed once triggered
+    pub trigger_price: u64,
+    pub trigger_direction: TriggerDirection,
+    pub amount: u64,
+    pub price: u64, // limit price, or worst acceptable price for market orders
+    pub escrowed_amount: u64,
+    pub keeper_bounty: u64,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+impl TriggerOrder {
+    pub const LEN: usize = 8 + // discriminator
+        8 + // id
+        32 + // user
+        8 + // company_id
+        1 + // order_type
+        1 + // market_order_type
+        8 + // trigger_price
+        1 + // trigger_direction
+        8 + // amount
+        8 + // price
+        8 + // escrowed_amount
+        8 + // keeper_bounty
+        8 + // created_at
+        1; // bump
+
+    pub fn is_triggered(&self, reference_price: u64) -> bool {
+        match self.trigger_direction {
+            TriggerDirection::AtOrAbove => reference_price >= self.trigger_price,
+            TriggerDirection::AtOrBelow => reference_price <= self.trigger_price,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum TriggerDirection {
+    AtOrAbove, // e.g. take-profit on a sell, stop on a buy
+    AtOrBelow, // e.g. stop-loss on a sell
+}
+
+// Reference price pushed by a platform-appointed feeder. When configured on
+// an orderbook it replaces last_trade_price for trigger orders.
+#[account]
+pub struct PriceFeed {
+    pub company_id: u64,
+    pub feeder: Pubkey,
+    pub price: u64,
+    pub max_staleness: i64, // seconds a price stays usable
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl PriceFeed {
+    pub const LEN: usize = 8 + // discriminator
+        8 + // company_id
+        32 + // feeder
+        8 + // price
+        8 + // max_staleness
+        8 + // updated_at
+        1; // bump
+}
+
+// What happens when an incoming order would trade with a resting order of
+// the same owner or trading account. The incoming order's setting applies.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    CancelMaker,
+    CancelTaker,
+    CancelBoth,
+    DecrementAndCancel, // both shrink by the overlap, the smaller one is cancelled
+}
+
+// Wallets trading for the same owner, treated as one participant by
+// self-trade prevention
+#[account]
+pub struct TradingAccount {
+    pub owner: Pubkey,
+    pub wallets: Vec<Pubkey>,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+impl TradingAccount {
+    pub const MAX_LINKED_WALLETS: usize = 10;
+
+    pub const LEN: usize = 8 + // discriminator
+        32 + // owner
+        4 + (32 * Self::MAX_LINKED_WALLETS) + // wallets
+        8 + // created_at
+        1; // bump
+
+    pub fn includes(&self, wallet: &Pubkey) -> bool {
+        self.owner == *wallet || self.wallets.contains(wallet)
+    }
+}
+
+// A user's balances and resting orders in one market. Funds are deposited
+// into the market vaults once; orders lock part of the free balance, fills
+// credit the free balance and settle_funds pays it out.
+#[account]
+pub struct OpenOrders {
+    pub owner: Pubkey,
+    pub company_id: u64,
+    pub quote_mint: Pubkey,
+    pub base_free: u64,
+    pub base_locked: u64,  // backing resting sells
+    pub quote_free: u64,
+    pub quote_locked: u64, // backing resting buys
+    pub orders: [OpenOrderSlot; OpenOrders::MAX_ORDERS],
+    pub created_at: i64,
+    pub bump: u8,
+    pub trading_account: Pubkey, // default when the owner trades for itself
+}
+
+impl OpenOrders {
+    pub const MAX_ORDERS: usize = 16;
+
+    pub const LEN: usize = 8 + // discriminator
+        32 + // owner
+        8 + // company_id
+        32 + // quote_mint
+        8 + // base_free
+        8 + // base_locked
+        8 + // quote_free
+        8 + // quote_locked
+        OpenOrderSlot::LEN * Self::MAX_ORDERS + // orders
+        8 + // created_at
+        1 + // bump
+        32; // trading_account
+
+    // Orders sharing this key never trade with each other, as with
+    // EnhancedOrder::self_trade_key
+    pub fn self_trade_key(&self) -> Pubkey {
+        if self.trading_account == Pubkey::default() {
+            self.owner
+        } else {
+            self.trading_account
+        }
+    }
+
+    pub fn find_order(&self, order_id: u64) -> Option<usize> {
+        self.orders.iter().position(|slot| slot.order_id == order_id)
+    }
+
+    pub fn add_order(&mut self, slot: OpenOrderSlot) -> Result<()> {
+        let index = self.find_order(0).ok_or(DefiTradingError::OpenOrdersFull)?;
+        self.orders[index] = slot;
+        Ok(())
+    }
+
+    // Moves funds backing `amount` at `price` from free to locked
+    pub fn lock(&mut self, order_type: &OrderType, amount: u64, price: u64, base_decimals: u8) -> Result<()> {
+        match order_type {
+            OrderType::Sell => {
+                self.base_free = self.base_free
+                    .checked_sub(amount)
+                    .ok_or(DefiTradingError::InsufficientTokens)?;
+                self.base_locked = self.base_locked
+                    .checked_add(amount)
+                    .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            },
+            OrderType::Buy => {
+                let quote = buy_escrow_required(amount, price, base_decimals)?;
+                self.quote_free = self.quote_free
+                    .checked_sub(quote)
+                    .ok_or(DefiTradingError::InsufficientFunds)?;
+                self.quote_locked = self.quote_locked
+                    .checked_add(quote)
+                    .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the funds locked for `amount` of the order at `index` to the
+    // free balance and shrinks the order
+    pub fn unlock(&mut self, index: usize, amount: u64, base_decimals: u8) -> Result<()> {
+        let slot = &self.orders[index];
+        let released = match slot.order_type {
+            OrderType::Sell => amount,
+            OrderType::Buy => buy_escrow_release(slot.remaining_amount, amount, slot.price, base_decimals)?,
+        };
+        match slot.order_type {
+            OrderType::Sell => {
+                self.base_locked = self.base_locked
+                    .checked_sub(released)
+                    .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+                self.base_free = self.base_free
+                    .checked_add(released)
+                    .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            },
+            OrderType::Buy => {
+                self.quote_locked = self.quote_locked
+                    .checked_sub(released)
+                    .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+                self.quote_free = self.quote_free
+                    .checked_add(released)
+                    .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            }
+        }
+        self.reduce_order(index, amount)
+    }
+
+    // Settles a maker fill of `amount`: the locked side is spent and the
+    // proceeds of the other side are credited as free
+    pub fn fill(&mut self, index: usize, amount: u64, proceeds: u64, base_decimals: u8) -> Result<u64> {
+        let slot = &self.orders[index];
+        let spent = match slot.order_type {
+            OrderType::Sell => amount,
+            OrderType::Buy => buy_escrow_release(slot.remaining_amount, amount, slot.price, base_decimals)?,
+        };
+        match slot.order_type {
+            OrderType::Sell => {
+                self.base_locked = self.base_locked
+                    .checked_sub(spent)
+                    .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+                self.quote_free = self.quote_free
+                    .checked_add(proceeds)
+                    .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            },
+            OrderType::Buy => {
+                self.quote_locked = self.quote_locked
+                    .checked_sub(spent)
+                    .ok_or(DefiTradingError::EscrowInvariantViolated)?;
+                self.base_free = self.base_free
+                    .checked_add(proceeds)
+                    .ok_or(DefiTradingError::ArithmeticOverflow)?;
+            }
+        }
+        self.reduce_order(index, amount)?;
+        Ok(spent)
+    }
+
+    fn reduce_order(&mut self, index: usize, amount: u64) -> Result<()> {
+        let slot = &mut self.orders[index];
+        slot.remaining_amount = slot.remaining_amount
+            .checked_sub(amount)
+            .ok_or(DefiTradingError::ArithmeticUnderflow)?;
+        if slot.remaining_amount == 0 {
+            *slot = OpenOrderSlot::default();
+        }
+        Ok(())
+    }
+}
+
+// One resting order of an open orders account; order_id is 0 when free
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct OpenOrderSlot {
+    pub order_id: u64,
+    pub order_type: OrderType,
+    pub price: u64,
+    pub remaining_amount: u64,
+    pub expires_at: i64, // 0 when the order does not expire
+}
+
+impl OpenOrderSlot {
+    pub const LEN: usize = 8 + // order_id
+        1 + // order_type
+        8 + // price
+        8 + // remaining_amount
+        8; // expires_at
+}
+
+impl Default for OpenOrderSlot {
+    fn default() -> Self {
+        OpenOrderSlot {
+            order_id: 0,
+            order_type: OrderType::Buy,
+            price: 0,
+            remaining_amount: 0,
+            expires_at: 0,
+        }
+    }
+}
+
+// Fill summary returned by create_market_order
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MarketOrderFill {
+    pub order_id: u64,
+    pub filled_amount: u64,
+    pub quote_amount: u64,
+    pub platform_fees: u64,
+    pub average_price: u64,
+    pub fills: u8,
 }
 
 #[account]
@@ -405,15 +1365,23 @@ pub struct PriceLevelData {
 }
 
 impl MarketDepth {
+    pub const MAX_LEVELS_PER_SIDE: usize = 20;
     pub const LEN: usize = 8 + // discriminator
         32 + // orderbook
-        4 + (40 * 20) + // price_levels (max 20 levels, 40 bytes each)
+        4 + PriceLevelData::LEN * 2 * MarketDepth::MAX_LEVELS_PER_SIDE + // price_levels, bids then asks
         8 + // total_buy_volume
         8 + // total_sell_volume
         8 + // last_updated
         1; // bump
 }
 
+impl PriceLevelData {
+    pub const LEN: usize = 8 + // price
+        8 + // amount
+        8 + // orders_count
+        1; // side
+}
+
 #[account]
 pub struct TokenDistribution {
     pub company_id: u64,
@@ -436,4 +1404,325 @@ impl TokenDistribution {
         8 + // amount_per_recipient
         8 + // distributed_at
         1; // bump
-}
\ No newline at end of file
+}
+
+#[account]
+pub struct BondingCurve {
+    pub company_id: u64,
+    pub authority: Pubkey,
+    pub token_mint: Pubkey,
+    pub base_decimals: u8, // decimals of token_mint, prices are per 10^base_decimals atoms
+    pub quote_mint: Pubkey,
+    pub curve_kind: CurveKind,
+    pub base_price: u64,
+    pub curve_param: u64, // linear: price step per whole token, exponential: growth in bps
+    pub max_supply: u64,
+    pub tokens_sold: u64,
+    pub reserve_balance: u64,
+    pub graduation_threshold: u64, // reserve balance that moves trading to the orderbook
+    pub seed_liquidity_bps: u16, // share of the reserve used to seed the orderbook bid
+    pub status: BondingCurveStatus,
+    pub created_at: i64,
+    pub graduated_at: Option<i64>,
+    pub bump: u8,
+}
+
+impl BondingCurve {
+    pub const LEN: usize = 8 + // discriminator
+        8 + // company_id
+        32 + // authority
+        32 + // token_mint
+        1 + // base_decimals
+        32 + // quote_mint
+        1 + // curve_kind
+        8 + // base_price
+        8 + // curve_param
+        8 + // max_supply
+        8 + // tokens_sold
+        8 + // reserve_balance
+        8 + // graduation_threshold
+        2 + // seed_liquidity_bps
+        1 + // status
+        8 + // created_at
+        1 + 8 + // graduated_at (Option<i64>)
+        1; // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum CurveKind {
+    Linear,
+    Exponential,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum BondingCurveStatus {
+    Active,
+    Graduated,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_book() -> OrderBookSlab {
+        bytemuck::Zeroable::zeroed()
+    }
+
+    fn rest(book: &mut OrderBookSlab, side: &OrderType, order_id: u64, price: u64) -> Result<Option<BookNode>> {
+        book.insert(side, order_id, Pubkey::default(), Pubkey::default(), price, 10, 0)
+    }
+
+    fn ids(book: &OrderBookSlab, side: &OrderType) -> Vec<u64> {
+        book.orders(side).iter().map(|node| node.order_id).collect()
+    }
+
+    #[test]
+    fn orders_rest_in_price_time_priority() {
+        let mut book = empty_book();
+        for (order_id, price) in [(1, 10), (2, 12), (3, 10), (4, 11)] {
+            rest(&mut book, &OrderType::Buy, order_id, price).unwrap();
+            rest(&mut book, &OrderType::Sell, order_id, price).unwrap();
+        }
+        assert_eq!(ids(&book, &OrderType::Buy), vec![2, 4, 1, 3]);
+        assert_eq!(ids(&book, &OrderType::Sell), vec![1, 3, 4, 2]);
+        assert_eq!(book.best_price(&OrderType::Buy, 0), 12);
+        assert_eq!(book.best_price(&OrderType::Sell, 0), 10);
+        // ids are unique per side
+        assert!(rest(&mut book, &OrderType::Buy, 1, 13).is_err());
+    }
+
+    #[test]
+    fn reduce_keeps_priority_until_filled() {
+        let mut book = empty_book();
+        for (order_id, price) in [(1, 10), (2, 10), (3, 9)] {
+            rest(&mut book, &OrderType::Buy, order_id, price).unwrap();
+        }
+        book.reduce(&OrderType::Buy, 1, 4).unwrap();
+        assert_eq!(ids(&book, &OrderType::Buy), vec![1, 2, 3]);
+        assert_eq!(book.head(&OrderType::Buy).unwrap().quantity, 6);
+
+        book.reduce(&OrderType::Buy, 1, 6).unwrap();
+        assert_eq!(ids(&book, &OrderType::Buy), vec![2, 3]);
+        assert_eq!(book.reduce(&OrderType::Buy, 2, 11).unwrap_err(), error!(DefiTradingError::ArithmeticUnderflow));
+        assert_eq!(book.reduce(&OrderType::Buy, 1, 1).unwrap_err(), error!(DefiTradingError::OrderNotInBook));
+    }
+
+    #[test]
+    fn remove_closes_the_gap() {
+        let mut book = empty_book();
+        for (order_id, price) in [(1, 10), (2, 11), (3, 12)] {
+            rest(&mut book, &OrderType::Sell, order_id, price).unwrap();
+        }
+        let removed = book.remove(&OrderType::Sell, 2).unwrap();
+        assert_eq!((removed.order_id, removed.price), (2, 11));
+        assert_eq!(ids(&book, &OrderType::Sell), vec![1, 3]);
+        assert_eq!(book.ask_count, 2);
+        assert!(book.remove(&OrderType::Sell, 2).is_err());
+    }
+
+    #[test]
+    fn full_side_evicts_its_worst_order_for_a_better_price() {
+        let mut book = empty_book();
+        let max = OrderBookSlab::MAX_ORDERS_PER_SIDE as u64;
+        for order_id in 1..=max {
+            assert!(rest(&mut book, &OrderType::Buy, order_id, 100 + order_id).unwrap().is_none());
+        }
+
+        // equal or worse prices cannot push anything out
+        assert_eq!(
+            rest(&mut book, &OrderType::Buy, max + 1, 101).err(),
+            Some(error!(DefiTradingError::OrderBookFull))
+        );
+        assert_eq!(book.bid_count, max);
+
+        let evicted = rest(&mut book, &OrderType::Buy, max + 1, 102).unwrap().unwrap();
+        assert_eq!((evicted.order_id, evicted.price), (1, 101));
+        assert_eq!(book.bid_count, max);
+        assert!(book.find(&OrderType::Buy, 1).is_none());
+        // behind the order already resting at 102
+        assert_eq!(book.orders(&OrderType::Buy).last().unwrap().order_id, max + 1);
+        assert_eq!(ids(&book, &OrderType::Buy)[max as usize - 2], 2);
+    }
+
+    #[test]
+    fn market_config_rejects_orders_off_tick_off_lot_or_too_small() {
+        let config = MarketConfig { tick_size: 5, lot_size: 100, min_base_size: 1_000, min_notional: 100 };
+        config.validate().unwrap();
+
+        config.check_price(15).unwrap();
+        assert_eq!(config.check_price(12).unwrap_err(), error!(DefiTradingError::PriceNotOnTick));
+        config.check_lot(1_100).unwrap();
+        assert_eq!(config.check_lot(1_150).unwrap_err(), error!(DefiTradingError::AmountNotOnLot));
+        assert_eq!(config.check_amount(900).unwrap_err(), error!(DefiTradingError::OrderBelowMinimumSize));
+
+        // 2,000 atoms at 5 per 100 atoms is worth exactly the minimum
+        config.check_limit_order(2_000, 5, 2).unwrap();
+        // 1,900 atoms are worth 95, rounded down
+        assert_eq!(
+            config.check_limit_order(1_900, 5, 2).unwrap_err(),
+            error!(DefiTradingError::OrderBelowMinimumNotional)
+        );
+        assert_eq!(config.check_limit_order(2_000, 7, 2).unwrap_err(), error!(DefiTradingError::PriceNotOnTick));
+        assert_eq!(config.check_limit_order(2_050, 5, 2).unwrap_err(), error!(DefiTradingError::AmountNotOnLot));
+    }
+
+    #[test]
+    fn market_config_needs_granularity_and_a_minimum_notional() {
+        let config = MarketConfig { tick_size: 5, lot_size: 100, min_base_size: 1_000, min_notional: 100 };
+        for invalid in [
+            MarketConfig { tick_size: 0, ..config },
+            MarketConfig { lot_size: 0, ..config },
+            MarketConfig { min_notional: 0, ..config },
+            MarketConfig { min_base_size: 1_050, ..config },
+        ] {
+            assert_eq!(invalid.validate().unwrap_err(), error!(DefiTradingError::InvalidMarketConfig));
+        }
+        MarketConfig::DEFAULT.validate().unwrap();
+    }
+
+    fn open_orders(base_free: u64, quote_free: u64) -> OpenOrders {
+        OpenOrders {
+            owner: Pubkey::new_unique(),
+            company_id: 1,
+            quote_mint: Pubkey::default(),
+            base_free,
+            base_locked: 0,
+            quote_free,
+            quote_locked: 0,
+            orders: Default::default(),
+            created_at: 0,
+            bump: 0,
+            trading_account: Pubkey::default(),
+        }
+    }
+
+    fn slot(order_id: u64, order_type: OrderType, price: u64, remaining_amount: u64) -> OpenOrderSlot {
+        OpenOrderSlot { order_id, order_type, price, remaining_amount, expires_at: 0 }
+    }
+
+    #[test]
+    fn open_orders_slots_fill_up_and_free_when_done() {
+        let mut open_orders = open_orders(1_000, 0);
+        for order_id in 1..=OpenOrders::MAX_ORDERS as u64 {
+            open_orders.lock(&OrderType::Sell, 10, 5, 0).unwrap();
+            open_orders.add_order(slot(order_id, OrderType::Sell, 5, 10)).unwrap();
+        }
+        assert_eq!(
+            open_orders.add_order(slot(99, OrderType::Sell, 5, 10)).unwrap_err(),
+            error!(DefiTradingError::OpenOrdersFull)
+        );
+
+        // a partial cancel keeps the slot, cancelling the rest frees it
+        let freed = open_orders.find_order(3).unwrap();
+        open_orders.unlock(freed, 4, 0).unwrap();
+        assert_eq!(open_orders.orders[freed].remaining_amount, 6);
+        open_orders.unlock(freed, 6, 0).unwrap();
+        assert_eq!(open_orders.find_order(3), None);
+        assert_eq!(open_orders.orders[freed].order_id, 0);
+        assert_eq!(open_orders.base_free, 1_000 - 150);
+        assert_eq!(open_orders.base_locked, 150);
+
+        // so does a full fill
+        let index = open_orders.find_order(7).unwrap();
+        assert_eq!(open_orders.fill(index, 10, 50, 0).unwrap(), 10);
+        assert_eq!(open_orders.find_order(7), None);
+        assert_eq!(open_orders.base_locked, 140);
+        assert_eq!(open_orders.quote_free, 50);
+
+        // freed slots are reused lowest first
+        open_orders.add_order(slot(99, OrderType::Sell, 5, 10)).unwrap();
+        assert_eq!(open_orders.find_order(99), Some(freed));
+        open_orders.add_order(slot(100, OrderType::Sell, 5, 10)).unwrap();
+        assert_eq!(open_orders.find_order(100), Some(index));
+    }
+
+    #[test]
+    fn open_orders_lock_only_free_balances() {
+        let mut open_orders = open_orders(10, 100);
+        assert_eq!(
+            open_orders.lock(&OrderType::Sell, 11, 5, 0).unwrap_err(),
+            error!(DefiTradingError::InsufficientTokens)
+        );
+        // 21 at 5 needs 105 of quote
+        assert_eq!(
+            open_orders.lock(&OrderType::Buy, 21, 5, 0).unwrap_err(),
+            error!(DefiTradingError::InsufficientFunds)
+        );
+
+        open_orders.lock(&OrderType::Buy, 20, 5, 0).unwrap();
+        open_orders.add_order(slot(1, OrderType::Buy, 5, 20)).unwrap();
+        assert_eq!((open_orders.quote_free, open_orders.quote_locked), (0, 100));
+
+        // filling 8 spends 40 of the locked quote and credits the base bought
+        assert_eq!(open_orders.fill(0, 8, 8, 0).unwrap(), 40);
+        assert_eq!((open_orders.quote_free, open_orders.quote_locked), (0, 60));
+        assert_eq!(open_orders.base_free, 18);
+
+        // cancelling the rest returns what is still locked
+        open_orders.unlock(0, 12, 0).unwrap();
+        assert_eq!((open_orders.quote_free, open_orders.quote_locked), (60, 0));
+        assert_eq!(open_orders.find_order(1), None);
+    }
+
+    #[test]
+    fn open_orders_trade_as_their_trading_account() {
+        let mut open_orders = open_orders(0, 0);
+        assert_eq!(open_orders.self_trade_key(), open_orders.owner);
+        open_orders.trading_account = Pubkey::new_unique();
+        assert_eq!(open_orders.self_trade_key(), open_orders.trading_account);
+    }
+
+    fn circuit_breaker(reference: BandReference) -> CircuitBreaker {
+        CircuitBreaker {
+            config: CircuitBreakerConfig {
+                band_bps: 500,
+                reference,
+                max_breaches: 3,
+                breach_window: 60,
+                halt_duration: 300,
+            },
+            ..CircuitBreaker::DISABLED
+        }
+    }
+
+    #[test]
+    fn price_band_includes_its_edges() {
+        let breaker = circuit_breaker(BandReference::LastTrade);
+        assert!(breaker.in_band(950, 1_000));
+        assert!(breaker.in_band(1_050, 1_000));
+        assert!(!breaker.in_band(949, 1_000));
+        assert!(!breaker.in_band(1_051, 1_000));
+        // no reference price yet, or no band
+        assert!(breaker.in_band(1, 0));
+        assert!(CircuitBreaker::DISABLED.in_band(1, 1_000));
+    }
+
+    #[test]
+    fn rolling_average_starts_at_the_first_fill() {
+        let mut breaker = circuit_breaker(BandReference::RollingAverage);
+        breaker.record_fill(1_000);
+        assert_eq!(breaker.rolling_average, 1_000);
+        breaker.record_fill(1_200);
+        assert_eq!(breaker.rolling_average, 1_010);
+        assert_eq!(breaker.reference_price(2_000), 1_010);
+        assert_eq!(circuit_breaker(BandReference::LastTrade).reference_price(2_000), 2_000);
+    }
+
+    #[test]
+    fn breaches_within_the_window_halt_the_market() {
+        let mut breaker = circuit_breaker(BandReference::LastTrade);
+        assert!(!breaker.record_breach(100).unwrap());
+        assert!(!breaker.record_breach(110).unwrap());
+        assert!(breaker.record_breach(120).unwrap());
+        assert_eq!(breaker.halted_until, 420);
+        assert_eq!(breaker.breaches, 0);
+        assert_eq!(breaker.check_not_halted(419).unwrap_err(), error!(DefiTradingError::MarketHalted));
+        breaker.check_not_halted(420).unwrap();
+
+        // a breach after the window has passed starts a new one
+        assert!(!breaker.record_breach(500).unwrap());
+        assert!(!breaker.record_breach(559).unwrap());
+        assert!(!breaker.record_breach(560).unwrap());
+        assert_eq!((breaker.breaches, breaker.window_started_at), (1, 560));
+    }
+}
+